@@ -0,0 +1,155 @@
+//! ADMIN packets: operator requests against the server's session registry.
+//!
+//! Requests and responses are JSON payloads. A request names a `command`; the
+//! server answers with an ADMIN packet whose `result` describes the outcome.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Packet, ProtocolError, Urgency, PACKET_TYPE_ADMIN};
+
+/// Operator command sent to the server.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum AdminRequest {
+    /// List every connected session.
+    List,
+    /// Show one session.
+    Inspect { session: u64 },
+    /// Disconnect a session.
+    Kick {
+        session: u64,
+        #[serde(default)]
+        reason: Option<String>,
+    },
+    /// Deliver a message packet to one session.
+    Send {
+        session: u64,
+        message: String,
+        #[serde(default = "default_urgency")]
+        urgency: String,
+    },
+    /// List the cluster's nodes and the sessions on each.
+    Cluster,
+}
+
+fn default_urgency() -> String {
+    Urgency::Green.as_str().to_string()
+}
+
+/// Snapshot of one connected session.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionInfo {
+    pub id: u64,
+    pub peer: String,
+    /// Path the session connected to.
+    #[serde(default)]
+    pub endpoint: String,
+    /// Authenticated identity, if the session has one.
+    pub identity: Option<String>,
+    /// Subject of the bearer token presented on upgrade, if any.
+    #[serde(default)]
+    pub token_subject: Option<String>,
+    /// Roles the server's authorization policy applies to the session.
+    #[serde(default)]
+    pub roles: Vec<String>,
+    /// Bearer token expiry as seconds since the Unix epoch.
+    #[serde(default)]
+    pub token_expires_at: Option<u64>,
+    pub subprotocol: String,
+    pub version: u8,
+    /// Connect time as seconds since the Unix epoch.
+    pub connected_at: u64,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub packets_in: u64,
+    pub packets_out: u64,
+    pub subscriptions: Vec<String>,
+}
+
+/// A session connected to another node of the cluster.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemoteSession {
+    /// Session id on its own node.
+    pub id: u64,
+    /// Token subject or certificate name, if the session has one.
+    pub who: Option<String>,
+}
+
+/// One node of the cluster as seen from the node answering.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeInfo {
+    pub node: String,
+    /// Whether this is the node answering.
+    pub local: bool,
+    /// Whether the node's link to the answering node is up.
+    pub connected: bool,
+    pub sessions: Vec<RemoteSession>,
+}
+
+/// Server answer to an [`AdminRequest`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "result", content = "data", rename_all = "snake_case")]
+pub enum AdminResponse {
+    Sessions(Vec<SessionInfo>),
+    Session(Box<SessionInfo>),
+    Cluster(Vec<NodeInfo>),
+    Done(String),
+    Error(String),
+}
+
+impl Packet {
+    /// Build an ADMIN request packet.
+    pub fn admin_request(request: &AdminRequest) -> Self {
+        Self::typed(
+            PACKET_TYPE_ADMIN,
+            Urgency::Yellow,
+            serde_json::to_vec(request).unwrap_or_default(),
+        )
+    }
+
+    /// Build an ADMIN response packet.
+    pub fn admin_response(response: &AdminResponse) -> Self {
+        Self::typed(
+            PACKET_TYPE_ADMIN,
+            Urgency::Yellow,
+            serde_json::to_vec(response).unwrap_or_default(),
+        )
+    }
+
+    /// Parse an ADMIN request packet.
+    pub fn to_admin_request(&self) -> Result<AdminRequest, ProtocolError> {
+        self.expect_type(PACKET_TYPE_ADMIN)?;
+        Ok(serde_json::from_slice(&self.payload)?)
+    }
+
+    /// Parse an ADMIN response packet.
+    pub fn to_admin_response(&self) -> Result<AdminResponse, ProtocolError> {
+        self.expect_type(PACKET_TYPE_ADMIN)?;
+        Ok(serde_json::from_slice(&self.payload)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_admin_roundtrip() {
+        let request: AdminRequest =
+            serde_json::from_str(r#"{"command":"kick","session":7}"#).unwrap();
+        assert_eq!(
+            request,
+            AdminRequest::Kick {
+                session: 7,
+                reason: None
+            }
+        );
+
+        let packet = Packet::from_bytes(&Packet::admin_request(&request).to_bytes()).unwrap();
+        assert_eq!(packet.to_admin_request().unwrap(), request);
+
+        let response = AdminResponse::Done("kicked session 7".into());
+        let packet = Packet::admin_response(&response);
+        assert_eq!(packet.to_admin_response().unwrap(), response);
+    }
+}
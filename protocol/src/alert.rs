@@ -0,0 +1,127 @@
+//! Alert fan-out: where a rebroadcast alert came from, and duplicate suppression.
+//!
+//! When the server rebroadcasts a RED or YELLOW packet it stamps it with an
+//! [`EXT_ORIGIN`] extension naming the alert and the session that raised it.
+//! The stamp travels with every copy, so a relay that forwards the alert back
+//! into the mesh produces a packet the server recognises and drops.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{HashSet, VecDeque};
+use std::time::{Duration, Instant};
+
+use crate::{Packet, ProtocolError};
+
+/// Extension kind carrying the JSON-encoded [`AlertOrigin`] of a rebroadcast alert.
+pub const EXT_ORIGIN: u8 = 2;
+
+/// Where an alert was first raised.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AlertOrigin {
+    /// Identifier unique to this alert, shared by every copy of it.
+    pub id: String,
+    /// Session that raised the alert, on the server that first saw it.
+    pub session: u64,
+    /// Authenticated name of that session, or `anonymous`.
+    pub who: String,
+}
+
+impl Packet {
+    /// Stamp this packet with the origin of the alert it carries (v2 extension).
+    pub fn with_origin(mut self, origin: &AlertOrigin) -> Result<Self, ProtocolError> {
+        self.set_extension(EXT_ORIGIN, serde_json::to_vec(origin)?)?;
+        Ok(self)
+    }
+
+    /// The origin stamp of a rebroadcast alert, if any.
+    pub fn origin(&self) -> Option<AlertOrigin> {
+        self.extension(EXT_ORIGIN)
+            .and_then(|data| serde_json::from_slice(data).ok())
+    }
+}
+
+/// Remembers recently seen alert ids so each alert is delivered once.
+///
+/// Ids are forgotten after `ttl`, or oldest first once `capacity` are held.
+#[derive(Debug)]
+pub struct AlertDeduplicator {
+    ttl: Duration,
+    capacity: usize,
+    seen: HashSet<String>,
+    order: VecDeque<(Instant, String)>,
+}
+
+impl AlertDeduplicator {
+    /// Create a deduplicator remembering up to `capacity` ids for `ttl` each.
+    pub fn new(ttl: Duration, capacity: usize) -> Self {
+        Self {
+            ttl,
+            capacity: capacity.max(1),
+            seen: HashSet::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Record an alert id, returning `false` if it was already seen.
+    pub fn first_sighting(&mut self, id: &str, now: Instant) -> bool {
+        self.expire(now);
+        if !self.seen.insert(id.to_string()) {
+            return false;
+        }
+        self.order.push_back((now, id.to_string()));
+        true
+    }
+
+    /// Whether an alert id was seen within the ttl, without recording it.
+    pub fn seen(&mut self, id: &str, now: Instant) -> bool {
+        self.expire(now);
+        self.seen.contains(id)
+    }
+
+    /// Forget ids older than the ttl, and the oldest ones beyond capacity.
+    fn expire(&mut self, now: Instant) {
+        while let Some((seen_at, _)) = self.order.front() {
+            if now.saturating_duration_since(*seen_at) < self.ttl
+                && self.order.len() < self.capacity
+            {
+                break;
+            }
+            if let Some((_, old)) = self.order.pop_front() {
+                self.seen.remove(&old);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Urgency;
+
+    #[test]
+    fn test_origin_roundtrip_and_dedup() {
+        let origin = AlertOrigin {
+            id: "node-a-1".into(),
+            session: 3,
+            who: "op-1".into(),
+        };
+        let packet = Packet::new("TARGET LOST", Urgency::Red)
+            .with_version(crate::PROTOCOL_VERSION_2)
+            .with_origin(&origin)
+            .unwrap();
+        let decoded = Packet::from_bytes(&packet.to_bytes()).unwrap();
+        assert_eq!(decoded.origin(), Some(origin));
+
+        let start = Instant::now();
+        let mut dedup = AlertDeduplicator::new(Duration::from_secs(60), 2);
+        assert!(dedup.first_sighting("a", start));
+        assert!(!dedup.first_sighting("a", start + Duration::from_secs(1)));
+        assert!(dedup.seen("a", start));
+        assert!(!dedup.seen("b", start));
+        assert!(dedup.first_sighting("b", start));
+        // Capacity reached: "a" is evicted to make room for "c"
+        assert!(dedup.first_sighting("c", start));
+        assert!(dedup.first_sighting("a", start));
+        // Expired after the ttl
+        assert!(dedup.first_sighting("c", start + Duration::from_secs(61)));
+    }
+}
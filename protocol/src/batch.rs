@@ -0,0 +1,213 @@
+//! Batch container packets and send-side coalescing.
+//!
+//! A batch packet carries several complete wire-format packets back to back in
+//! its payload, so a burst of routine telemetry costs a single WebSocket frame.
+//! Only GREEN packets are ever coalesced; anything more urgent goes out on its
+//! own and flushes whatever is pending.
+
+use std::time::{Duration, Instant};
+
+use crate::{Packet, ProtocolError, Urgency, HEADER_LEN_V2, PACKET_TYPE_BATCH, PROTOCOL_VERSION};
+
+impl Packet {
+    /// Wrap already-built packets into a single batch container packet.
+    ///
+    /// The container takes the highest urgency of its members and the wire
+    /// version of the first one.
+    pub fn batch(packets: &[Packet]) -> Self {
+        let mut payload = Vec::new();
+        let mut urgency = Urgency::Green;
+        for packet in packets {
+            payload.extend_from_slice(&packet.to_bytes());
+            if packet.header.urgency as u8 > urgency as u8 {
+                urgency = packet.header.urgency;
+            }
+        }
+
+        let version = packets
+            .first()
+            .map_or(PROTOCOL_VERSION, |p| p.header.version);
+        Self::typed(PACKET_TYPE_BATCH, urgency, payload).with_version(version)
+    }
+
+    /// Whether this packet is a batch container.
+    pub fn is_batch(&self) -> bool {
+        self.header.packet_type == PACKET_TYPE_BATCH
+    }
+
+    /// Split a batch container back into its member packets.
+    ///
+    /// Nested batches and members of another version than the container are rejected.
+    pub fn unbatch(&self) -> Result<Vec<Packet>, ProtocolError> {
+        if !self.is_batch() {
+            return Err(ProtocolError::InvalidFormat(format!(
+                "packet type {} is not a batch",
+                self.header.packet_type
+            )));
+        }
+
+        let mut packets = Vec::new();
+        let mut rest = self.payload.as_slice();
+        while !rest.is_empty() {
+            let packet = Packet::from_bytes(rest)?;
+            if packet.is_batch() {
+                return Err(ProtocolError::InvalidFormat("nested batch packet".into()));
+            }
+            if packet.header.version != self.header.version {
+                return Err(ProtocolError::InvalidFormat(format!(
+                    "v{} member in a v{} batch",
+                    packet.header.version, self.header.version
+                )));
+            }
+            rest = &rest[packet.wire_len()..];
+            packets.push(packet);
+        }
+
+        Ok(packets)
+    }
+}
+
+/// Worst-case wire overhead of the batch container's own header.
+const BATCH_HEADER_LEN: usize = HEADER_LEN_V2;
+
+/// Groups GREEN packets sent within a short window into batch packets.
+///
+/// The coalescer is transport-agnostic: callers push packets as they are
+/// produced, send whatever it hands back immediately, and call
+/// [`BatchCoalescer::poll_expired`] once [`BatchCoalescer::deadline`] passes.
+#[derive(Debug)]
+pub struct BatchCoalescer {
+    window: Duration,
+    max_packets: usize,
+    max_bytes: usize,
+    pending: Vec<Packet>,
+    pending_bytes: usize,
+    deadline: Option<Instant>,
+}
+
+impl BatchCoalescer {
+    /// Create a coalescer. A zero window disables batching entirely.
+    pub fn new(window: Duration, max_packets: usize, max_bytes: usize) -> Self {
+        Self {
+            window,
+            max_packets: max_packets.max(1),
+            max_bytes,
+            pending: Vec::new(),
+            pending_bytes: 0,
+            deadline: None,
+        }
+    }
+
+    /// Create a coalescer that never batches.
+    pub fn disabled() -> Self {
+        Self::new(Duration::ZERO, 1, 0)
+    }
+
+    /// Queue a packet, returning the frames that must be sent right now.
+    ///
+    /// RED packets are returned first, followed by any pending batch they flushed.
+    /// YELLOW packets bypass batching but leave pending GREEN packets in place.
+    pub fn push(&mut self, packet: Packet, now: Instant) -> Vec<Packet> {
+        if self.window.is_zero() || packet.is_batch() {
+            return vec![packet];
+        }
+
+        match packet.header.urgency {
+            Urgency::Red => {
+                let mut out = vec![packet];
+                out.extend(self.flush());
+                out
+            }
+            Urgency::Yellow => vec![packet],
+            Urgency::Green => {
+                // Never let the container grow past max_bytes (members plus its own header)
+                let mut out = Vec::new();
+                if !self.pending.is_empty()
+                    && BATCH_HEADER_LEN + self.pending_bytes + packet.wire_len() > self.max_bytes
+                {
+                    out.extend(self.flush());
+                }
+
+                self.pending_bytes += packet.wire_len();
+                self.pending.push(packet);
+                if self.deadline.is_none() {
+                    self.deadline = Some(now + self.window);
+                }
+
+                if self.pending.len() >= self.max_packets {
+                    out.extend(self.flush());
+                }
+                out
+            }
+        }
+    }
+
+    /// When the pending batch must be flushed, if anything is pending.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Flush the pending batch if its window has elapsed.
+    pub fn poll_expired(&mut self, now: Instant) -> Option<Packet> {
+        match self.deadline {
+            Some(deadline) if now >= deadline => self.flush(),
+            _ => None,
+        }
+    }
+
+    /// Flush whatever is pending. A lone packet is returned unwrapped.
+    pub fn flush(&mut self) -> Option<Packet> {
+        self.deadline = None;
+        self.pending_bytes = 0;
+        match self.pending.len() {
+            0 => None,
+            1 => self.pending.pop(),
+            _ => Some(Packet::batch(&std::mem::take(&mut self.pending))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_batch_roundtrip() {
+        let batch = Packet::batch(&[Packet::green("a"), Packet::yellow("bb")]);
+        let decoded = Packet::from_bytes(&batch.to_bytes()).unwrap();
+
+        assert!(decoded.is_batch());
+        assert_eq!(decoded.header.urgency, Urgency::Yellow);
+        let members = decoded.unbatch().unwrap();
+        assert_eq!(members.len(), 2);
+        assert_eq!(members[1].payload_str().unwrap(), "bb");
+    }
+
+    #[test]
+    fn test_member_version_must_match_container() {
+        let member = Packet::red("LOCK")
+            .with_version(crate::PROTOCOL_VERSION_2)
+            .with_topic("uav-7/track")
+            .unwrap();
+        // A v1 container smuggling a v2 member with a topic
+        let batch = Packet::batch(&[member]).with_version(PROTOCOL_VERSION);
+        let decoded = Packet::from_bytes(&batch.to_bytes()).unwrap();
+
+        assert!(decoded.unbatch().is_err());
+    }
+
+    #[test]
+    fn test_red_flushes_pending_batch() {
+        let now = Instant::now();
+        let mut coalescer = BatchCoalescer::new(Duration::from_millis(10), 64, 64 * 1024);
+
+        assert!(coalescer.push(Packet::green("one"), now).is_empty());
+        assert!(coalescer.push(Packet::green("two"), now).is_empty());
+
+        let out = coalescer.push(Packet::red("FIRE"), now);
+        assert_eq!(out.len(), 2);
+        assert_eq!(out[0].header.urgency, Urgency::Red);
+        assert!(out[1].is_batch());
+        assert!(coalescer.deadline().is_none());
+    }
+}
@@ -0,0 +1,342 @@
+//! Rule-based classification of untyped messages.
+//!
+//! Legacy sensors send plain text with no header, so nothing tells us whether
+//! "TARGET LOST" is routine or critical. A [`Classifier`] evaluates an ordered
+//! list of rules against such input and assigns urgency and packet type before
+//! dispatch. The first matching rule wins; unmatched input stays GREEN.
+//!
+//! Rules are loaded from a JSON file:
+//!
+//! ```json
+//! {
+//!   "rules": [
+//!     { "name": "target-lost", "when": { "keyword": { "any": ["TARGET LOST"] } }, "urgency": "RED" },
+//!     { "name": "bearing", "when": { "regex": { "pattern": "BEARING \\d{3}" } }, "urgency": "YELLOW" },
+//!     { "name": "json-alert", "when": { "json_field": { "pointer": "/alert", "equals": "critical" } },
+//!       "urgency": "RED", "packet_type": 5 }
+//!   ]
+//! }
+//! ```
+//!
+//! A rule may only produce MESSAGE or TRACK packets: text never becomes a
+//! control packet such as ADMIN or SUBSCRIBE.
+
+use regex::Regex;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::SystemTime;
+
+use crate::{ProtocolError, Urgency, PACKET_TYPE_MESSAGE, PACKET_TYPE_TRACK};
+
+/// Rule condition as written in the rules file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum PredicateSpec {
+    /// Any of the keywords occurs in the text.
+    Keyword {
+        any: Vec<String>,
+        #[serde(default)]
+        case_sensitive: bool,
+    },
+    /// The text matches a regular expression.
+    Regex { pattern: String },
+    /// The text is JSON and the field at `pointer` equals `equals`, or exists if unset.
+    JsonField {
+        pointer: String,
+        #[serde(default)]
+        equals: Option<serde_json::Value>,
+    },
+}
+
+/// Rule as written in the rules file.
+#[derive(Debug, Clone, Deserialize)]
+struct RuleSpec {
+    name: String,
+    when: PredicateSpec,
+    #[serde(deserialize_with = "deserialize_urgency")]
+    urgency: Urgency,
+    #[serde(default = "default_packet_type")]
+    packet_type: u8,
+}
+
+/// Accept urgency names case-insensitively, as [`Urgency::from_str`] does.
+fn deserialize_urgency<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Urgency, D::Error> {
+    String::deserialize(deserializer)?
+        .parse()
+        .map_err(serde::de::Error::custom)
+}
+
+fn default_packet_type() -> u8 {
+    PACKET_TYPE_MESSAGE
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct RulesFile {
+    rules: Vec<RuleSpec>,
+}
+
+/// Compiled rule condition.
+#[derive(Debug)]
+enum Predicate {
+    Keyword {
+        any: Vec<String>,
+        case_sensitive: bool,
+    },
+    Regex(Regex),
+    JsonField {
+        pointer: String,
+        equals: Option<serde_json::Value>,
+    },
+}
+
+impl Predicate {
+    fn compile(spec: PredicateSpec, rule: &str) -> Result<Self, ProtocolError> {
+        Ok(match spec {
+            PredicateSpec::Keyword {
+                any,
+                case_sensitive,
+            } => Predicate::Keyword {
+                any: if case_sensitive {
+                    any
+                } else {
+                    any.iter().map(|k| k.to_lowercase()).collect()
+                },
+                case_sensitive,
+            },
+            PredicateSpec::Regex { pattern } => {
+                Predicate::Regex(Regex::new(&pattern).map_err(|e| {
+                    ProtocolError::InvalidFormat(format!("rule {}: invalid regex: {}", rule, e))
+                })?)
+            }
+            PredicateSpec::JsonField { pointer, equals } => {
+                Predicate::JsonField { pointer, equals }
+            }
+        })
+    }
+
+    fn matches(&self, text: &str, lowered: &str, json: Option<&serde_json::Value>) -> bool {
+        match self {
+            Predicate::Keyword {
+                any,
+                case_sensitive,
+            } => {
+                let haystack = if *case_sensitive { text } else { lowered };
+                any.iter()
+                    .any(|keyword| haystack.contains(keyword.as_str()))
+            }
+            Predicate::Regex(regex) => regex.is_match(text),
+            Predicate::JsonField { pointer, equals } => {
+                match json.and_then(|value| value.pointer(pointer)) {
+                    Some(field) => equals.as_ref().is_none_or(|expected| field == expected),
+                    None => false,
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Rule {
+    name: String,
+    predicate: Predicate,
+    urgency: Urgency,
+    packet_type: u8,
+}
+
+/// Outcome of classifying one message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Classification {
+    pub urgency: Urgency,
+    pub packet_type: u8,
+    /// Name of the matching rule, `None` when the default applied.
+    pub rule: Option<String>,
+}
+
+impl Default for Classification {
+    fn default() -> Self {
+        Self {
+            urgency: Urgency::Green,
+            packet_type: PACKET_TYPE_MESSAGE,
+            rule: None,
+        }
+    }
+}
+
+/// Ordered set of compiled classification rules.
+#[derive(Debug, Default)]
+pub struct Classifier {
+    rules: Vec<Rule>,
+}
+
+impl Classifier {
+    /// Compile rules from the JSON rules-file format.
+    pub fn from_json_str(json: &str) -> Result<Self, ProtocolError> {
+        let file: RulesFile = serde_json::from_str(json)?;
+        let rules = file
+            .rules
+            .into_iter()
+            .map(|spec| {
+                if ![PACKET_TYPE_MESSAGE, PACKET_TYPE_TRACK].contains(&spec.packet_type) {
+                    return Err(ProtocolError::InvalidFormat(format!(
+                        "rule {}: packet_type {} is not MESSAGE ({}) or TRACK ({})",
+                        spec.name, spec.packet_type, PACKET_TYPE_MESSAGE, PACKET_TYPE_TRACK
+                    )));
+                }
+                Ok(Rule {
+                    predicate: Predicate::compile(spec.when, &spec.name)?,
+                    name: spec.name,
+                    urgency: spec.urgency,
+                    packet_type: spec.packet_type,
+                })
+            })
+            .collect::<Result<_, ProtocolError>>()?;
+
+        Ok(Self { rules })
+    }
+
+    /// Load and compile a rules file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ProtocolError> {
+        Self::from_json_str(&std::fs::read_to_string(path)?)
+    }
+
+    /// Number of rules.
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    /// Whether there are no rules.
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Classify untyped text. The first matching rule wins.
+    pub fn classify(&self, text: &str) -> Classification {
+        let lowered = text.to_lowercase();
+        let needs_json = self
+            .rules
+            .iter()
+            .any(|rule| matches!(rule.predicate, Predicate::JsonField { .. }));
+        let json = needs_json
+            .then(|| serde_json::from_str::<serde_json::Value>(text).ok())
+            .flatten();
+
+        self.rules
+            .iter()
+            .find(|rule| rule.predicate.matches(text, &lowered, json.as_ref()))
+            .map(|rule| Classification {
+                urgency: rule.urgency,
+                packet_type: rule.packet_type,
+                rule: Some(rule.name.clone()),
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// A [`Classifier`] backed by a rules file that can be reloaded while running.
+///
+/// A file that fails to load or compile leaves the previous rules in place.
+#[derive(Debug)]
+pub struct ReloadingClassifier {
+    path: PathBuf,
+    current: RwLock<Arc<Classifier>>,
+    modified: Mutex<Option<SystemTime>>,
+}
+
+impl ReloadingClassifier {
+    /// Load the rules file for the first time.
+    pub fn load(path: impl Into<PathBuf>) -> Result<Self, ProtocolError> {
+        let path = path.into();
+        let modified = std::fs::metadata(&path)?.modified().ok();
+        let classifier = Classifier::load(&path)?;
+
+        Ok(Self {
+            path,
+            current: RwLock::new(Arc::new(classifier)),
+            modified: Mutex::new(modified),
+        })
+    }
+
+    /// Path of the backing rules file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Snapshot of the rules currently in force.
+    pub fn current(&self) -> Arc<Classifier> {
+        Arc::clone(&self.current.read().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    /// Reload the rules if the file's modification time changed.
+    ///
+    /// Returns `Ok(true)` when new rules were installed.
+    pub fn reload_if_changed(&self) -> Result<bool, ProtocolError> {
+        let modified = std::fs::metadata(&self.path)?.modified().ok();
+        let mut last = self.modified.lock().unwrap_or_else(|e| e.into_inner());
+        if modified == *last {
+            return Ok(false);
+        }
+
+        // Record the attempt first so a broken file is not re-parsed every poll
+        *last = modified;
+        let classifier = Classifier::load(&self.path)?;
+        *self.current.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(classifier);
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULES: &str = r#"{
+        "rules": [
+            { "name": "lost", "when": { "keyword": { "any": ["target lost"] } }, "urgency": "RED" },
+            { "name": "bearing", "when": { "regex": { "pattern": "BEARING \\d{3}" } }, "urgency": "YELLOW" },
+            { "name": "json", "when": { "json_field": { "pointer": "/alert", "equals": "critical" } },
+              "urgency": "RED", "packet_type": 5 }
+        ]
+    }"#;
+
+    #[test]
+    fn test_rules_assign_urgency_and_type() {
+        let classifier = Classifier::from_json_str(RULES).unwrap();
+
+        let lost = classifier.classify("SENSOR 4: TARGET LOST");
+        assert_eq!(lost.urgency, Urgency::Red);
+        assert_eq!(lost.rule.as_deref(), Some("lost"));
+
+        assert_eq!(classifier.classify("BEARING 270").urgency, Urgency::Yellow);
+
+        let alert = classifier.classify(r#"{"alert":"critical"}"#);
+        assert_eq!(alert.urgency, Urgency::Red);
+        assert_eq!(alert.packet_type, PACKET_TYPE_TRACK);
+
+        assert_eq!(classifier.classify("all quiet"), Classification::default());
+    }
+
+    #[test]
+    fn test_invalid_regex_rejected() {
+        let rules = r#"{ "rules": [ { "name": "bad", "when": { "regex": { "pattern": "(" } }, "urgency": "RED" } ] }"#;
+
+        assert!(Classifier::from_json_str(rules).is_err());
+    }
+
+    #[test]
+    fn test_control_packet_types_rejected() {
+        let rule = |packet_type: u8| {
+            format!(
+                r#"{{ "rules": [ {{ "name": "r", "when": {{ "keyword": {{ "any": ["x"] }} }},
+                    "urgency": "RED", "packet_type": {} }} ] }}"#,
+                packet_type
+            )
+        };
+        for packet_type in 0..=u8::MAX {
+            let loaded = Classifier::from_json_str(&rule(packet_type));
+            let allowed = packet_type == PACKET_TYPE_MESSAGE || packet_type == PACKET_TYPE_TRACK;
+            assert_eq!(loaded.is_ok(), allowed, "packet_type {}", packet_type);
+        }
+    }
+}
@@ -0,0 +1,283 @@
+//! permessage-deflate (RFC 7692) extension negotiation.
+//!
+//! [`DeflateParams`] parses and answers `Sec-WebSocket-Extensions` offers.
+//! The frames themselves are compressed by the transport, underneath the
+//! WebSocket library (see `svckit::wire`).
+
+use thiserror::Error;
+
+/// Registered name of the extension in `Sec-WebSocket-Extensions`.
+pub const EXTENSION_DEFLATE: &str = "permessage-deflate";
+
+/// Largest LZ77 window, and the default when a side names none.
+pub const MAX_WINDOW_BITS: u8 = 15;
+
+/// Smallest window we compress with; zlib cannot write raw streams with an 8-bit window.
+pub const MIN_WINDOW_BITS: u8 = 9;
+
+/// Negotiation and compression failures. The display text doubles as the close reason.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum DeflateError {
+    #[error("invalid permessage-deflate response: {0}")]
+    InvalidResponse(String),
+
+    #[error("message exceeds the {limit} byte compression limit")]
+    TooLarge { limit: usize },
+
+    #[error("corrupt compressed data: {0}")]
+    Corrupt(String),
+}
+
+/// Agreed permessage-deflate parameters of a session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeflateParams {
+    /// Window the server compresses with, as a power of two.
+    pub server_max_window_bits: u8,
+    /// Window the client compresses with, as a power of two.
+    pub client_max_window_bits: u8,
+    /// Server resets its compressor after every message.
+    pub server_no_context_takeover: bool,
+    /// Client resets its compressor after every message.
+    pub client_no_context_takeover: bool,
+}
+
+/// Parameters of one extension offer or response, as written by the peer.
+#[derive(Debug, Default)]
+struct Offer {
+    server_max_window_bits: Option<u8>,
+    /// `Some(None)` for the bare parameter, which only announces support.
+    client_max_window_bits: Option<Option<u8>>,
+    server_no_context_takeover: bool,
+    client_no_context_takeover: bool,
+}
+
+impl Offer {
+    /// Parse one comma-separated element of `Sec-WebSocket-Extensions`.
+    ///
+    /// Returns `None` for other extensions, `Some(Err)` for a malformed offer.
+    fn parse(element: &str) -> Option<Result<Self, String>> {
+        let mut parts = element.split(';').map(str::trim);
+        if !parts.next()?.eq_ignore_ascii_case(EXTENSION_DEFLATE) {
+            return None;
+        }
+
+        let mut offer = Offer::default();
+        for param in parts.filter(|param| !param.is_empty()) {
+            let (name, value) = match param.split_once('=') {
+                Some((name, value)) => (name.trim(), Some(value.trim().trim_matches('"'))),
+                None => (param, None),
+            };
+            let bits = |value: Option<&str>| -> Result<u8, String> {
+                value
+                    .and_then(|value| value.parse().ok())
+                    .filter(|bits| (8..=MAX_WINDOW_BITS).contains(bits))
+                    .ok_or_else(|| format!("invalid {} value {:?}", name, value))
+            };
+            let duplicate = match name {
+                "server_max_window_bits" => offer
+                    .server_max_window_bits
+                    .replace(match bits(value) {
+                        Ok(bits) => bits,
+                        Err(e) => return Some(Err(e)),
+                    })
+                    .is_some(),
+                "client_max_window_bits" => {
+                    let bits = match value.map(|value| bits(Some(value))).transpose() {
+                        Ok(bits) => bits,
+                        Err(e) => return Some(Err(e)),
+                    };
+                    offer.client_max_window_bits.replace(bits).is_some()
+                }
+                "server_no_context_takeover" if value.is_none() => {
+                    std::mem::replace(&mut offer.server_no_context_takeover, true)
+                }
+                "client_no_context_takeover" if value.is_none() => {
+                    std::mem::replace(&mut offer.client_no_context_takeover, true)
+                }
+                _ => return Some(Err(format!("unexpected parameter {:?}", param))),
+            };
+            if duplicate {
+                return Some(Err(format!("duplicate parameter {}", name)));
+            }
+        }
+        Some(Ok(offer))
+    }
+}
+
+impl DeflateParams {
+    /// The client's offer for a local window size and context takeover policy.
+    pub fn offer(window_bits: u8, context_takeover: bool) -> String {
+        let window_bits = window_bits.clamp(MIN_WINDOW_BITS, MAX_WINDOW_BITS);
+        let mut offer = format!("{}; client_max_window_bits", EXTENSION_DEFLATE);
+        if window_bits < MAX_WINDOW_BITS {
+            offer.push_str(&format!("; server_max_window_bits={}", window_bits));
+        }
+        if !context_takeover {
+            offer.push_str("; server_no_context_takeover; client_no_context_takeover");
+        }
+        offer
+    }
+
+    /// Accept the first acceptable offer in `Sec-WebSocket-Extensions` header
+    /// values, shrinking windows to `window_bits` and dropping context takeover
+    /// unless `context_takeover` is set. `None` when nothing was acceptable.
+    pub fn negotiate<'a>(
+        offered: impl IntoIterator<Item = &'a str>,
+        window_bits: u8,
+        context_takeover: bool,
+    ) -> Option<Self> {
+        let window_bits = window_bits.clamp(MIN_WINDOW_BITS, MAX_WINDOW_BITS);
+        offered
+            .into_iter()
+            .flat_map(|value| value.split(','))
+            .filter_map(Offer::parse)
+            .filter_map(Result::ok)
+            // We cannot honour a request for an 8-bit server window
+            .filter(|offer| offer.server_max_window_bits != Some(8))
+            .map(|offer| Self {
+                server_max_window_bits: offer
+                    .server_max_window_bits
+                    .map_or(window_bits, |bits| bits.min(window_bits)),
+                // Without the parameter the client may use any window
+                client_max_window_bits: match offer.client_max_window_bits {
+                    Some(bits) => bits.unwrap_or(MAX_WINDOW_BITS).min(window_bits),
+                    None => MAX_WINDOW_BITS,
+                },
+                server_no_context_takeover: offer.server_no_context_takeover || !context_takeover,
+                client_no_context_takeover: offer.client_no_context_takeover || !context_takeover,
+            })
+            .next()
+    }
+
+    /// Check the server's response to an [`DeflateParams::offer`] made with the same settings.
+    pub fn accept(
+        response: &str,
+        window_bits: u8,
+        context_takeover: bool,
+    ) -> Result<Self, DeflateError> {
+        let window_bits = window_bits.clamp(MIN_WINDOW_BITS, MAX_WINDOW_BITS);
+        let mut elements = response.split(',').filter_map(Offer::parse);
+        let offer = elements
+            .next()
+            .ok_or_else(|| DeflateError::InvalidResponse("extension not selected".into()))?
+            .map_err(DeflateError::InvalidResponse)?;
+        if elements.next().is_some() {
+            return Err(DeflateError::InvalidResponse(
+                "extension selected twice".into(),
+            ));
+        }
+
+        let server_max_window_bits = offer.server_max_window_bits.unwrap_or(MAX_WINDOW_BITS);
+        if server_max_window_bits > window_bits {
+            return Err(DeflateError::InvalidResponse(format!(
+                "server window of {} bits exceeds the {} offered",
+                server_max_window_bits, window_bits
+            )));
+        }
+        if !context_takeover && !offer.server_no_context_takeover {
+            return Err(DeflateError::InvalidResponse(
+                "server kept context takeover".into(),
+            ));
+        }
+        let client_max_window_bits = match offer.client_max_window_bits {
+            Some(Some(8)) => {
+                return Err(DeflateError::InvalidResponse(
+                    "client window of 8 bits is not supported".into(),
+                ))
+            }
+            Some(Some(bits)) => bits.min(window_bits),
+            Some(None) => {
+                return Err(DeflateError::InvalidResponse(
+                    "client_max_window_bits without a value".into(),
+                ))
+            }
+            None => window_bits,
+        };
+
+        Ok(Self {
+            server_max_window_bits,
+            client_max_window_bits,
+            server_no_context_takeover: offer.server_no_context_takeover,
+            client_no_context_takeover: offer.client_no_context_takeover || !context_takeover,
+        })
+    }
+
+    /// `Sec-WebSocket-Extensions` value the server answers with.
+    pub fn to_header(&self) -> String {
+        let mut header = EXTENSION_DEFLATE.to_string();
+        if self.server_max_window_bits < MAX_WINDOW_BITS {
+            header.push_str(&format!(
+                "; server_max_window_bits={}",
+                self.server_max_window_bits
+            ));
+        }
+        if self.client_max_window_bits < MAX_WINDOW_BITS {
+            header.push_str(&format!(
+                "; client_max_window_bits={}",
+                self.client_max_window_bits
+            ));
+        }
+        if self.server_no_context_takeover {
+            header.push_str("; server_no_context_takeover");
+        }
+        if self.client_no_context_takeover {
+            header.push_str("; client_no_context_takeover");
+        }
+        header
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_negotiate_shrinks_windows_and_honours_offer() {
+        let offered = [
+            "x-webkit-deflate-frame",
+            "permessage-deflate; client_max_window_bits; server_max_window_bits=12",
+        ];
+        let params = DeflateParams::negotiate(offered, 10, true).unwrap();
+
+        assert_eq!(params.server_max_window_bits, 10);
+        assert_eq!(params.client_max_window_bits, 10);
+        assert!(!params.server_no_context_takeover);
+        assert_eq!(
+            params.to_header(),
+            "permessage-deflate; server_max_window_bits=10; client_max_window_bits=10"
+        );
+    }
+
+    #[test]
+    fn test_negotiate_skips_invalid_offers() {
+        let offered = ["permessage-deflate; foo, permessage-deflate; server_max_window_bits=8"];
+        assert_eq!(DeflateParams::negotiate(offered, 15, true), None);
+
+        let offered = ["permessage-deflate; server_max_window_bits=20, permessage-deflate"];
+        let params = DeflateParams::negotiate(offered, 15, false).unwrap();
+        assert!(params.server_no_context_takeover && params.client_no_context_takeover);
+        // The client did not offer client_max_window_bits, so its window stays unbounded
+        assert_eq!(params.client_max_window_bits, MAX_WINDOW_BITS);
+    }
+
+    #[test]
+    fn test_offer_round_trips_through_negotiate_and_accept() {
+        let offer = DeflateParams::offer(11, false);
+        let server = DeflateParams::negotiate([offer.as_str()], 13, true).unwrap();
+        let client = DeflateParams::accept(&server.to_header(), 11, false).unwrap();
+
+        assert_eq!(server.server_max_window_bits, 11);
+        assert_eq!(server.client_max_window_bits, 13);
+        // The client may always compress with a smaller window than allowed
+        assert_eq!(client.client_max_window_bits, 11);
+        assert!(client.server_no_context_takeover && client.client_no_context_takeover);
+    }
+
+    #[test]
+    fn test_accept_rejects_responses_outside_the_offer() {
+        assert!(DeflateParams::accept("permessage-deflate", 12, true).is_err());
+        assert!(DeflateParams::accept("permessage-deflate", 15, false).is_err());
+        assert!(DeflateParams::accept("permessage-deflate; mystery", 15, true).is_err());
+        assert!(DeflateParams::accept("x-other", 15, true).is_err());
+    }
+}
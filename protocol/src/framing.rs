@@ -0,0 +1,81 @@
+//! WebSocket subprotocols and the frame encoding each one selects.
+//!
+//! Clients list the subprotocols they speak in `Sec-WebSocket-Protocol`; the
+//! server picks the first one it supports and that name fixes how packets are
+//! carried for the rest of the session.
+
+use std::fmt;
+
+/// Subprotocol carrying packets as binary frames in wire format.
+pub const SUBPROTOCOL_BINARY: &str = "drone-track.v1";
+
+/// Subprotocol carrying packets as text frames in their JSON representation.
+pub const SUBPROTOCOL_JSON: &str = "drone-track.json.v1";
+
+/// How packets are carried in WebSocket frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Framing {
+    /// Binary frames holding wire-format packets (`drone-track.v1`).
+    Binary,
+    /// Text frames holding JSON packets (`drone-track.json.v1`).
+    Json,
+}
+
+impl Framing {
+    /// Every framing mode, in server preference order.
+    pub const ALL: [Framing; 2] = [Framing::Binary, Framing::Json];
+
+    /// Registered subprotocol name for this framing mode.
+    pub fn subprotocol(&self) -> &'static str {
+        match self {
+            Framing::Binary => SUBPROTOCOL_BINARY,
+            Framing::Json => SUBPROTOCOL_JSON,
+        }
+    }
+
+    /// Look up the framing mode for a subprotocol name.
+    pub fn from_subprotocol(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|framing| framing.subprotocol() == name.trim())
+    }
+
+    /// Pick the first subprotocol in a `Sec-WebSocket-Protocol` header value that
+    /// is also in `supported`, honouring the client's order of preference.
+    pub fn negotiate<'a>(
+        offered: impl IntoIterator<Item = &'a str>,
+        supported: &[Framing],
+    ) -> Option<Self> {
+        offered
+            .into_iter()
+            .flat_map(|value| value.split(','))
+            .filter_map(Self::from_subprotocol)
+            .find(|framing| supported.contains(framing))
+    }
+}
+
+impl fmt::Display for Framing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.subprotocol())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_negotiate_honours_client_order() {
+        let offered = ["chat, drone-track.json.v1", "drone-track.v1"];
+
+        assert_eq!(
+            Framing::negotiate(offered, &Framing::ALL),
+            Some(Framing::Json)
+        );
+        assert_eq!(
+            Framing::negotiate(offered, &[Framing::Binary]),
+            Some(Framing::Binary)
+        );
+        assert_eq!(Framing::negotiate(["chat"], &Framing::ALL), None);
+    }
+}
@@ -0,0 +1,278 @@
+//! HELLO / HELLO-ACK capability handshake.
+//!
+//! The client opens every session with a HELLO listing the protocol versions,
+//! payload codecs and optional features it understands. The server answers with
+//! a HELLO-ACK carrying the single agreed version and codec, the smaller of the
+//! two packet size limits, and the features both sides support. Handshake
+//! packets always travel with a version 1 header so that any peer can read them.
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    Packet, ProtocolError, Urgency, PACKET_TYPE_HELLO, PACKET_TYPE_HELLO_ACK, SUPPORTED_VERSIONS,
+};
+
+/// Uncompressed payload codec, supported by every peer.
+pub const CODEC_RAW: &str = "raw";
+
+/// Feature flag: peer accepts batch container packets.
+pub const FEATURE_BATCH: &str = "batch";
+
+/// Default maximum packet size (1 MiB).
+pub const DEFAULT_MAX_PACKET_SIZE: u32 = 1024 * 1024;
+
+/// Capability offer sent by the client.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Hello {
+    pub versions: Vec<u8>,
+    pub max_packet_size: u32,
+    pub codecs: Vec<String>,
+    pub features: Vec<String>,
+}
+
+/// Negotiated session parameters returned by the server.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HelloAck {
+    pub version: u8,
+    pub max_packet_size: u32,
+    pub codec: String,
+    pub features: Vec<String>,
+}
+
+impl HelloAck {
+    /// Whether the named feature was agreed for this session.
+    pub fn has_feature(&self, feature: &str) -> bool {
+        self.features.iter().any(|f| f == feature)
+    }
+}
+
+/// Handshake failures. The display text doubles as the WebSocket close reason.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum HandshakeError {
+    #[error("no common protocol version (offered {offered:?}, supported {supported:?})")]
+    NoCommonVersion {
+        offered: Vec<u8>,
+        supported: Vec<u8>,
+    },
+
+    #[error("no common codec (offered {offered:?})")]
+    NoCommonCodec { offered: Vec<String> },
+
+    #[error("required feature not offered: {0}")]
+    MissingFeature(String),
+
+    #[error("peer answered outside our offer: {0}")]
+    UnexpectedAck(String),
+}
+
+/// Locally supported capabilities, in order of preference.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Supported protocol versions; the highest common one wins.
+    pub versions: Vec<u8>,
+    /// Largest packet this side will accept.
+    pub max_packet_size: u32,
+    /// Payload codecs, most preferred first.
+    pub codecs: Vec<String>,
+    /// Optional features this side understands.
+    pub features: Vec<String>,
+    /// Features the peer must offer or the handshake fails.
+    pub required_features: Vec<String>,
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Self {
+            versions: SUPPORTED_VERSIONS.to_vec(),
+            max_packet_size: DEFAULT_MAX_PACKET_SIZE,
+            codecs: vec![CODEC_RAW.to_string()],
+            features: vec![FEATURE_BATCH.to_string()],
+            required_features: Vec::new(),
+        }
+    }
+}
+
+impl Capabilities {
+    /// Build the HELLO offer for these capabilities.
+    pub fn hello(&self) -> Hello {
+        Hello {
+            versions: self.versions.clone(),
+            max_packet_size: self.max_packet_size,
+            codecs: self.codecs.clone(),
+            features: self.features.clone(),
+        }
+    }
+
+    /// Server side: pick session parameters for a client's HELLO.
+    pub fn negotiate(&self, hello: &Hello) -> Result<HelloAck, HandshakeError> {
+        let version = self
+            .versions
+            .iter()
+            .copied()
+            .filter(|v| hello.versions.contains(v))
+            .max()
+            .ok_or_else(|| HandshakeError::NoCommonVersion {
+                offered: hello.versions.clone(),
+                supported: self.versions.clone(),
+            })?;
+
+        let codec = self
+            .codecs
+            .iter()
+            .find(|c| hello.codecs.contains(c))
+            .cloned()
+            .ok_or_else(|| HandshakeError::NoCommonCodec {
+                offered: hello.codecs.clone(),
+            })?;
+
+        if let Some(missing) = self
+            .required_features
+            .iter()
+            .find(|f| !hello.features.contains(f))
+        {
+            return Err(HandshakeError::MissingFeature(missing.clone()));
+        }
+
+        let features = self
+            .features
+            .iter()
+            .filter(|f| hello.features.contains(f))
+            .cloned()
+            .collect();
+
+        Ok(HelloAck {
+            version,
+            max_packet_size: self.max_packet_size.min(hello.max_packet_size),
+            codec,
+            features,
+        })
+    }
+
+    /// Client side: check that the server's HELLO-ACK stays within our offer.
+    pub fn accept(&self, ack: &HelloAck) -> Result<(), HandshakeError> {
+        if !self.versions.contains(&ack.version) {
+            return Err(HandshakeError::UnexpectedAck(format!(
+                "version {}",
+                ack.version
+            )));
+        }
+        if !self.codecs.contains(&ack.codec) {
+            return Err(HandshakeError::UnexpectedAck(format!(
+                "codec {}",
+                ack.codec
+            )));
+        }
+        if ack.max_packet_size > self.max_packet_size {
+            return Err(HandshakeError::UnexpectedAck(format!(
+                "max packet size {}",
+                ack.max_packet_size
+            )));
+        }
+        if let Some(extra) = ack.features.iter().find(|f| !self.features.contains(f)) {
+            return Err(HandshakeError::UnexpectedAck(format!("feature {}", extra)));
+        }
+        if let Some(missing) = self
+            .required_features
+            .iter()
+            .find(|f| !ack.features.contains(f))
+        {
+            return Err(HandshakeError::MissingFeature(missing.clone()));
+        }
+        Ok(())
+    }
+}
+
+impl Packet {
+    /// Build a HELLO packet.
+    pub fn hello(hello: &Hello) -> Self {
+        Self::typed(
+            PACKET_TYPE_HELLO,
+            Urgency::Green,
+            serde_json::to_vec(hello).unwrap_or_default(),
+        )
+    }
+
+    /// Build a HELLO-ACK packet.
+    pub fn hello_ack(ack: &HelloAck) -> Self {
+        Self::typed(
+            PACKET_TYPE_HELLO_ACK,
+            Urgency::Green,
+            serde_json::to_vec(ack).unwrap_or_default(),
+        )
+    }
+
+    /// Parse a HELLO packet.
+    pub fn to_hello(&self) -> Result<Hello, ProtocolError> {
+        self.expect_type(PACKET_TYPE_HELLO)?;
+        Ok(serde_json::from_slice(&self.payload)?)
+    }
+
+    /// Parse a HELLO-ACK packet.
+    pub fn to_hello_ack(&self) -> Result<HelloAck, ProtocolError> {
+        self.expect_type(PACKET_TYPE_HELLO_ACK)?;
+        Ok(serde_json::from_slice(&self.payload)?)
+    }
+
+    pub(crate) fn expect_type(&self, packet_type: u8) -> Result<(), ProtocolError> {
+        if self.header.packet_type != packet_type {
+            return Err(ProtocolError::InvalidFormat(format!(
+                "expected packet type {}, got {}",
+                packet_type, self.header.packet_type
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_negotiate_picks_common_parameters() {
+        let server = Capabilities {
+            max_packet_size: 4096,
+            ..Capabilities::default()
+        };
+        let client = Capabilities {
+            features: vec!["batch".into(), "telepathy".into()],
+            ..Capabilities::default()
+        };
+
+        let packet = Packet::from_bytes(&Packet::hello(&client.hello()).to_bytes()).unwrap();
+        let ack = server.negotiate(&packet.to_hello().unwrap()).unwrap();
+
+        assert_eq!(ack.version, crate::PROTOCOL_VERSION_2);
+        assert_eq!(ack.max_packet_size, 4096);
+        assert_eq!(ack.features, vec!["batch".to_string()]);
+        assert!(client.accept(&ack).is_ok());
+    }
+
+    #[test]
+    fn test_negotiate_falls_back_to_v1_client() {
+        let server = Capabilities::default();
+        let legacy = Capabilities {
+            versions: vec![crate::PROTOCOL_VERSION],
+            ..Capabilities::default()
+        };
+
+        let ack = server.negotiate(&legacy.hello()).unwrap();
+        assert_eq!(ack.version, crate::PROTOCOL_VERSION);
+        assert!(legacy.accept(&ack).is_ok());
+    }
+
+    #[test]
+    fn test_negotiate_rejects_version_mismatch() {
+        let server = Capabilities::default();
+        let hello = Hello {
+            versions: vec![9],
+            ..server.hello()
+        };
+
+        assert!(matches!(
+            server.negotiate(&hello),
+            Err(HandshakeError::NoCommonVersion { .. })
+        ));
+    }
+}
@@ -0,0 +1,155 @@
+//! Heartbeats and dead-peer detection.
+//!
+//! Each side pings on a fixed interval, with both a WebSocket ping frame and
+//! a HEARTBEAT packet. The frame keeps the transport honest; the packet also
+//! crosses relays that terminate WebSocket, and the peer answers it with an
+//! acknowledging HEARTBEAT. Any inbound frame counts as a sign of life. A peer
+//! silent for a full interval plus the pong timeout is considered gone.
+
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+use crate::{Packet, ProtocolError, Urgency, PACKET_TYPE_HEARTBEAT};
+
+/// Payload of a HEARTBEAT packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Heartbeat {
+    /// Sender's heartbeat counter, echoed in the acknowledgement.
+    pub seq: u64,
+    /// `true` for the answer to a peer's heartbeat.
+    #[serde(default)]
+    pub ack: bool,
+}
+
+impl Heartbeat {
+    /// The acknowledgement for this heartbeat.
+    pub fn ack(&self) -> Self {
+        Self {
+            seq: self.seq,
+            ack: true,
+        }
+    }
+}
+
+/// Change in the state of a session's link, reported to strategy handlers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkEvent {
+    /// The session completed its handshake.
+    Up,
+    /// The peer went silent and the session was dropped.
+    Lost { silent_for: Duration },
+    /// The session ended in an orderly way.
+    Closed,
+}
+
+/// Tracks when to ping the peer and when to give up on it.
+///
+/// Like [`crate::BatchCoalescer`] it does no I/O: callers send a ping when
+/// [`LinkMonitor::next_ping`] passes, call [`LinkMonitor::record_activity`] for
+/// every inbound frame and drop the link once [`LinkMonitor::dead_after`] passes.
+#[derive(Debug)]
+pub struct LinkMonitor {
+    interval: Option<Duration>,
+    timeout: Duration,
+    last_seen: Instant,
+    next_ping: Instant,
+    seq: u64,
+}
+
+impl LinkMonitor {
+    /// Create a monitor. Without an interval it never pings and never declares the peer dead.
+    pub fn new(interval: Option<Duration>, timeout: Duration, now: Instant) -> Self {
+        Self {
+            interval,
+            timeout,
+            last_seen: now,
+            next_ping: now + interval.unwrap_or_default(),
+            seq: 0,
+        }
+    }
+
+    /// Create a monitor that never pings.
+    pub fn disabled() -> Self {
+        Self::new(None, Duration::ZERO, Instant::now())
+    }
+
+    /// Note that a frame arrived from the peer.
+    pub fn record_activity(&mut self, now: Instant) {
+        self.last_seen = now;
+    }
+
+    /// When the next ping is due.
+    pub fn next_ping(&self) -> Option<Instant> {
+        self.interval.map(|_| self.next_ping)
+    }
+
+    /// When the peer counts as dead if nothing more arrives.
+    pub fn dead_after(&self) -> Option<Instant> {
+        self.interval
+            .map(|interval| self.last_seen + interval + self.timeout)
+    }
+
+    /// How long the peer has been silent.
+    pub fn silent_for(&self, now: Instant) -> Duration {
+        now.saturating_duration_since(self.last_seen)
+    }
+
+    /// Schedule the next ping and build this one's HEARTBEAT packet.
+    pub fn ping(&mut self, now: Instant) -> Packet {
+        self.next_ping = now + self.interval.unwrap_or_default();
+        self.seq += 1;
+        Packet::heartbeat(&Heartbeat {
+            seq: self.seq,
+            ack: false,
+        })
+    }
+}
+
+impl Packet {
+    /// Build a HEARTBEAT packet. YELLOW, so batching never delays it.
+    pub fn heartbeat(heartbeat: &Heartbeat) -> Self {
+        Self::typed(
+            PACKET_TYPE_HEARTBEAT,
+            Urgency::Yellow,
+            serde_json::to_vec(heartbeat).unwrap_or_default(),
+        )
+    }
+
+    /// Parse a HEARTBEAT packet.
+    pub fn to_heartbeat(&self) -> Result<Heartbeat, ProtocolError> {
+        self.expect_type(PACKET_TYPE_HEARTBEAT)?;
+        Ok(serde_json::from_slice(&self.payload)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_link_monitor_deadlines() {
+        let start = Instant::now();
+        let interval = Duration::from_secs(10);
+        let mut monitor = LinkMonitor::new(Some(interval), Duration::from_secs(5), start);
+        assert_eq!(monitor.next_ping(), Some(start + interval));
+        assert_eq!(monitor.dead_after(), Some(start + Duration::from_secs(15)));
+
+        let packet = monitor.ping(start + interval);
+        assert_eq!(
+            packet.to_heartbeat().unwrap(),
+            Heartbeat { seq: 1, ack: false }
+        );
+        assert_eq!(monitor.next_ping(), Some(start + interval * 2));
+
+        monitor.record_activity(start + Duration::from_secs(12));
+        assert_eq!(monitor.dead_after(), Some(start + Duration::from_secs(27)));
+        assert_eq!(
+            monitor.silent_for(start + Duration::from_secs(20)),
+            Duration::from_secs(8)
+        );
+
+        let disabled = LinkMonitor::disabled();
+        assert_eq!(disabled.next_ping(), None);
+        assert_eq!(disabled.dead_after(), None);
+    }
+}
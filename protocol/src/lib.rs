@@ -6,13 +6,112 @@
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
-
-/// Protocol version constant.
+use tracing::warn;
+
+mod admin;
+mod alert;
+mod batch;
+mod classify;
+mod deflate;
+mod framing;
+mod handshake;
+mod heartbeat;
+mod mailbox;
+mod stream;
+mod topic;
+mod trace;
+
+pub use admin::{AdminRequest, AdminResponse, NodeInfo, RemoteSession, SessionInfo};
+pub use alert::{AlertDeduplicator, AlertOrigin, EXT_ORIGIN};
+pub use batch::BatchCoalescer;
+pub use classify::{Classification, Classifier, ReloadingClassifier};
+pub use deflate::{
+    DeflateError, DeflateParams, EXTENSION_DEFLATE, MAX_WINDOW_BITS, MIN_WINDOW_BITS,
+};
+pub use framing::{Framing, SUBPROTOCOL_BINARY, SUBPROTOCOL_JSON};
+pub use handshake::{
+    Capabilities, HandshakeError, Hello, HelloAck, CODEC_RAW, DEFAULT_MAX_PACKET_SIZE,
+    FEATURE_BATCH,
+};
+pub use heartbeat::{Heartbeat, LinkEvent, LinkMonitor};
+pub use mailbox::{Mailbox, MailboxLimits, EXT_PACKET_ID};
+pub use stream::{
+    ErrorNotice, TrackUpdate, ERROR_FORBIDDEN, ERROR_INVALID_TOPIC, ERROR_STREAM_LAGGED,
+    ERROR_WRONG_VERSION,
+};
+pub use topic::{TopicFilter, EXT_TOPIC};
+pub use trace::{TraceContext, EXT_TRACE_CONTEXT};
+
+/// Protocol version constant (original 6-byte header).
+///
+/// Handshake packets always use this version so that any peer can read them.
 pub const PROTOCOL_VERSION: u8 = 1;
 
+/// Extended header with 8-bit type, flags, extensions and 64-bit length.
+pub const PROTOCOL_VERSION_2: u8 = 2;
+
+/// Every wire version this crate can encode and decode, oldest first.
+pub const SUPPORTED_VERSIONS: [u8; 2] = [PROTOCOL_VERSION, PROTOCOL_VERSION_2];
+
+/// Size of the v1 header on the wire.
+pub const HEADER_LEN_V1: usize = 6;
+
+/// Size of the fixed v2 header on the wire; the extension block follows it.
+pub const HEADER_LEN_V2: usize = 14;
+
 /// Packet type for standard messages.
 pub const PACKET_TYPE_MESSAGE: u8 = 1;
 
+/// Packet type for batch containers holding several packets.
+pub const PACKET_TYPE_BATCH: u8 = 2;
+
+/// Packet type for the client's capability offer.
+pub const PACKET_TYPE_HELLO: u8 = 3;
+
+/// Packet type for the server's negotiated capabilities.
+pub const PACKET_TYPE_HELLO_ACK: u8 = 4;
+
+/// Packet type for drone coordinate stream updates.
+pub const PACKET_TYPE_TRACK: u8 = 5;
+
+/// Packet type for errors reported without closing the session.
+pub const PACKET_TYPE_ERROR: u8 = 6;
+
+/// Packet type for adding topic subscriptions.
+pub const PACKET_TYPE_SUBSCRIBE: u8 = 7;
+
+/// Packet type for removing topic subscriptions.
+pub const PACKET_TYPE_UNSUBSCRIBE: u8 = 8;
+
+/// Packet type for operator requests and their responses.
+pub const PACKET_TYPE_ADMIN: u8 = 9;
+
+/// Packet type for application-level heartbeats and their acknowledgements.
+pub const PACKET_TYPE_HEARTBEAT: u8 = 10;
+
+/// Short lowercase name of every known packet type, as used in logs, traces,
+/// audit records and policies.
+pub const PACKET_TYPES: &[(u8, &str)] = &[
+    (PACKET_TYPE_MESSAGE, "message"),
+    (PACKET_TYPE_BATCH, "batch"),
+    (PACKET_TYPE_HELLO, "hello"),
+    (PACKET_TYPE_HELLO_ACK, "hello_ack"),
+    (PACKET_TYPE_TRACK, "track"),
+    (PACKET_TYPE_ERROR, "error"),
+    (PACKET_TYPE_SUBSCRIBE, "subscribe"),
+    (PACKET_TYPE_UNSUBSCRIBE, "unsubscribe"),
+    (PACKET_TYPE_ADMIN, "admin"),
+    (PACKET_TYPE_HEARTBEAT, "heartbeat"),
+];
+
+/// Look up a packet type by its [`PACKET_TYPES`] name, ignoring case.
+pub fn packet_type_from_name(name: &str) -> Option<u8> {
+    PACKET_TYPES
+        .iter()
+        .find(|(_, type_name)| type_name.eq_ignore_ascii_case(name))
+        .map(|(packet_type, _)| *packet_type)
+}
+
 /// Urgency levels for packet prioritization.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[repr(u8)]
@@ -36,6 +135,23 @@ impl From<u8> for Urgency {
     }
 }
 
+impl std::str::FromStr for Urgency {
+    type Err = ProtocolError;
+
+    /// Parse an urgency name (`GREEN`, `YELLOW`, `RED`), case-insensitively.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "GREEN" => Ok(Urgency::Green),
+            "YELLOW" => Ok(Urgency::Yellow),
+            "RED" => Ok(Urgency::Red),
+            _ => Err(ProtocolError::InvalidFormat(format!(
+                "unknown urgency: {}",
+                s
+            ))),
+        }
+    }
+}
+
 impl Urgency {
     pub fn as_str(&self) -> &'static str {
         match self {
@@ -48,42 +164,88 @@ impl Urgency {
 
 /// Packed header for wire protocol.
 ///
-/// Layout (6 bytes total):
+/// Version 1 layout (6 bytes total):
 /// - version: 4 bits
 /// - type: 4 bits
 /// - urgent: 2 bits
 /// - reserved: 6 bits
 /// - length: 32 bits
+///
+/// Version 2 layout (14 bytes, followed by the extension block):
+/// - version: 4 bits
+/// - reserved: 4 bits
+/// - type: 8 bits
+/// - urgent: 2 bits
+/// - reserved: 6 bits
+/// - flags: 8 bits
+/// - extension block length: 16 bits
+/// - length: 64 bits
+///
+/// The version nibble sits in the same place in both layouts, so a decoder can
+/// tell them apart from the first byte.
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct PacketHeader {
     pub version: u8,
     pub packet_type: u8,
     pub urgency: Urgency,
-    pub length: u32,
+    /// Feature flags (v2 only, dropped when encoding v1).
+    pub flags: u8,
+    pub length: u64,
 }
 
 impl PacketHeader {
     /// Create a new header with the given urgency and payload length.
-    pub fn new(urgency: Urgency, length: u32) -> Self {
+    pub fn new(urgency: Urgency, length: u64) -> Self {
         Self {
             version: PROTOCOL_VERSION,
             packet_type: PACKET_TYPE_MESSAGE,
             urgency,
+            flags: 0,
             length,
         }
     }
 
-    /// Serialize header to wire format (6 bytes).
-    pub fn to_bytes(&self) -> [u8; 6] {
+    /// Short lowercase name of the packet type, for logs and traces.
+    pub fn type_name(&self) -> &'static str {
+        PACKET_TYPES
+            .iter()
+            .find(|(packet_type, _)| *packet_type == self.packet_type)
+            .map_or("unknown", |(_, name)| name)
+    }
+
+    /// Serialize header to v1 wire format (6 bytes).
+    ///
+    /// Types above 15 and lengths above `u32::MAX` do not fit. Every defined
+    /// packet type is below 16 and payloads are capped by the negotiated
+    /// `max_packet_size`, a `u32`, so v1 headers never need them; encoding one
+    /// anyway is a bug, caught by a debug assertion.
+    pub fn to_bytes(&self) -> [u8; HEADER_LEN_V1] {
+        debug_assert!(
+            self.packet_type <= 0x0F,
+            "packet type {} does not fit a v1 header",
+            self.packet_type
+        );
+        debug_assert!(
+            self.length <= u32::MAX as u64,
+            "length {} does not fit a v1 header",
+            self.length
+        );
         let byte0 = (self.version & 0x0F) | ((self.packet_type & 0x0F) << 4);
         let byte1 = (self.urgency as u8) & 0x03; // 2 bits urgency, 6 bits reserved (zeros)
-        let len_bytes = self.length.to_be_bytes();
-
-        [byte0, byte1, len_bytes[0], len_bytes[1], len_bytes[2], len_bytes[3]]
+        let len_bytes = (self.length as u32).to_be_bytes();
+
+        [
+            byte0,
+            byte1,
+            len_bytes[0],
+            len_bytes[1],
+            len_bytes[2],
+            len_bytes[3],
+        ]
     }
 
-    /// Deserialize header from wire format.
-    pub fn from_bytes(bytes: &[u8; 6]) -> Self {
+    /// Deserialize header from v1 wire format.
+    pub fn from_bytes(bytes: &[u8; HEADER_LEN_V1]) -> Self {
         let version = bytes[0] & 0x0F;
         let packet_type = (bytes[0] >> 4) & 0x0F;
         let urgency = Urgency::from(bytes[1] & 0x03);
@@ -93,15 +255,74 @@ impl PacketHeader {
             version,
             packet_type,
             urgency,
-            length,
+            flags: 0,
+            length: length as u64,
         }
     }
+
+    /// Serialize header to v2 wire format (14 bytes).
+    pub fn to_bytes_v2(&self, extensions_len: u16) -> [u8; HEADER_LEN_V2] {
+        let mut bytes = [0u8; HEADER_LEN_V2];
+        bytes[0] = self.version & 0x0F;
+        bytes[1] = self.packet_type;
+        bytes[2] = (self.urgency as u8) & 0x03;
+        bytes[3] = self.flags;
+        bytes[4..6].copy_from_slice(&extensions_len.to_be_bytes());
+        bytes[6..14].copy_from_slice(&self.length.to_be_bytes());
+        bytes
+    }
+
+    /// Deserialize header from v2 wire format, returning it with the extension block length.
+    pub fn from_bytes_v2(bytes: &[u8; HEADER_LEN_V2]) -> (Self, u16) {
+        let header = Self {
+            version: bytes[0] & 0x0F,
+            packet_type: bytes[1],
+            urgency: Urgency::from(bytes[2] & 0x03),
+            flags: bytes[3],
+            length: u64::from_be_bytes(bytes[6..14].try_into().unwrap()),
+        };
+        let extensions_len = u16::from_be_bytes([bytes[4], bytes[5]]);
+
+        (header, extensions_len)
+    }
+}
+
+/// Typed header extension carried by v2 packets.
+///
+/// Encoded as kind (8 bits), data length (16 bits) and data. Extensions are
+/// metadata only; v1 peers never see them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Extension {
+    pub kind: u8,
+    pub data: Vec<u8>,
+}
+
+impl Extension {
+    fn wire_len(&self) -> usize {
+        3 + self.data.len()
+    }
+}
+
+/// Reject extension lists whose block would not fit the v2 header's 16-bit length.
+fn deserialize_extensions<'de, D>(deserializer: D) -> Result<Vec<Extension>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let extensions = Vec::<Extension>::deserialize(deserializer)?;
+    if extensions.iter().map(Extension::wire_len).sum::<usize>() > u16::MAX as usize {
+        return Err(serde::de::Error::custom("extension block too large"));
+    }
+    Ok(extensions)
 }
 
 /// Complete packet with header and payload.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Packet {
     pub header: PacketHeader,
+    /// Header extensions (v2 only); kept within the block's 16-bit length by
+    /// [`Packet::set_extension`].
+    #[serde(default, deserialize_with = "deserialize_extensions")]
+    extensions: Vec<Extension>,
     pub payload: Vec<u8>,
 }
 
@@ -109,8 +330,23 @@ impl Packet {
     /// Create a new packet from a message string and urgency level.
     pub fn new(message: impl AsRef<str>, urgency: Urgency) -> Self {
         let payload = message.as_ref().as_bytes().to_vec();
-        let header = PacketHeader::new(urgency, payload.len() as u32);
-        Self { header, payload }
+        let header = PacketHeader::new(urgency, payload.len() as u64);
+        Self {
+            header,
+            extensions: Vec::new(),
+            payload,
+        }
+    }
+
+    /// Create a packet of a specific type from a raw payload.
+    pub fn typed(packet_type: u8, urgency: Urgency, payload: Vec<u8>) -> Self {
+        let mut header = PacketHeader::new(urgency, payload.len() as u64);
+        header.packet_type = packet_type;
+        Self {
+            header,
+            extensions: Vec::new(),
+            payload,
+        }
     }
 
     /// Create a GREEN urgency packet (convenience method).
@@ -128,6 +364,45 @@ impl Packet {
         Self::new(message, Urgency::Red)
     }
 
+    /// Re-target the packet at a wire version (as negotiated for a session).
+    pub fn with_version(mut self, version: u8) -> Self {
+        self.header.version = version;
+        self
+    }
+
+    /// Header extensions, in wire order.
+    pub fn extensions(&self) -> &[Extension] {
+        &self.extensions
+    }
+
+    /// Look up the data of the first extension of the given kind.
+    pub fn extension(&self, kind: u8) -> Option<&[u8]> {
+        self.extensions
+            .iter()
+            .find(|ext| ext.kind == kind)
+            .map(|ext| ext.data.as_slice())
+    }
+
+    /// Set an extension, replacing any existing one of the same kind.
+    ///
+    /// Fails if the extension block would no longer fit the v2 header.
+    pub fn set_extension(&mut self, kind: u8, data: Vec<u8>) -> Result<(), ProtocolError> {
+        self.extensions.retain(|ext| ext.kind != kind);
+        let ext = Extension { kind, data };
+        if self.extensions_len() + ext.wire_len() > u16::MAX as usize {
+            return Err(ProtocolError::InvalidFormat(format!(
+                "extension {} does not fit the extension block",
+                kind
+            )));
+        }
+        self.extensions.push(ext);
+        Ok(())
+    }
+
+    fn extensions_len(&self) -> usize {
+        self.extensions.iter().map(Extension::wire_len).sum()
+    }
+
     /// Get payload as UTF-8 string.
     pub fn payload_str(&self) -> Result<&str, std::str::Utf8Error> {
         std::str::from_utf8(&self.payload)
@@ -138,27 +413,61 @@ impl Packet {
         String::from_utf8_lossy(&self.payload).into_owned()
     }
 
-    /// Serialize entire packet to wire format.
+    /// Size of the packet in wire format.
+    pub fn wire_len(&self) -> usize {
+        match self.header.version {
+            PROTOCOL_VERSION_2 => HEADER_LEN_V2 + self.extensions_len() + self.payload.len(),
+            _ => HEADER_LEN_V1 + self.payload.len(),
+        }
+    }
+
+    /// Serialize entire packet to wire format, using the header's version.
+    ///
+    /// Version 1 has no room for flags or extensions, so they are dropped.
     pub fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::with_capacity(6 + self.payload.len());
-        bytes.extend_from_slice(&self.header.to_bytes());
+        let mut header = self.header;
+        header.length = self.payload.len() as u64;
+
+        let mut bytes = Vec::with_capacity(self.wire_len());
+        if header.version == PROTOCOL_VERSION_2 {
+            bytes.extend_from_slice(&header.to_bytes_v2(self.extensions_len() as u16));
+            for ext in &self.extensions {
+                bytes.push(ext.kind);
+                bytes.extend_from_slice(&(ext.data.len() as u16).to_be_bytes());
+                bytes.extend_from_slice(&ext.data);
+            }
+        } else {
+            bytes.extend_from_slice(&header.to_bytes());
+        }
         bytes.extend_from_slice(&self.payload);
         bytes
     }
 
-    /// Deserialize packet from wire format.
+    /// Deserialize packet from wire format, detecting the header version.
     pub fn from_bytes(bytes: &[u8]) -> Result<Self, ProtocolError> {
-        if bytes.len() < 6 {
+        match bytes.first().map(|b| b & 0x0F) {
+            None => Err(ProtocolError::InsufficientData {
+                expected: HEADER_LEN_V1,
+                actual: 0,
+            }),
+            Some(PROTOCOL_VERSION) => Self::from_bytes_v1(bytes),
+            Some(PROTOCOL_VERSION_2) => Self::from_bytes_v2(bytes),
+            Some(version) => Err(ProtocolError::UnsupportedVersion(version)),
+        }
+    }
+
+    fn from_bytes_v1(bytes: &[u8]) -> Result<Self, ProtocolError> {
+        if bytes.len() < HEADER_LEN_V1 {
             return Err(ProtocolError::InsufficientData {
-                expected: 6,
+                expected: HEADER_LEN_V1,
                 actual: bytes.len(),
             });
         }
 
-        let header_bytes: [u8; 6] = bytes[0..6].try_into().unwrap();
+        let header_bytes: [u8; HEADER_LEN_V1] = bytes[0..HEADER_LEN_V1].try_into().unwrap();
         let header = PacketHeader::from_bytes(&header_bytes);
 
-        let expected_len = 6 + header.length as usize;
+        let expected_len = HEADER_LEN_V1 + header.length as usize;
         if bytes.len() < expected_len {
             return Err(ProtocolError::InsufficientData {
                 expected: expected_len,
@@ -166,20 +475,197 @@ impl Packet {
             });
         }
 
-        let payload = bytes[6..expected_len].to_vec();
+        let payload = bytes[HEADER_LEN_V1..expected_len].to_vec();
 
-        Ok(Self { header, payload })
+        Ok(Self {
+            header,
+            extensions: Vec::new(),
+            payload,
+        })
+    }
+
+    fn from_bytes_v2(bytes: &[u8]) -> Result<Self, ProtocolError> {
+        if bytes.len() < HEADER_LEN_V2 {
+            return Err(ProtocolError::InsufficientData {
+                expected: HEADER_LEN_V2,
+                actual: bytes.len(),
+            });
+        }
+
+        let header_bytes: [u8; HEADER_LEN_V2] = bytes[0..HEADER_LEN_V2].try_into().unwrap();
+        let (header, extensions_len) = PacketHeader::from_bytes_v2(&header_bytes);
+
+        let payload_start = HEADER_LEN_V2 + extensions_len as usize;
+        let expected_len = usize::try_from(header.length)
+            .ok()
+            .and_then(|len| payload_start.checked_add(len))
+            .ok_or_else(|| {
+                ProtocolError::InvalidFormat(format!("payload length {} too large", header.length))
+            })?;
+        if bytes.len() < expected_len {
+            return Err(ProtocolError::InsufficientData {
+                expected: expected_len,
+                actual: bytes.len(),
+            });
+        }
+
+        let mut extensions = Vec::new();
+        let mut rest = &bytes[HEADER_LEN_V2..payload_start];
+        while !rest.is_empty() {
+            if rest.len() < 3 {
+                return Err(ProtocolError::InvalidFormat("truncated extension".into()));
+            }
+            let data_len = u16::from_be_bytes([rest[1], rest[2]]) as usize;
+            let data = rest
+                .get(3..3 + data_len)
+                .ok_or_else(|| ProtocolError::InvalidFormat("truncated extension".into()))?;
+            extensions.push(Extension {
+                kind: rest[0],
+                data: data.to_vec(),
+            });
+            rest = &rest[3 + data_len..];
+        }
+
+        let payload = bytes[payload_start..expected_len].to_vec();
+
+        Ok(Self {
+            header,
+            extensions,
+            payload,
+        })
     }
 
     /// Convert to JSON representation.
+    ///
+    /// Packets carrying extensions are written as version 2, whatever their
+    /// header says, since version 1 has no room for them. The topic, origin,
+    /// packet id and trace context are written as their shorthand fields; only
+    /// other extensions, or ones whose data does not decode, go in `extensions`.
     pub fn to_json(&self) -> serde_json::Value {
-        serde_json::json!({
-            "version": self.header.version,
+        let version = match self.extensions.is_empty() {
+            true => self.header.version,
+            false => self.header.version.max(PROTOCOL_VERSION_2),
+        };
+        let mut json = serde_json::json!({
+            "version": version,
             "type": self.header.packet_type,
             "urgency": self.header.urgency.as_str(),
+            "flags": self.header.flags,
             "length": self.header.length,
             "payload": self.payload_string_lossy()
-        })
+        });
+        let mut shorthand = Vec::new();
+        if let Some(topic) = self.topic() {
+            json["topic"] = serde_json::json!(topic);
+            shorthand.push(EXT_TOPIC);
+        }
+        if let Some(origin) = self.origin() {
+            json["origin"] = serde_json::json!(origin);
+            shorthand.push(EXT_ORIGIN);
+        }
+        if let Some(id) = self.id() {
+            json["id"] = serde_json::json!(id);
+            shorthand.push(EXT_PACKET_ID);
+        }
+        if let Some(trace) = self.trace_context() {
+            json["trace"] = serde_json::json!(trace);
+            shorthand.push(EXT_TRACE_CONTEXT);
+        }
+        let rest: Vec<_> = self
+            .extensions
+            .iter()
+            .filter(|ext| !shorthand.contains(&ext.kind))
+            .collect();
+        if !rest.is_empty() {
+            json["extensions"] = serde_json::json!(rest);
+        }
+        json
+    }
+
+    /// Parse the JSON representation produced by [`Packet::to_json`].
+    ///
+    /// Only `payload` is required; `length` is ignored and recomputed and a
+    /// missing `version` means version 1. String `topic` and `id` fields and
+    /// `origin` and `trace` objects are accepted as shorthand for the topic,
+    /// packet id, origin and trace context extensions; they replace an entry
+    /// of the same kind in `extensions`. A version 1 packet with any extension
+    /// is promoted to version 2.
+    pub fn from_json(json: &serde_json::Value) -> Result<Self, ProtocolError> {
+        Self::from_json_versioned(json, PROTOCOL_VERSION)
+    }
+
+    /// Parse a JSON packet, taking `default_version` when it has no `version`.
+    ///
+    /// Sessions pass their negotiated version, so JSON senders may leave it out.
+    pub fn from_json_versioned(
+        json: &serde_json::Value,
+        default_version: u8,
+    ) -> Result<Self, ProtocolError> {
+        let object = json
+            .as_object()
+            .ok_or_else(|| ProtocolError::InvalidFormat("packet JSON must be an object".into()))?;
+        let payload = object
+            .get("payload")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| ProtocolError::InvalidFormat("missing string field: payload".into()))?;
+
+        let field_u8 = |name: &str, default: u8| -> Result<u8, ProtocolError> {
+            match object.get(name) {
+                None => Ok(default),
+                Some(v) => v
+                    .as_u64()
+                    .and_then(|n| u8::try_from(n).ok())
+                    .ok_or_else(|| {
+                        ProtocolError::InvalidFormat(format!("invalid field: {}", name))
+                    }),
+            }
+        };
+        let urgency = match object.get("urgency").and_then(|v| v.as_str()) {
+            Some(name) => name.parse()?,
+            None => Urgency::Green,
+        };
+
+        let version = field_u8("version", default_version)?;
+        if !matches!(version, PROTOCOL_VERSION | PROTOCOL_VERSION_2) {
+            return Err(ProtocolError::UnsupportedVersion(version));
+        }
+
+        let mut packet = Self::typed(
+            field_u8("type", PACKET_TYPE_MESSAGE)?,
+            urgency,
+            payload.as_bytes().to_vec(),
+        )
+        .with_version(version);
+        packet.header.flags = field_u8("flags", 0)?;
+        if let Some(extensions) = object.get("extensions") {
+            let extensions: Vec<Extension> = serde_json::from_value(extensions.clone())?;
+            for ext in extensions {
+                packet.set_extension(ext.kind, ext.data)?;
+            }
+        }
+        if let Some(topic) = object.get("topic") {
+            let topic = topic
+                .as_str()
+                .ok_or_else(|| ProtocolError::InvalidFormat("invalid field: topic".into()))?;
+            packet = packet.with_topic(topic)?;
+        }
+        if let Some(origin) = object.get("origin") {
+            packet = packet.with_origin(&serde_json::from_value(origin.clone())?)?;
+        }
+        if let Some(id) = object.get("id") {
+            let id = id
+                .as_str()
+                .ok_or_else(|| ProtocolError::InvalidFormat("invalid field: id".into()))?;
+            packet = packet.with_id(id)?;
+        }
+        if let Some(trace) = object.get("trace") {
+            packet = packet.with_trace_context(&serde_json::from_value(trace.clone())?)?;
+        }
+        if !packet.extensions.is_empty() {
+            packet.header.version = PROTOCOL_VERSION_2;
+        }
+
+        Ok(packet)
     }
 }
 
@@ -192,8 +678,17 @@ pub enum ProtocolError {
     #[error("Invalid packet format: {0}")]
     InvalidFormat(String),
 
+    #[error("Unsupported protocol version: {0}")]
+    UnsupportedVersion(u8),
+
     #[error("Serialization error: {0}")]
     Serialization(#[from] serde_json::Error),
+
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Handshake failed: {0}")]
+    Handshake(#[from] HandshakeError),
 }
 
 // ============================================================================
@@ -234,6 +729,11 @@ pub trait StrategyHandler: Send + Sync {
 
     /// Handle GREEN urgency packets - normal priority.
     async fn on_normal(&self, packet: &Packet);
+
+    /// React to the session's link coming up, dropping out or closing.
+    async fn on_link_event(&self, _event: &LinkEvent) {
+        // Default: nothing to do
+    }
 }
 
 /// Protocol API for packet creation and dispatch.
@@ -251,7 +751,25 @@ impl ProtocolApi {
     }
 
     /// Dispatch packet to appropriate strategy handler method.
+    ///
+    /// Batch containers are unpacked and each member dispatched in order.
     pub async fn dispatch<H: StrategyHandler>(&self, packet: &Packet, handler: &H) {
+        if packet.is_batch() {
+            match packet.unbatch() {
+                Ok(packets) => {
+                    for packet in &packets {
+                        self.dispatch_one(packet, handler).await;
+                    }
+                }
+                Err(e) => warn!("Dropping malformed batch packet: {}", e),
+            }
+            return;
+        }
+
+        self.dispatch_one(packet, handler).await;
+    }
+
+    async fn dispatch_one<H: StrategyHandler>(&self, packet: &Packet, handler: &H) {
         match packet.header.urgency {
             Urgency::Red => handler.on_urgent_red(packet).await,
             Urgency::Yellow => handler.on_urgent_yellow(packet).await,
@@ -285,4 +803,167 @@ mod tests {
         assert_eq!(decoded.urgency, Urgency::Yellow);
         assert_eq!(decoded.length, 1024);
     }
+
+    #[test]
+    fn test_v2_roundtrip_with_extensions() {
+        let mut original =
+            Packet::typed(0x42, Urgency::Red, b"LOCK".to_vec()).with_version(PROTOCOL_VERSION_2);
+        original.header.flags = 0x81;
+        original.set_extension(7, vec![1, 2, 3]).unwrap();
+
+        let bytes = original.to_bytes();
+        assert_eq!(bytes.len(), original.wire_len());
+        let decoded = Packet::from_bytes(&bytes).unwrap();
+
+        assert_eq!(decoded.header.version, PROTOCOL_VERSION_2);
+        assert_eq!(decoded.header.packet_type, 0x42);
+        assert_eq!(decoded.header.flags, 0x81);
+        assert_eq!(decoded.extension(7), Some(&[1u8, 2, 3][..]));
+        assert_eq!(decoded.payload_str().unwrap(), "LOCK");
+    }
+
+    #[test]
+    fn test_packet_type_names() {
+        for (packet_type, name) in PACKET_TYPES {
+            assert_eq!(
+                Packet::typed(*packet_type, Urgency::Green, vec![])
+                    .header
+                    .type_name(),
+                *name
+            );
+            assert_eq!(
+                packet_type_from_name(&name.to_uppercase()),
+                Some(*packet_type)
+            );
+        }
+        assert_eq!(
+            Packet::typed(0xEE, Urgency::Green, vec![])
+                .header
+                .type_name(),
+            "unknown"
+        );
+        assert_eq!(packet_type_from_name("unknown"), None);
+    }
+
+    #[test]
+    fn test_json_extension_limits() {
+        let oversized = serde_json::json!({
+            "urgency": "green",
+            "payload": "x",
+            "extensions": [{"kind": 9, "data": vec![0u8; u16::MAX as usize]}],
+        });
+        assert!(Packet::from_json(&oversized).is_err());
+
+        let block = serde_json::json!({
+            "header": Packet::green("x").header,
+            "extensions": [{"kind": 9, "data": vec![0u8; u16::MAX as usize]}],
+            "payload": [],
+        });
+        assert!(serde_json::from_value::<Packet>(block).is_err());
+    }
+
+    #[test]
+    fn test_json_roundtrip() {
+        let original = Packet::yellow("TRACK 7 BEARING 270");
+        let decoded = Packet::from_json(&original.to_json()).unwrap();
+
+        assert_eq!(decoded.header.urgency, Urgency::Yellow);
+        assert_eq!(decoded.header.packet_type, PACKET_TYPE_MESSAGE);
+        assert_eq!(decoded.payload, original.payload);
+    }
+
+    #[test]
+    fn test_unknown_version_rejected() {
+        let mut bytes = Packet::green("x").to_bytes();
+        bytes[0] = (bytes[0] & 0xF0) | 0x0F;
+
+        assert!(matches!(
+            Packet::from_bytes(&bytes),
+            Err(ProtocolError::UnsupportedVersion(15))
+        ));
+
+        let mut json = Packet::green("x").to_json();
+        for version in [0, 3, 15] {
+            json["version"] = version.into();
+            assert!(matches!(
+                Packet::from_json(&json),
+                Err(ProtocolError::UnsupportedVersion(v)) if v == version
+            ));
+        }
+        json["version"] = PROTOCOL_VERSION_2.into();
+        assert_eq!(
+            Packet::from_json(&json).unwrap().header.version,
+            PROTOCOL_VERSION_2
+        );
+    }
+
+    #[test]
+    fn test_json_extensions_written_once() {
+        let packet = Packet::red("LOCK")
+            .with_version(PROTOCOL_VERSION_2)
+            .with_topic("uav-7/track")
+            .unwrap()
+            .with_id("pkt-1")
+            .unwrap();
+        let mut other = packet.clone();
+        other.set_extension(9, b"opaque".to_vec()).unwrap();
+
+        let json = packet.to_json();
+        assert_eq!(json["topic"], "uav-7/track");
+        assert!(json.get("extensions").is_none());
+        let json = other.to_json();
+        assert_eq!(json["extensions"].as_array().unwrap().len(), 1);
+        assert_eq!(Packet::from_json(&json).unwrap().extensions().len(), 3);
+
+        // The shorthand field wins over an extension of the same kind
+        let conflicting = serde_json::json!({
+            "version": 2,
+            "payload": "x",
+            "extensions": [{"kind": EXT_TOPIC, "data": b"uav-8/track"}],
+            "topic": "uav-7/track",
+        });
+        let decoded = Packet::from_json(&conflicting).unwrap();
+        assert_eq!(decoded.topic(), Some("uav-7/track"));
+        assert_eq!(decoded.extensions().len(), 1);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "does not fit a v1 header")]
+    fn test_v1_header_rejects_wide_type() {
+        Packet::typed(16, Urgency::Green, Vec::new()).to_bytes();
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "does not fit a v1 header")]
+    fn test_v1_header_rejects_wide_length() {
+        PacketHeader::new(Urgency::Green, u32::MAX as u64 + 1).to_bytes();
+    }
+
+    #[test]
+    fn test_json_version_defaults_and_promotion() {
+        let json = serde_json::json!({"payload": "x"});
+        assert_eq!(
+            Packet::from_json(&json).unwrap().header.version,
+            PROTOCOL_VERSION
+        );
+        assert_eq!(
+            Packet::from_json_versioned(&json, PROTOCOL_VERSION_2)
+                .unwrap()
+                .header
+                .version,
+            PROTOCOL_VERSION_2
+        );
+
+        // Extensions never ride on a v1 packet
+        let json = serde_json::json!({"version": 1, "payload": "x", "topic": "uav-7/track"});
+        let packet = Packet::from_json(&json).unwrap();
+        assert_eq!(packet.header.version, PROTOCOL_VERSION_2);
+        assert_eq!(packet.topic(), Some("uav-7/track"));
+
+        let v1 = packet.with_version(PROTOCOL_VERSION).to_json();
+        assert_eq!(v1["version"], PROTOCOL_VERSION_2);
+        assert_eq!(v1["topic"], "uav-7/track");
+    }
 }
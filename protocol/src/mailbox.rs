@@ -0,0 +1,232 @@
+//! Store-and-forward mailbox for a peer that is temporarily offline.
+//!
+//! Packets queued while the peer is away are replayed when it returns: RED
+//! first, then YELLOW, then GREEN, oldest first within each urgency. A packet
+//! with an id ([`EXT_PACKET_ID`], or the id of the alert it carries) is queued
+//! at most once. The mailbox is bounded by packet count, bytes and age; when it
+//! is full, the oldest packet of the least urgent kind present makes room, and
+//! a packet less urgent than everything queued is refused instead.
+
+use std::collections::{HashSet, VecDeque};
+use std::time::{Duration, SystemTime};
+
+use crate::{Packet, ProtocolError, Urgency};
+
+/// Extension kind carrying a UTF-8 id the sender assigns to a packet.
+pub const EXT_PACKET_ID: u8 = 3;
+
+impl Packet {
+    /// Tag this packet with an id used for duplicate suppression (v2 extension).
+    pub fn with_id(mut self, id: &str) -> Result<Self, ProtocolError> {
+        if id.is_empty() {
+            return Err(ProtocolError::InvalidFormat(
+                "packet id must not be empty".into(),
+            ));
+        }
+        self.set_extension(EXT_PACKET_ID, id.as_bytes().to_vec())?;
+        Ok(self)
+    }
+
+    /// The id the sender assigned, if any.
+    pub fn id(&self) -> Option<&str> {
+        self.extension(EXT_PACKET_ID)
+            .and_then(|data| std::str::from_utf8(data).ok())
+    }
+}
+
+/// Bounds of a [`Mailbox`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MailboxLimits {
+    pub max_packets: usize,
+    /// Total encoded size of the queued packets.
+    pub max_bytes: usize,
+    /// Packets older than this are dropped instead of replayed.
+    pub max_age: Duration,
+}
+
+#[derive(Debug)]
+struct Queued {
+    at: SystemTime,
+    key: Option<String>,
+    size: usize,
+    packet: Packet,
+}
+
+/// Bounded, priority-ordered queue of packets awaiting an offline peer.
+#[derive(Debug)]
+pub struct Mailbox {
+    limits: MailboxLimits,
+    /// One FIFO per urgency, most urgent first.
+    queues: [VecDeque<Queued>; 3],
+    bytes: usize,
+    keys: HashSet<String>,
+}
+
+fn priority(urgency: Urgency) -> usize {
+    match urgency {
+        Urgency::Red => 0,
+        Urgency::Yellow => 1,
+        Urgency::Green => 2,
+    }
+}
+
+/// Id a packet is deduplicated by.
+fn dedup_key(packet: &Packet) -> Option<String> {
+    packet
+        .id()
+        .map(str::to_string)
+        .or_else(|| packet.origin().map(|origin| origin.id))
+}
+
+impl Mailbox {
+    /// Create an empty mailbox.
+    pub fn new(limits: MailboxLimits) -> Self {
+        Self {
+            limits,
+            queues: Default::default(),
+            bytes: 0,
+            keys: HashSet::new(),
+        }
+    }
+
+    /// Number of queued packets.
+    pub fn len(&self) -> usize {
+        self.queues.iter().map(VecDeque::len).sum()
+    }
+
+    /// Whether nothing is queued.
+    pub fn is_empty(&self) -> bool {
+        self.queues.iter().all(VecDeque::is_empty)
+    }
+
+    /// Encoded size of the queued packets.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Queue a packet that arrived at `at`.
+    ///
+    /// Returns `false` if it is a duplicate of a queued packet or cannot fit.
+    pub fn push(&mut self, packet: Packet, at: SystemTime) -> bool {
+        self.expire(at);
+        let key = dedup_key(&packet);
+        if key.as_ref().is_some_and(|key| self.keys.contains(key)) {
+            return false;
+        }
+        let size = packet.wire_len();
+        if self.limits.max_packets == 0 || size > self.limits.max_bytes {
+            return false;
+        }
+
+        let rank = priority(packet.header.urgency);
+        while self.len() >= self.limits.max_packets || self.bytes + size > self.limits.max_bytes {
+            // Make room from the least urgent queue, but never for a less urgent packet
+            let Some(victim) = (0..self.queues.len())
+                .rev()
+                .find(|&q| !self.queues[q].is_empty())
+            else {
+                break;
+            };
+            if victim < rank {
+                return false;
+            }
+            self.pop(victim);
+        }
+
+        if let Some(key) = &key {
+            self.keys.insert(key.clone());
+        }
+        self.bytes += size;
+        self.queues[rank].push_back(Queued {
+            at,
+            key,
+            size,
+            packet,
+        });
+        true
+    }
+
+    /// Take every packet still fresh at `now`, in replay order.
+    pub fn drain(&mut self, now: SystemTime) -> Vec<Packet> {
+        self.expire(now);
+        self.bytes = 0;
+        self.keys.clear();
+        self.queues
+            .iter_mut()
+            .flat_map(|queue| queue.drain(..).map(|queued| queued.packet))
+            .collect()
+    }
+
+    /// Queued packets with the time each arrived, in replay order.
+    ///
+    /// Pushing them into an empty mailbox in this order recreates it.
+    pub fn entries(&self) -> impl Iterator<Item = (SystemTime, &Packet)> {
+        self.queues
+            .iter()
+            .flatten()
+            .map(|queued| (queued.at, &queued.packet))
+    }
+
+    fn pop(&mut self, q: usize) {
+        if let Some(queued) = self.queues[q].pop_front() {
+            self.bytes -= queued.size;
+            if let Some(key) = &queued.key {
+                self.keys.remove(key);
+            }
+        }
+    }
+
+    fn expire(&mut self, now: SystemTime) {
+        for q in 0..self.queues.len() {
+            while self.queues[q].front().is_some_and(|queued| {
+                now.duration_since(queued.at).unwrap_or_default() > self.limits.max_age
+            }) {
+                self.pop(q);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mailbox_order_dedup_and_limits() {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000);
+        let green = Packet::green("g1");
+        let limits = MailboxLimits {
+            max_packets: 3,
+            max_bytes: 1024,
+            max_age: Duration::from_secs(60),
+        };
+        let mut mailbox = Mailbox::new(limits);
+
+        assert!(mailbox.push(green.clone(), start));
+        assert!(mailbox.push(Packet::new("y1", Urgency::Yellow), start));
+        let red = Packet::red("r1")
+            .with_version(crate::PROTOCOL_VERSION_2)
+            .with_id("alert-1")
+            .unwrap();
+        assert!(mailbox.push(red.clone(), start));
+        // Same id again is suppressed
+        assert!(!mailbox.push(red, start));
+        // Full: a RED evicts the oldest GREEN, another GREEN is refused
+        assert!(mailbox.push(Packet::red("r2"), start));
+        assert!(!mailbox.push(Packet::green("g2"), start));
+        assert_eq!(mailbox.len(), 3);
+
+        let replay: Vec<_> = mailbox
+            .drain(start)
+            .iter()
+            .map(|p| p.payload_string_lossy())
+            .collect();
+        assert_eq!(replay, ["r1", "r2", "y1"]);
+        assert!(mailbox.is_empty());
+        assert_eq!(mailbox.bytes(), 0);
+
+        // Expired packets are not replayed
+        assert!(mailbox.push(green, start));
+        assert!(mailbox.drain(start + Duration::from_secs(61)).is_empty());
+    }
+}
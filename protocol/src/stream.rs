@@ -0,0 +1,102 @@
+//! Drone coordinate stream updates and in-band error notices.
+//!
+//! While a target is being tracked the server pushes TRACK packets to every
+//! session. Problems the client should know about but that do not end the
+//! session, such as updates lost to a slow reader, travel as ERROR packets.
+//! Both carry JSON payloads.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Packet, ProtocolError, Urgency, PACKET_TYPE_ERROR, PACKET_TYPE_TRACK};
+
+/// Error code: the session fell behind the drone stream and updates were dropped.
+pub const ERROR_STREAM_LAGGED: &str = "stream_lagged";
+
+/// Error code: a SUBSCRIBE or UNSUBSCRIBE packet held an invalid topic filter.
+pub const ERROR_INVALID_TOPIC: &str = "invalid_topic";
+
+/// Error code: the session is not allowed to make this request.
+pub const ERROR_FORBIDDEN: &str = "forbidden";
+
+/// Error code: the packet carries a protocol version other than the session's.
+pub const ERROR_WRONG_VERSION: &str = "wrong_version";
+
+/// One position fix from the drone coordinate stream.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TrackUpdate {
+    /// Position in the stream, starting at 0 for each tracking run.
+    pub seq: u64,
+    pub lat: f64,
+    pub lon: f64,
+}
+
+/// Error reported to the peer without closing the session.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErrorNotice {
+    /// Machine-readable error code, e.g. [`ERROR_STREAM_LAGGED`].
+    pub code: String,
+    pub message: String,
+}
+
+impl ErrorNotice {
+    /// Create a notice with the given code and message.
+    pub fn new(code: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            code: code.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl Packet {
+    /// Build a TRACK packet. Track updates are time-sensitive, so YELLOW.
+    pub fn track(update: &TrackUpdate) -> Self {
+        Self::typed(
+            PACKET_TYPE_TRACK,
+            Urgency::Yellow,
+            serde_json::to_vec(update).unwrap_or_default(),
+        )
+    }
+
+    /// Build an ERROR packet.
+    pub fn error_notice(notice: &ErrorNotice) -> Self {
+        Self::typed(
+            PACKET_TYPE_ERROR,
+            Urgency::Yellow,
+            serde_json::to_vec(notice).unwrap_or_default(),
+        )
+    }
+
+    /// Parse a TRACK packet.
+    pub fn to_track(&self) -> Result<TrackUpdate, ProtocolError> {
+        self.expect_type(PACKET_TYPE_TRACK)?;
+        Ok(serde_json::from_slice(&self.payload)?)
+    }
+
+    /// Parse an ERROR packet.
+    pub fn to_error_notice(&self) -> Result<ErrorNotice, ProtocolError> {
+        self.expect_type(PACKET_TYPE_ERROR)?;
+        Ok(serde_json::from_slice(&self.payload)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_track_and_error_roundtrip() {
+        let update = TrackUpdate {
+            seq: 3,
+            lat: 34.2348,
+            lon: 69.124,
+        };
+        let packet = Packet::from_bytes(&Packet::track(&update).to_bytes()).unwrap();
+        assert_eq!(packet.to_track().unwrap(), update);
+        assert!(packet.to_error_notice().is_err());
+
+        let notice = ErrorNotice::new(ERROR_STREAM_LAGGED, "missed 4 updates");
+        let packet = Packet::from_json(&Packet::error_notice(&notice).to_json()).unwrap();
+        assert_eq!(packet.to_error_notice().unwrap(), notice);
+    }
+}
@@ -0,0 +1,222 @@
+//! Topics, topic filters and SUBSCRIBE / UNSUBSCRIBE packets.
+//!
+//! A packet is published to a topic by attaching a [`EXT_TOPIC`] extension,
+//! conventionally `<drone>/<track>`. Routing matches filters against the
+//! packet's *routing key*, which prefixes the topic with the lowercase urgency:
+//! `red/uav-7/t42`. Filters use MQTT-style wildcards, so `red/#` follows every
+//! RED packet, `+/uav-7/#` one drone and `+/+/t42` one track.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Packet, ProtocolError, Urgency, PACKET_TYPE_SUBSCRIBE, PACKET_TYPE_UNSUBSCRIBE};
+
+/// Extension kind carrying the UTF-8 topic a packet is published to.
+pub const EXT_TOPIC: u8 = 1;
+
+/// Matches exactly one topic level.
+const WILDCARD_ONE: &str = "+";
+
+/// Matches any number of trailing levels, including none.
+const WILDCARD_MANY: &str = "#";
+
+/// Validated topic filter, e.g. `+/uav-7/#`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TopicFilter(String);
+
+impl TopicFilter {
+    /// Parse and validate a filter.
+    ///
+    /// Wildcards must occupy a whole level, and `#` may only be the last level.
+    pub fn parse(filter: &str) -> Result<Self, ProtocolError> {
+        let invalid = |reason: &str| {
+            ProtocolError::InvalidFormat(format!("invalid topic filter {:?}: {}", filter, reason))
+        };
+        if filter.is_empty() {
+            return Err(invalid("empty"));
+        }
+
+        let levels: Vec<&str> = filter.split('/').collect();
+        for (i, level) in levels.iter().enumerate() {
+            if *level == WILDCARD_MANY && i != levels.len() - 1 {
+                return Err(invalid("'#' must be the last level"));
+            }
+            if *level != WILDCARD_ONE
+                && *level != WILDCARD_MANY
+                && (level.contains('+') || level.contains('#'))
+            {
+                return Err(invalid("wildcards must occupy a whole level"));
+            }
+        }
+
+        Ok(Self(filter.to_string()))
+    }
+
+    /// The filter as written.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Whether a routing key matches this filter.
+    pub fn matches(&self, key: &str) -> bool {
+        let mut levels = key.split('/');
+        for pattern in self.0.split('/') {
+            if pattern == WILDCARD_MANY {
+                return true;
+            }
+            match levels.next() {
+                Some(level) if pattern == WILDCARD_ONE || pattern == level => {}
+                _ => return false,
+            }
+        }
+        levels.next().is_none()
+    }
+
+    /// Whether every routing key matched by `other` is also matched by this filter.
+    pub fn covers(&self, other: &TopicFilter) -> bool {
+        let mut levels = other.0.split('/');
+        for pattern in self.0.split('/') {
+            if pattern == WILDCARD_MANY {
+                return true;
+            }
+            match levels.next() {
+                Some(level) if level == WILDCARD_MANY => return false,
+                Some(level) if pattern == WILDCARD_ONE || pattern == level => {}
+                _ => return false,
+            }
+        }
+        levels.next().is_none()
+    }
+}
+
+impl std::fmt::Display for TopicFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Payload of SUBSCRIBE and UNSUBSCRIBE packets.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct TopicList {
+    topics: Vec<String>,
+}
+
+/// Check that a topic can be published to: non-empty and free of wildcards.
+fn validate_topic(topic: &str) -> Result<(), ProtocolError> {
+    if topic.is_empty() || topic.contains('+') || topic.contains('#') {
+        return Err(ProtocolError::InvalidFormat(format!(
+            "invalid topic {:?}: must be non-empty and free of wildcards",
+            topic
+        )));
+    }
+    Ok(())
+}
+
+impl Packet {
+    /// Publish this packet to a topic (v2 extension).
+    pub fn with_topic(mut self, topic: &str) -> Result<Self, ProtocolError> {
+        validate_topic(topic)?;
+        self.set_extension(EXT_TOPIC, topic.as_bytes().to_vec())?;
+        Ok(self)
+    }
+
+    /// The topic this packet is published to, if any.
+    pub fn topic(&self) -> Option<&str> {
+        self.extension(EXT_TOPIC)
+            .and_then(|data| std::str::from_utf8(data).ok())
+    }
+
+    /// Key that topic filters are matched against: `<urgency>/<topic>`.
+    pub fn routing_key(&self) -> Option<String> {
+        let urgency = match self.header.urgency {
+            Urgency::Green => "green",
+            Urgency::Yellow => "yellow",
+            Urgency::Red => "red",
+        };
+        self.topic().map(|topic| format!("{}/{}", urgency, topic))
+    }
+
+    /// Build a SUBSCRIBE packet for the given filters.
+    pub fn subscribe<S: AsRef<str>>(filters: &[S]) -> Self {
+        Self::topic_list(PACKET_TYPE_SUBSCRIBE, filters)
+    }
+
+    /// Build an UNSUBSCRIBE packet for the given filters.
+    pub fn unsubscribe<S: AsRef<str>>(filters: &[S]) -> Self {
+        Self::topic_list(PACKET_TYPE_UNSUBSCRIBE, filters)
+    }
+
+    /// Parse the filters carried by a SUBSCRIBE or UNSUBSCRIBE packet.
+    pub fn to_topic_filters(&self) -> Result<Vec<TopicFilter>, ProtocolError> {
+        if self.header.packet_type != PACKET_TYPE_SUBSCRIBE {
+            self.expect_type(PACKET_TYPE_UNSUBSCRIBE)?;
+        }
+        let list: TopicList = serde_json::from_slice(&self.payload)?;
+        list.topics.iter().map(|t| TopicFilter::parse(t)).collect()
+    }
+
+    fn topic_list<S: AsRef<str>>(packet_type: u8, filters: &[S]) -> Self {
+        let list = TopicList {
+            topics: filters.iter().map(|f| f.as_ref().to_string()).collect(),
+        };
+        Self::typed(
+            packet_type,
+            Urgency::Green,
+            serde_json::to_vec(&list).unwrap_or_default(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_wildcards() {
+        let drone = TopicFilter::parse("+/uav-7/#").unwrap();
+        assert!(drone.matches("red/uav-7/t42"));
+        assert!(drone.matches("green/uav-7"));
+        assert!(!drone.matches("red/uav-8/t42"));
+
+        let track = TopicFilter::parse("+/+/t42").unwrap();
+        assert!(track.matches("yellow/uav-7/t42"));
+        assert!(!track.matches("yellow/uav-7/t42/extra"));
+
+        assert!(TopicFilter::parse("red/#/t42").is_err());
+        assert!(TopicFilter::parse("red/uav+").is_err());
+    }
+
+    #[test]
+    fn test_filter_covers() {
+        let filter = |f: &str| TopicFilter::parse(f).unwrap();
+        let drone = filter("+/uav-7/#");
+        assert!(drone.covers(&filter("red/uav-7/t42")));
+        assert!(drone.covers(&filter("+/uav-7/+")));
+        assert!(drone.covers(&filter("+/uav-7/#")));
+        assert!(drone.covers(&filter("+/uav-7")));
+        assert!(!drone.covers(&filter("+/+/t42")));
+        assert!(!drone.covers(&filter("red/#")));
+        assert!(!drone.covers(&filter("#")));
+
+        let track = filter("+/uav-7/+");
+        assert!(track.covers(&filter("green/uav-7/t1")));
+        assert!(!track.covers(&filter("green/uav-7/#")));
+        assert!(!track.covers(&filter("green/uav-7")));
+        assert!(!filter("red/uav-7/t1").covers(&filter("red/uav-7/+")));
+    }
+
+    #[test]
+    fn test_topic_and_subscribe_roundtrip() {
+        let packet = Packet::red("lock").with_version(crate::PROTOCOL_VERSION_2);
+        let packet = packet.with_topic("uav-7/t42").unwrap();
+        let decoded = Packet::from_bytes(&packet.to_bytes()).unwrap();
+        assert_eq!(decoded.routing_key().as_deref(), Some("red/uav-7/t42"));
+        assert!(Packet::green("x").with_topic("uav-7/#").is_err());
+
+        let subscribe = Packet::subscribe(&["red/#", "+/uav-7/#"]);
+        let filters = Packet::from_bytes(&subscribe.to_bytes())
+            .unwrap()
+            .to_topic_filters()
+            .unwrap();
+        assert_eq!(filters[1].as_str(), "+/uav-7/#");
+    }
+}
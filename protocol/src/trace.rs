@@ -0,0 +1,177 @@
+//! W3C trace context carried with a packet, so one trace can follow a packet
+//! from the client that raised it through relays and servers to the handler
+//! that acts on it.
+//!
+//! The [`EXT_TRACE_CONTEXT`] extension holds the `traceparent` value and, when
+//! present, a newline and the `tracestate` value, exactly as they would appear
+//! in HTTP headers. The crate only validates and carries them; creating spans
+//! and propagating their context is up to the binaries.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Packet, ProtocolError};
+
+/// Extension kind carrying the [`TraceContext`] of the span that sent a packet.
+pub const EXT_TRACE_CONTEXT: u8 = 4;
+
+/// Longest `tracestate` value accepted, as the W3C recommendation allows.
+const MAX_TRACESTATE_LEN: usize = 512;
+
+/// `traceparent` and `tracestate` of the span a packet was sent from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TraceContext {
+    /// `version-traceid-parentid-flags`, e.g.
+    /// `00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01`.
+    pub traceparent: String,
+    /// Vendor-specific entries, passed along untouched.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tracestate: Option<String>,
+}
+
+impl TraceContext {
+    /// Validate a `traceparent` and optional `tracestate`.
+    pub fn new(traceparent: &str, tracestate: Option<&str>) -> Result<Self, ProtocolError> {
+        let context = Self {
+            traceparent: traceparent.to_string(),
+            tracestate: tracestate
+                .filter(|state| !state.is_empty())
+                .map(str::to_string),
+        };
+        context.validate()?;
+        Ok(context)
+    }
+
+    fn validate(&self) -> Result<(), ProtocolError> {
+        let invalid =
+            |reason: &str| ProtocolError::InvalidFormat(format!("traceparent {}", reason));
+        let fields: Vec<&str> = self.traceparent.split('-').collect();
+        let [version, trace_id, parent_id, flags, ..] = fields[..] else {
+            return Err(invalid("needs version, trace id, parent id and flags"));
+        };
+        let is_hex = |field: &str, len: usize| {
+            field.len() == len
+                && field
+                    .bytes()
+                    .all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+        };
+        // Later versions may append fields; version 00 has exactly four
+        if !is_hex(version, 2) || version == "ff" || (version == "00" && fields.len() != 4) {
+            return Err(invalid("has an unsupported version"));
+        }
+        if !is_hex(trace_id, 32) || trace_id.bytes().all(|b| b == b'0') {
+            return Err(invalid("has an invalid trace id"));
+        }
+        if !is_hex(parent_id, 16) || parent_id.bytes().all(|b| b == b'0') {
+            return Err(invalid("has an invalid parent id"));
+        }
+        if !is_hex(flags, 2) {
+            return Err(invalid("has invalid flags"));
+        }
+
+        if let Some(state) = &self.tracestate {
+            if state.len() > MAX_TRACESTATE_LEN
+                || !state.bytes().all(|b| (b' '..=b'~').contains(&b))
+            {
+                return Err(ProtocolError::InvalidFormat("invalid tracestate".into()));
+            }
+        }
+        Ok(())
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        match &self.tracestate {
+            Some(state) => format!("{}\n{}", self.traceparent, state).into_bytes(),
+            None => self.traceparent.clone().into_bytes(),
+        }
+    }
+
+    fn from_bytes(data: &[u8]) -> Option<Self> {
+        let text = std::str::from_utf8(data).ok()?;
+        let (traceparent, tracestate) = match text.split_once('\n') {
+            Some((traceparent, tracestate)) => (traceparent, Some(tracestate)),
+            None => (text, None),
+        };
+        Self::new(traceparent, tracestate).ok()
+    }
+}
+
+impl Packet {
+    /// Carry the trace context of the span sending this packet (v2 extension).
+    pub fn with_trace_context(mut self, context: &TraceContext) -> Result<Self, ProtocolError> {
+        self.set_trace_context(context)?;
+        Ok(self)
+    }
+
+    /// Set the trace context, replacing the one the packet arrived with.
+    pub fn set_trace_context(&mut self, context: &TraceContext) -> Result<(), ProtocolError> {
+        context.validate()?;
+        self.set_extension(EXT_TRACE_CONTEXT, context.to_bytes())
+    }
+
+    /// The trace context the packet was sent with; malformed contexts are ignored.
+    pub fn trace_context(&self) -> Option<TraceContext> {
+        self.extension(EXT_TRACE_CONTEXT)
+            .and_then(TraceContext::from_bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Urgency, PROTOCOL_VERSION_2};
+
+    const TRACEPARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    #[test]
+    fn test_trace_context_roundtrip() {
+        let context = TraceContext::new(TRACEPARENT, Some("vendor=abc,other=1")).unwrap();
+        let packet = Packet::new("TARGET LOCKED", Urgency::Red)
+            .with_version(PROTOCOL_VERSION_2)
+            .with_trace_context(&context)
+            .unwrap();
+
+        let decoded = Packet::from_bytes(&packet.to_bytes()).unwrap();
+        assert_eq!(decoded.trace_context(), Some(context.clone()));
+
+        let json = packet.to_json();
+        assert_eq!(json["trace"]["traceparent"], TRACEPARENT);
+        let decoded = Packet::from_json(&json).unwrap();
+        assert_eq!(decoded.trace_context(), Some(context));
+
+        // Version 1 has no extensions, so the context is dropped
+        let v1 =
+            Packet::from_bytes(&packet.with_version(crate::PROTOCOL_VERSION).to_bytes()).unwrap();
+        assert_eq!(v1.trace_context(), None);
+    }
+
+    #[test]
+    fn test_trace_context_validation() {
+        assert!(TraceContext::new(TRACEPARENT, None).is_ok());
+        // Unknown later versions may carry extra fields
+        assert!(TraceContext::new(&format!("01{}-extra", &TRACEPARENT[2..]), None).is_ok());
+
+        for traceparent in [
+            "",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+        ] {
+            assert!(
+                TraceContext::new(traceparent, None).is_err(),
+                "{}",
+                traceparent
+            );
+        }
+        assert!(TraceContext::new(TRACEPARENT, Some("a=1\nb=2")).is_err());
+
+        // A malformed extension reads as no context at all
+        let mut packet = Packet::green("x").with_version(PROTOCOL_VERSION_2);
+        packet
+            .set_extension(EXT_TRACE_CONTEXT, b"garbage".to_vec())
+            .unwrap();
+        assert_eq!(packet.trace_context(), None);
+    }
+}
@@ -2,10 +2,133 @@
 //!
 //! Provides the `AddrConfig` parameter object following the Open-Closed Principle,
 //! allowing future parameter extension without disrupting existing API consumers.
+//!
+//! The client and server share their WebSocket framing glue through the `wire`
+//! feature and their OpenTelemetry setup through the `telemetry` feature.
+
+#[cfg(feature = "telemetry")]
+pub mod telemetry;
+#[cfg(feature = "wire")]
+pub mod wire;
 
 use serde::{Deserialize, Serialize};
 use std::env;
+use std::ops::RangeInclusive;
 use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// An environment variable that is set but cannot be parsed.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("invalid {key}={value:?}: {reason}")]
+pub struct EnvError {
+    pub key: String,
+    pub value: String,
+    pub reason: String,
+}
+
+/// Value of an environment variable, treating an empty one as unset.
+fn env_value(key: &str) -> Option<String> {
+    env::var(key).ok().filter(|value| !value.trim().is_empty())
+}
+
+/// Read and parse an environment variable, falling back to `default` when unset.
+///
+/// A value that does not parse is an error rather than the default, so a typo
+/// cannot quietly switch a setting back to its default.
+fn env_parse<T>(key: &str, default: T) -> Result<T, EnvError>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    let Some(value) = env_value(key) else {
+        return Ok(default);
+    };
+    value.trim().parse().map_err(|e: T::Err| EnvError {
+        key: key.to_string(),
+        value,
+        reason: e.to_string(),
+    })
+}
+
+/// Like [`env_parse`], but a value outside `range` is an error too.
+fn env_parse_in<T>(key: &str, default: T, range: RangeInclusive<T>) -> Result<T, EnvError>
+where
+    T: FromStr + PartialOrd + std::fmt::Display,
+    T::Err: std::fmt::Display,
+{
+    let value = env_parse(key, default)?;
+    if !range.contains(&value) {
+        return Err(EnvError {
+            key: key.to_string(),
+            value: value.to_string(),
+            reason: format!("expected {} to {}", range.start(), range.end()),
+        });
+    }
+    Ok(value)
+}
+
+/// Read a boolean environment variable: `true`/`false`, `1`/`0`, `yes`/`no` or
+/// `on`/`off`, in any case; unset means `default`.
+fn env_flag(key: &str, default: bool) -> Result<bool, EnvError> {
+    let Some(value) = env_value(key) else {
+        return Ok(default);
+    };
+    match value.trim().to_ascii_lowercase().as_str() {
+        "true" | "1" | "yes" | "on" => Ok(true),
+        "false" | "0" | "no" | "off" => Ok(false),
+        _ => Err(EnvError {
+            key: key.to_string(),
+            value,
+            reason: "expected true or false".to_string(),
+        }),
+    }
+}
+
+/// Split a comma-separated environment variable into its non-empty, trimmed items.
+fn env_list(key: &str) -> Vec<String> {
+    env::var(key)
+        .map(|list| {
+            list.split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Parse every item of a comma-separated environment variable; `None` when unset.
+fn env_parse_list<T>(key: &str) -> Result<Option<Vec<T>>, EnvError>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    if env_value(key).is_none() {
+        return Ok(None);
+    }
+    env_list(key)
+        .into_iter()
+        .map(|item| {
+            item.parse().map_err(|e: T::Err| EnvError {
+                key: key.to_string(),
+                value: item,
+                reason: e.to_string(),
+            })
+        })
+        .collect::<Result<_, _>>()
+        .map(Some)
+}
+
+/// This server's name among its peers: `NODE_ID`, else `HOSTNAME`, else `ws-server`.
+pub fn node_id_from_env() -> String {
+    ["NODE_ID", "HOSTNAME"]
+        .iter()
+        .filter_map(|key| env::var(key).ok())
+        .map(|value| value.trim().to_string())
+        .find(|value| !value.is_empty())
+        .unwrap_or_else(|| "ws-server".to_string())
+}
 
 /// TLS certificate paths configuration.
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -13,22 +136,58 @@ pub struct TlsConfig {
     pub cert_file: PathBuf,
     pub key_file: PathBuf,
     pub ca_file: PathBuf,
+    /// Server: CA bundle that client certificates must chain to. Setting it turns on mTLS.
+    #[serde(default)]
+    pub client_ca_file: Option<PathBuf>,
+    /// Server: PEM certificate revocation lists checked against client certificates.
+    #[serde(default)]
+    pub crl_files: Vec<PathBuf>,
+    /// Client: certificate presented to servers that require mTLS.
+    #[serde(default)]
+    pub client_cert_file: Option<PathBuf>,
+    /// Client: private key for `client_cert_file`.
+    #[serde(default)]
+    pub client_key_file: Option<PathBuf>,
+    /// Server: how often to check `cert_file` / `key_file` for changes; `None` reloads on SIGHUP only.
+    #[serde(default)]
+    pub reload_interval: Option<Duration>,
+}
+
+/// Read an optional path from the environment, treating an empty value as unset.
+fn env_path(key: &str) -> Option<PathBuf> {
+    env_value(key).map(PathBuf::from)
 }
 
 impl TlsConfig {
     /// Create TLS config from environment-derived paths.
     ///
     /// Uses `CERT_PATH` environment variable if set, otherwise falls back to `./certificates`.
-    pub fn from_env() -> Self {
-        let base = env::var("CERT_PATH")
-            .map(PathBuf::from)
-            .unwrap_or_else(|_| PathBuf::from("certificates"));
+    /// Mutual TLS is configured with `TLS_CLIENT_CA` and `TLS_CRL_FILES` (comma-separated)
+    /// on the server, and `TLS_CLIENT_CERT` / `TLS_CLIENT_KEY` on the client.
+    /// The server polls its certificate every `TLS_RELOAD_MS` (default 30s, `0` disables).
+    pub fn from_env() -> Result<Self, EnvError> {
+        Ok(Self {
+            client_ca_file: env_path("TLS_CLIENT_CA"),
+            crl_files: env_list("TLS_CRL_FILES")
+                .into_iter()
+                .map(PathBuf::from)
+                .collect(),
+            client_cert_file: env_path("TLS_CLIENT_CERT"),
+            client_key_file: env_path("TLS_CLIENT_KEY"),
+            reload_interval: Some(Duration::from_millis(env_parse("TLS_RELOAD_MS", 30_000)?))
+                .filter(|interval| !interval.is_zero()),
+            ..Self::from_cert_path()
+        })
+    }
 
-        Self {
-            cert_file: base.join("server.pem"),
-            key_file: base.join("server-key.pem"),
-            ca_file: base.join("server.pem"),
-        }
+    /// Certificate paths under `CERT_PATH`, else `./certificates`, with nothing else configured.
+    pub fn from_cert_path() -> Self {
+        let base = env_path("CERT_PATH").unwrap_or_else(|| PathBuf::from("certificates"));
+        Self::new(
+            base.join("server.pem"),
+            base.join("server-key.pem"),
+            base.join("server.pem"),
+        )
     }
 
     /// Create TLS config from explicit paths.
@@ -37,8 +196,27 @@ impl TlsConfig {
             cert_file,
             key_file,
             ca_file,
+            client_ca_file: None,
+            crl_files: Vec::new(),
+            client_cert_file: None,
+            client_key_file: None,
+            reload_interval: None,
         }
     }
+
+    /// Server: require client certificates issued by `client_ca_file`, minus revoked ones.
+    pub fn with_client_auth(mut self, client_ca_file: PathBuf, crl_files: Vec<PathBuf>) -> Self {
+        self.client_ca_file = Some(client_ca_file);
+        self.crl_files = crl_files;
+        self
+    }
+
+    /// Client: present this certificate when the server asks for one.
+    pub fn with_client_cert(mut self, cert_file: PathBuf, key_file: PathBuf) -> Self {
+        self.client_cert_file = Some(cert_file);
+        self.client_key_file = Some(key_file);
+        self
+    }
 }
 
 /// Protocol hint for connection type.
@@ -78,6 +256,10 @@ pub struct AddrConfig {
     pub endpoint: String,
     pub protocol_hint: ProtocolHint,
     pub use_tls: bool,
+    /// WebSocket subprotocols to offer (client) or accept (server), in order of
+    /// preference. Empty means every subprotocol the binary supports.
+    #[serde(default)]
+    pub subprotocols: Vec<String>,
 }
 
 impl AddrConfig {
@@ -90,6 +272,7 @@ impl AddrConfig {
             endpoint: "/".to_string(),
             protocol_hint: ProtocolHint::Wss,
             use_tls: true,
+            subprotocols: Vec::new(),
         }
     }
 
@@ -98,7 +281,29 @@ impl AddrConfig {
     /// Uses `CERT_PATH` environment variable for certificate paths,
     /// falling back to `./certificates` if not set.
     pub fn from_env_defaults(host: impl Into<String>, port: u16) -> Self {
-        Self::new(host, port, TlsConfig::from_env())
+        Self::new(host, port, TlsConfig::from_cert_path())
+    }
+
+    /// Create configuration from the environment, with `host` and `port` as defaults.
+    ///
+    /// TLS settings come from [`TlsConfig::from_env`]. `WS_HOST` / `WS_PORT`
+    /// override the given host and port, `WS_ENDPOINT` the path (default `/`).
+    /// `WS_SUBPROTOCOLS` (comma-separated) restricts the WebSocket subprotocols.
+    /// `USE_TLS=false` switches to plaintext `ws://`.
+    pub fn from_env(host: impl Into<String>, port: u16) -> Result<Self, EnvError> {
+        let host = env_value("WS_HOST").unwrap_or_else(|| host.into());
+        let port = env_parse("WS_PORT", port)?;
+        let subprotocols = env_list("WS_SUBPROTOCOLS");
+        let mut config =
+            Self::new(host, port, TlsConfig::from_env()?).with_subprotocols(subprotocols);
+        if let Some(endpoint) = env_value("WS_ENDPOINT") {
+            config = config.with_endpoint(endpoint);
+        }
+        Ok(if env_flag("USE_TLS", true)? {
+            config
+        } else {
+            config.without_tls()
+        })
     }
 
     /// Returns the full WebSocket URL.
@@ -123,6 +328,12 @@ impl AddrConfig {
         self
     }
 
+    /// Builder method to set the WebSocket subprotocols, in order of preference.
+    pub fn with_subprotocols(mut self, subprotocols: Vec<String>) -> Self {
+        self.subprotocols = subprotocols;
+        self
+    }
+
     /// Builder method to disable TLS.
     pub fn without_tls(mut self) -> Self {
         self.use_tls = false;
@@ -131,6 +342,545 @@ impl AddrConfig {
     }
 }
 
+/// Send-side packet batching configuration.
+///
+/// GREEN packets produced within `window` of each other are coalesced into a
+/// single batch frame, up to `max_packets` packets or `max_bytes` of wire data.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchConfig {
+    pub window: Duration,
+    pub max_packets: usize,
+    pub max_bytes: usize,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_millis(10),
+            max_packets: 64,
+            max_bytes: 64 * 1024,
+        }
+    }
+}
+
+impl BatchConfig {
+    /// Create batching config from environment variables.
+    ///
+    /// Reads `BATCH_WINDOW_MS` (0 disables batching), `BATCH_MAX_PACKETS` and `BATCH_MAX_BYTES`.
+    pub fn from_env() -> Result<Self, EnvError> {
+        let defaults = Self::default();
+        Ok(Self {
+            window: Duration::from_millis(env_parse(
+                "BATCH_WINDOW_MS",
+                defaults.window.as_millis() as u64,
+            )?),
+            max_packets: env_parse("BATCH_MAX_PACKETS", defaults.max_packets)?,
+            max_bytes: env_parse("BATCH_MAX_BYTES", defaults.max_bytes)?,
+        })
+    }
+
+    /// Configuration that sends every packet in its own frame.
+    pub fn disabled() -> Self {
+        Self {
+            window: Duration::ZERO,
+            ..Self::default()
+        }
+    }
+}
+
+/// Session handshake configuration.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HandshakeConfig {
+    /// How long to wait for the peer's HELLO or HELLO-ACK.
+    pub timeout: Duration,
+    /// Largest packet this side will accept, in bytes.
+    pub max_packet_size: u32,
+    /// Protocol versions to offer or accept; `None` means every supported version.
+    pub versions: Option<Vec<u8>>,
+}
+
+impl Default for HandshakeConfig {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(10),
+            max_packet_size: 1024 * 1024,
+            versions: None,
+        }
+    }
+}
+
+impl HandshakeConfig {
+    /// Create handshake config from `HANDSHAKE_TIMEOUT_MS`, `MAX_PACKET_SIZE` and
+    /// `PROTOCOL_VERSIONS` (comma-separated, e.g. `1,2`).
+    ///
+    /// Pinning `PROTOCOL_VERSIONS` lets a fleet move between protocol versions gradually.
+    pub fn from_env() -> Result<Self, EnvError> {
+        let defaults = Self::default();
+        Ok(Self {
+            timeout: Duration::from_millis(env_parse(
+                "HANDSHAKE_TIMEOUT_MS",
+                defaults.timeout.as_millis() as u64,
+            )?),
+            max_packet_size: env_parse("MAX_PACKET_SIZE", defaults.max_packet_size)?,
+            versions: env_parse_list("PROTOCOL_VERSIONS")?,
+        })
+    }
+}
+
+/// Urgency classification rules for untyped text messages.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClassifierConfig {
+    /// JSON rules file; `None` leaves every untyped message GREEN.
+    pub rules_file: Option<PathBuf>,
+    /// How often to check the rules file for changes.
+    pub reload_interval: Duration,
+}
+
+impl Default for ClassifierConfig {
+    fn default() -> Self {
+        Self {
+            rules_file: None,
+            reload_interval: Duration::from_secs(5),
+        }
+    }
+}
+
+impl ClassifierConfig {
+    /// Create classifier config from `CLASSIFY_RULES_FILE` and `CLASSIFY_RELOAD_MS`.
+    pub fn from_env() -> Result<Self, EnvError> {
+        let defaults = Self::default();
+        Ok(Self {
+            rules_file: env_path("CLASSIFY_RULES_FILE"),
+            reload_interval: Duration::from_millis(env_parse(
+                "CLASSIFY_RELOAD_MS",
+                defaults.reload_interval.as_millis() as u64,
+            )?),
+        })
+    }
+}
+
+/// Bearer token authentication on the WebSocket upgrade.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AuthConfig {
+    /// Server: JSON key set used to verify tokens; `None` disables token checks.
+    pub keys_file: Option<PathBuf>,
+    /// Server: reject upgrades that carry no token. Tokens that are present are always verified.
+    pub required: bool,
+    /// Server: expected `iss` claim, if any.
+    pub issuer: Option<String>,
+    /// Server: expected `aud` claim, if any.
+    pub audience: Option<String>,
+    /// Server: JSON role policy; `None` lets every session send anything.
+    pub policy_file: Option<PathBuf>,
+    /// Client: token sent in the `Authorization` header.
+    pub token: Option<String>,
+}
+
+impl AuthConfig {
+    /// Create auth config from `AUTH_KEYS_FILE`, `AUTH_REQUIRED`, `AUTH_ISSUER`,
+    /// `AUTH_AUDIENCE`, `AUTHZ_POLICY_FILE` and, for the client, `AUTH_TOKEN`.
+    pub fn from_env() -> Result<Self, EnvError> {
+        Ok(Self {
+            keys_file: env_path("AUTH_KEYS_FILE"),
+            required: env_flag("AUTH_REQUIRED", false)?,
+            issuer: env_value("AUTH_ISSUER"),
+            audience: env_value("AUTH_AUDIENCE"),
+            policy_file: env_path("AUTHZ_POLICY_FILE"),
+            token: env_value("AUTH_TOKEN"),
+        })
+    }
+}
+
+/// Keepalive pings and dead-peer detection.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HeartbeatConfig {
+    /// How often to ping the peer; `None` disables pings and dead-peer detection.
+    pub ping_interval: Option<Duration>,
+    /// How long past a missed ping to wait before dropping a silent peer.
+    pub pong_timeout: Duration,
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        Self {
+            ping_interval: Some(Duration::from_secs(15)),
+            pong_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+impl HeartbeatConfig {
+    /// Create heartbeat config from `PING_INTERVAL_MS` (`0` disables) and `PONG_TIMEOUT_MS`.
+    pub fn from_env() -> Result<Self, EnvError> {
+        let defaults = Self::default();
+        let interval_ms = defaults
+            .ping_interval
+            .map_or(0, |interval| interval.as_millis() as u64);
+        Ok(Self {
+            ping_interval: Some(Duration::from_millis(env_parse(
+                "PING_INTERVAL_MS",
+                interval_ms,
+            )?))
+            .filter(|interval| !interval.is_zero()),
+            pong_timeout: Duration::from_millis(env_parse(
+                "PONG_TIMEOUT_MS",
+                defaults.pong_timeout.as_millis() as u64,
+            )?),
+        })
+    }
+}
+
+/// Rebroadcast of RED / YELLOW alerts to operator sessions.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlertConfig {
+    /// Urgency names to rebroadcast, e.g. `RED`; empty disables fan-out.
+    pub urgencies: Vec<String>,
+    /// Publish alerts to this topic instead of sending them to every authorized session.
+    pub topic: Option<String>,
+    /// Name of this server in alert ids, so ids stay unique across relays.
+    pub node_id: String,
+    /// How long an alert id is remembered to suppress duplicates.
+    pub dedup_window: Duration,
+}
+
+impl Default for AlertConfig {
+    fn default() -> Self {
+        Self {
+            urgencies: Vec::new(),
+            topic: None,
+            node_id: "ws-server".to_string(),
+            dedup_window: Duration::from_secs(60),
+        }
+    }
+}
+
+impl AlertConfig {
+    /// Create alert config from `ALERT_FANOUT` (comma-separated urgencies), `ALERT_TOPIC`,
+    /// the node id (see [`node_id_from_env`]) and `ALERT_DEDUP_MS`.
+    pub fn from_env() -> Result<Self, EnvError> {
+        let defaults = Self::default();
+        Ok(Self {
+            urgencies: env_list("ALERT_FANOUT"),
+            topic: env_value("ALERT_TOPIC"),
+            node_id: node_id_from_env(),
+            dedup_window: Duration::from_millis(env_parse(
+                "ALERT_DEDUP_MS",
+                defaults.dedup_window.as_millis() as u64,
+            )?),
+        })
+    }
+}
+
+/// Append-only audit log of critical packets and authorization decisions.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditConfig {
+    /// Active log file; `None` disables the audit log.
+    pub file: Option<PathBuf>,
+    /// Size at which the active file is rotated; `0` never rotates.
+    pub rotate_bytes: u64,
+    /// Record allowed GREEN packets and `/events` subscriptions too, not only
+    /// RED / YELLOW packets and denials.
+    pub all_decisions: bool,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            file: None,
+            rotate_bytes: 64 * 1024 * 1024,
+            all_decisions: false,
+        }
+    }
+}
+
+impl AuditConfig {
+    /// Create audit config from `AUDIT_LOG_FILE`, `AUDIT_ROTATE_BYTES` and
+    /// `AUDIT_ALL_DECISIONS`.
+    pub fn from_env() -> Result<Self, EnvError> {
+        let defaults = Self::default();
+        Ok(Self {
+            file: env_path("AUDIT_LOG_FILE"),
+            rotate_bytes: env_parse("AUDIT_ROTATE_BYTES", defaults.rotate_bytes)?,
+            all_decisions: env_flag("AUDIT_ALL_DECISIONS", defaults.all_decisions)?,
+        })
+    }
+}
+
+/// Store-and-forward queues for identified clients that are offline.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoreForwardConfig {
+    /// Whether packets for offline identities are queued at all.
+    pub enabled: bool,
+    /// Directory the queues are persisted to; `None` keeps them in memory only.
+    pub dir: Option<PathBuf>,
+    /// Most packets queued per identity.
+    pub max_packets: usize,
+    /// Most bytes queued per identity.
+    pub max_bytes: usize,
+    /// Queued packets older than this are dropped instead of replayed.
+    pub max_age: Duration,
+}
+
+impl Default for StoreForwardConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            dir: None,
+            max_packets: 1000,
+            max_bytes: 1024 * 1024,
+            max_age: Duration::from_secs(300),
+        }
+    }
+}
+
+impl StoreForwardConfig {
+    /// Create store-and-forward config from `STORE_FORWARD`, `STORE_FORWARD_DIR`,
+    /// `STORE_FORWARD_MAX_PACKETS`, `STORE_FORWARD_MAX_BYTES` and `STORE_FORWARD_MAX_AGE_MS`.
+    pub fn from_env() -> Result<Self, EnvError> {
+        let defaults = Self::default();
+        Ok(Self {
+            enabled: env_flag("STORE_FORWARD", defaults.enabled)?,
+            dir: env_path("STORE_FORWARD_DIR"),
+            max_packets: env_parse("STORE_FORWARD_MAX_PACKETS", defaults.max_packets)?,
+            max_bytes: env_parse("STORE_FORWARD_MAX_BYTES", defaults.max_bytes)?,
+            max_age: Duration::from_millis(env_parse(
+                "STORE_FORWARD_MAX_AGE_MS",
+                defaults.max_age.as_millis() as u64,
+            )?),
+        })
+    }
+}
+
+/// Links between server nodes sharing published packets and session presence.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ClusterConfig {
+    /// This node's name among its peers.
+    pub node_id: String,
+    /// Address peers connect to, e.g. `0.0.0.0:9443`; `None` accepts no peers.
+    pub listen: Option<String>,
+    /// Addresses of every other node.
+    pub peers: Vec<String>,
+    /// Shared secret peers must present when they connect.
+    pub secret: Option<String>,
+}
+
+impl ClusterConfig {
+    /// Create cluster config from `CLUSTER_LISTEN`, `CLUSTER_PEERS` (comma-separated),
+    /// `CLUSTER_SECRET` and the node id (see [`node_id_from_env`]).
+    pub fn from_env() -> Self {
+        Self {
+            node_id: node_id_from_env(),
+            listen: env_value("CLUSTER_LISTEN"),
+            peers: env_list("CLUSTER_PEERS"),
+            secret: env_value("CLUSTER_SECRET"),
+        }
+    }
+
+    /// Whether this node links to any other.
+    pub fn is_clustered(&self) -> bool {
+        self.listen.is_some() || !self.peers.is_empty()
+    }
+}
+
+/// WebSocket endpoint table of the server.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EndpointConfig {
+    /// JSON file binding strategies, limits and access rules to paths; `None`
+    /// serves a single endpoint at [`AddrConfig::endpoint`].
+    pub file: Option<PathBuf>,
+}
+
+impl EndpointConfig {
+    /// Create endpoint config from `ENDPOINTS_FILE`.
+    pub fn from_env() -> Self {
+        Self {
+            file: env_path("ENDPOINTS_FILE"),
+        }
+    }
+}
+
+/// HTTP listener for Server-Sent Events and packet injection.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HttpConfig {
+    /// Address to serve HTTP on, e.g. `0.0.0.0:8080`; `None` disables it.
+    pub listen: Option<String>,
+    /// How often an idle event stream gets a keep-alive comment.
+    pub keepalive: Duration,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            listen: None,
+            keepalive: Duration::from_secs(15),
+        }
+    }
+}
+
+impl HttpConfig {
+    /// Create HTTP config from `HTTP_LISTEN` and `SSE_KEEPALIVE_MS`.
+    pub fn from_env() -> Result<Self, EnvError> {
+        let defaults = Self::default();
+        Ok(Self {
+            listen: env_value("HTTP_LISTEN"),
+            keepalive: Duration::from_millis(env_parse(
+                "SSE_KEEPALIVE_MS",
+                defaults.keepalive.as_millis() as u64,
+            )?),
+        })
+    }
+}
+
+/// Plaintext listener serving Prometheus metrics.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MetricsConfig {
+    /// Address to serve `/metrics` on, e.g. `0.0.0.0:9090`; `None` disables it.
+    pub listen: Option<String>,
+}
+
+impl MetricsConfig {
+    /// Create metrics config from `METRICS_LISTEN`.
+    pub fn from_env() -> Self {
+        Self {
+            listen: env_value("METRICS_LISTEN"),
+        }
+    }
+}
+
+/// permessage-deflate compression of WebSocket messages.
+///
+/// Clients offer the extension and servers accept offers only when `enabled`;
+/// a server endpoint can turn it on or off for its own path.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompressionConfig {
+    pub enabled: bool,
+    /// Largest LZ77 window, as a power of two (9-15); smaller windows use less memory.
+    pub window_bits: u8,
+    /// Keep the compression context between messages, trading memory for ratio.
+    pub context_takeover: bool,
+    /// Largest message a session buffers or inflates, in bytes.
+    pub max_message_size: usize,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            window_bits: 15,
+            context_takeover: true,
+            max_message_size: 4 * 1024 * 1024,
+        }
+    }
+}
+
+impl CompressionConfig {
+    /// Create compression config from `WS_DEFLATE`, `WS_DEFLATE_WINDOW_BITS`,
+    /// `WS_DEFLATE_CONTEXT_TAKEOVER` and `WS_DEFLATE_MAX_BYTES`.
+    pub fn from_env() -> Result<Self, EnvError> {
+        let defaults = Self::default();
+        Ok(Self {
+            enabled: env_flag("WS_DEFLATE", defaults.enabled)?,
+            window_bits: env_parse_in("WS_DEFLATE_WINDOW_BITS", defaults.window_bits, 9..=15)?,
+            context_takeover: env_flag("WS_DEFLATE_CONTEXT_TAKEOVER", defaults.context_takeover)?,
+            max_message_size: env_parse("WS_DEFLATE_MAX_BYTES", defaults.max_message_size)?,
+        })
+    }
+}
+
+/// How log lines are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum LogFormat {
+    /// Human-readable lines, prefixed with the active spans.
+    #[default]
+    Text,
+    /// One JSON object per line, carrying the fields of every active span.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            other => Err(format!("unknown log format: {}", other)),
+        }
+    }
+}
+
+/// Logging configuration.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LogConfig {
+    pub format: LogFormat,
+}
+
+impl LogConfig {
+    /// Create logging config from `LOG_FORMAT` (`text` or `json`).
+    pub fn from_env() -> Result<Self, EnvError> {
+        Ok(Self {
+            format: env_parse("LOG_FORMAT", LogFormat::default())?,
+        })
+    }
+}
+
+/// Export of spans to an OpenTelemetry collector.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TraceConfig {
+    /// Base URL of the collector's OTLP/HTTP receiver, e.g. `http://localhost:4318`;
+    /// `None` keeps spans in the logs only.
+    pub otlp_endpoint: Option<String>,
+    /// Service name reported with every span; `None` uses the binary's name.
+    pub service_name: Option<String>,
+}
+
+impl TraceConfig {
+    /// Create trace export config from `OTEL_EXPORTER_OTLP_ENDPOINT` and `OTEL_SERVICE_NAME`.
+    pub fn from_env() -> Self {
+        Self {
+            otlp_endpoint: env_value("OTEL_EXPORTER_OTLP_ENDPOINT"),
+            service_name: env_value("OTEL_SERVICE_NAME"),
+        }
+    }
+
+    /// URL spans are posted to: the endpoint with the OTLP traces path appended.
+    pub fn traces_url(&self) -> Option<String> {
+        self.otlp_endpoint
+            .as_ref()
+            .map(|endpoint| format!("{}/v1/traces", endpoint.trim_end_matches('/')))
+    }
+}
+
+/// Graceful shutdown configuration.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShutdownConfig {
+    /// How long to wait for sessions to flush and close after a shutdown signal.
+    pub drain_timeout: Duration,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        Self {
+            drain_timeout: Duration::from_secs(8),
+        }
+    }
+}
+
+impl ShutdownConfig {
+    /// Create shutdown config from `SHUTDOWN_DRAIN_MS`.
+    ///
+    /// Keep it below the orchestrator's grace period (10s for `docker compose down`).
+    pub fn from_env() -> Result<Self, EnvError> {
+        let defaults = Self::default();
+        Ok(Self {
+            drain_timeout: Duration::from_millis(env_parse(
+                "SHUTDOWN_DRAIN_MS",
+                defaults.drain_timeout.as_millis() as u64,
+            )?),
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -144,6 +894,42 @@ mod tests {
         assert_eq!(cfg.ws_url(), "wss://localhost:8443/");
     }
 
+    #[test]
+    fn test_env_values_must_parse() {
+        env::set_var("SVCKIT_TEST_FLAG", "Yes ");
+        assert_eq!(env_flag("SVCKIT_TEST_FLAG", false), Ok(true));
+        env::set_var("SVCKIT_TEST_FLAG", "enabled");
+        let error = env_flag("SVCKIT_TEST_FLAG", false).unwrap_err();
+        assert_eq!(error.key, "SVCKIT_TEST_FLAG");
+        assert_eq!(error.value, "enabled");
+
+        env::set_var("SVCKIT_TEST_PORT", "84430");
+        assert!(env_parse::<u16>("SVCKIT_TEST_PORT", 8443).is_err());
+        env::set_var("SVCKIT_TEST_PORT", " ");
+        assert_eq!(env_parse::<u16>("SVCKIT_TEST_PORT", 8443), Ok(8443));
+
+        // Out-of-range values are errors, not clamped
+        for bits in ["8", "20"] {
+            env::set_var("SVCKIT_TEST_WINDOW_BITS", bits);
+            let error = env_parse_in::<u8>("SVCKIT_TEST_WINDOW_BITS", 15, 9..=15).unwrap_err();
+            assert_eq!(error.value, bits);
+        }
+        env::set_var("SVCKIT_TEST_WINDOW_BITS", "12");
+        assert_eq!(
+            env_parse_in::<u8>("SVCKIT_TEST_WINDOW_BITS", 15, 9..=15),
+            Ok(12)
+        );
+
+        env::set_var("SVCKIT_TEST_VERSIONS", "1, 2");
+        assert_eq!(
+            env_parse_list::<u8>("SVCKIT_TEST_VERSIONS"),
+            Ok(Some(vec![1, 2]))
+        );
+        env::set_var("SVCKIT_TEST_VERSIONS", "1,two");
+        assert!(env_parse_list::<u8>("SVCKIT_TEST_VERSIONS").is_err());
+        assert_eq!(env_parse_list::<u8>("SVCKIT_TEST_UNSET"), Ok(None));
+    }
+
     #[test]
     fn test_addr_config_without_tls() {
         let cfg = AddrConfig::from_env_defaults("localhost", 8080).without_tls();
@@ -0,0 +1,95 @@
+//! OpenTelemetry: OTLP export of a service's spans and W3C trace context
+//! carried through packets.
+//!
+//! A packet's `receive` span takes the context the packet arrived with as its
+//! parent; the server re-stamps the packet with the receive span's context so
+//! that every copy it forwards continues the same trace. Each packet written to
+//! a peer gets a `send` span, with an `encode` span for the frame, and carries
+//! the send span's context.
+//!
+//! Without an OTLP endpoint spans carry no OpenTelemetry context: nothing is
+//! stamped, and packets keep the context they arrived with.
+
+use anyhow::{Context as _, Result};
+use opentelemetry::propagation::TextMapPropagator;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{SdkTracer, SdkTracerProvider};
+use opentelemetry_sdk::Resource;
+use protocol::{Packet, TraceContext};
+use std::collections::HashMap;
+use tracing::{info_span, Span, Subscriber};
+use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::TraceConfig;
+
+const TRACEPARENT: &str = "traceparent";
+const TRACESTATE: &str = "tracestate";
+
+/// Layer exporting spans to the configured collector, with the provider to
+/// shut down at exit; `None` when no endpoint is configured.
+pub fn otlp_layer<S>(
+    config: &TraceConfig,
+    default_service: &str,
+) -> Result<Option<(OpenTelemetryLayer<S, SdkTracer>, SdkTracerProvider)>>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    let Some(url) = config.traces_url() else {
+        return Ok(None);
+    };
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(&url)
+        .build()
+        .with_context(|| format!("Failed to build OTLP exporter for {}", url))?;
+    let service = config.service_name.as_deref().unwrap_or(default_service);
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(
+            Resource::builder()
+                .with_service_name(service.to_string())
+                .build(),
+        )
+        .build();
+    let layer = tracing_opentelemetry::layer().with_tracer(provider.tracer(service.to_string()));
+    Ok(Some((layer, provider)))
+}
+
+/// Make the trace context `packet` arrived with the parent of `span`.
+pub fn set_remote_parent(span: &Span, packet: &Packet) {
+    let Some(context) = packet.trace_context() else {
+        return;
+    };
+    let mut carrier = HashMap::from([(TRACEPARENT.to_string(), context.traceparent)]);
+    if let Some(state) = context.tracestate {
+        carrier.insert(TRACESTATE.to_string(), state);
+    }
+    let _ = span.set_parent(TraceContextPropagator::new().extract(&carrier));
+}
+
+/// Stamp `packet` with the trace context of `span`, if it has one.
+pub fn inject(span: &Span, packet: &mut Packet) {
+    let mut carrier = HashMap::new();
+    TraceContextPropagator::new().inject_context(&span.context(), &mut carrier);
+    let Some(traceparent) = carrier.get(TRACEPARENT) else {
+        return;
+    };
+    if let Ok(context) = TraceContext::new(traceparent, carrier.get(TRACESTATE).map(String::as_str))
+    {
+        let _ = packet.set_trace_context(&context);
+    }
+}
+
+/// Span for writing one packet to a peer, continuing the packet's trace.
+pub fn send_span(packet: &Packet) -> Span {
+    let span = info_span!(
+        "send",
+        packet_type = packet.header.type_name(),
+        urgency = packet.header.urgency.as_str()
+    );
+    set_remote_parent(&span, packet);
+    span
+}
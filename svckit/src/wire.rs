@@ -0,0 +1,271 @@
+//! WebSocket framing glue: packet encoding per subprotocol, the writer task,
+//! traffic counters and the permessage-deflate transport ([`DeflateStream`]).
+//!
+//! The negotiated [`Framing`] decides whether packets travel as binary frames
+//! in wire format or as text frames in their JSON representation.
+
+use futures_util::{Sink, SinkExt};
+use protocol::{BatchCoalescer, Framing, Packet, ProtocolError};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use tokio_tungstenite::tungstenite::{self, Message};
+use tracing::{info_span, Instrument};
+
+use crate::telemetry;
+
+mod deflate;
+
+pub use deflate::{DeflateCodec, DeflateRole, DeflateStream};
+
+/// Traffic counters of one connection.
+#[derive(Debug, Default)]
+pub struct Traffic {
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    packets_in: AtomicU64,
+    packets_out: AtomicU64,
+}
+
+impl Traffic {
+    /// Record a received frame of `bytes` carrying `packets` packets.
+    pub fn record_in(&self, bytes: usize, packets: usize) {
+        self.bytes_in.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.packets_in.fetch_add(packets as u64, Ordering::Relaxed);
+    }
+
+    /// Record a sent frame of `bytes` carrying `packets` packets.
+    pub fn record_out(&self, bytes: usize, packets: usize) {
+        self.bytes_out.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.packets_out
+            .fetch_add(packets as u64, Ordering::Relaxed);
+    }
+
+    pub fn bytes_in(&self) -> u64 {
+        self.bytes_in.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_out(&self) -> u64 {
+        self.bytes_out.load(Ordering::Relaxed)
+    }
+
+    pub fn packets_in(&self) -> u64 {
+        self.packets_in.load(Ordering::Relaxed)
+    }
+
+    pub fn packets_out(&self) -> u64 {
+        self.packets_out.load(Ordering::Relaxed)
+    }
+}
+
+/// Longest close reason that fits in a WebSocket control frame.
+const MAX_CLOSE_REASON: usize = 123;
+
+/// Build a close frame, trimming the reason to fit a control frame.
+pub fn close_message(code: CloseCode, reason: &str) -> Message {
+    let mut end = reason.len().min(MAX_CLOSE_REASON);
+    while !reason.is_char_boundary(end) {
+        end -= 1;
+    }
+    Message::Close(Some(CloseFrame {
+        code,
+        reason: reason[..end].to_string().into(),
+    }))
+}
+
+/// Encode a packet as a WebSocket message for the given framing.
+pub fn encode_packet(framing: Framing, packet: &Packet) -> Message {
+    match framing {
+        Framing::Binary => Message::Binary(packet.to_bytes().into()),
+        Framing::Json => Message::Text(packet.to_json().to_string().into()),
+    }
+}
+
+/// Content of a data frame, interpreted according to the connection's framing.
+pub enum Inbound {
+    /// A protocol packet.
+    Packet(Packet),
+    /// Untyped text, as sent by legacy senders.
+    Text(String),
+}
+
+/// Interpret a data frame. Control frames yield `Ok(None)`.
+///
+/// Binary sessions treat text frames as untyped messages. JSON sessions parse
+/// text frames that hold a JSON object as packets, at `version` unless they
+/// name one, and reject binary frames.
+pub fn decode_frame(
+    framing: Framing,
+    version: u8,
+    msg: &Message,
+) -> Result<Option<Inbound>, ProtocolError> {
+    match (framing, msg) {
+        (Framing::Binary, Message::Binary(data)) => {
+            Packet::from_bytes(data).map(|p| Some(Inbound::Packet(p)))
+        }
+        (Framing::Binary, Message::Text(text)) => Ok(Some(Inbound::Text(text.to_string()))),
+        (Framing::Json, Message::Text(text)) => {
+            match serde_json::from_str::<serde_json::Value>(text) {
+                Ok(json) if json.is_object() => {
+                    Packet::from_json_versioned(&json, version).map(|p| Some(Inbound::Packet(p)))
+                }
+                _ => Ok(Some(Inbound::Text(text.to_string()))),
+            }
+        }
+        (Framing::Json, Message::Binary(_)) => Err(ProtocolError::InvalidFormat(
+            "binary frame on a JSON-framed session".into(),
+        )),
+        _ => Ok(None),
+    }
+}
+
+/// Frames queued for a connection's writer task.
+pub enum Outbound {
+    /// Protocol packet, subject to GREEN batching.
+    Packet(Packet),
+    /// Raw WebSocket message, sent after any pending batch.
+    Message(Message),
+}
+
+/// Sleep until `deadline`, or forever without one.
+pub async fn sleep_until_deadline(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline.into()).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Encode a packet and send it, counting the frame in `traffic`.
+async fn send_frame<S>(
+    sink: &mut S,
+    framing: Framing,
+    packet: &Packet,
+    traffic: &Traffic,
+) -> Result<(), tungstenite::Error>
+where
+    S: Sink<Message, Error = tungstenite::Error> + Unpin,
+{
+    let msg = info_span!("encode").in_scope(|| encode_packet(framing, packet));
+    traffic.record_out(msg.len(), 0);
+    sink.send(msg).await
+}
+
+/// Send one packet outside the writer task, in a `send` span whose trace context it carries.
+pub async fn send_packet<S>(
+    sink: &mut S,
+    framing: Framing,
+    mut packet: Packet,
+    traffic: &Traffic,
+) -> Result<(), tungstenite::Error>
+where
+    S: Sink<Message, Error = tungstenite::Error> + Unpin,
+{
+    traffic.record_out(0, 1);
+    let span = telemetry::send_span(&packet);
+    telemetry::inject(&span, &mut packet);
+    send_frame(sink, framing, &packet, traffic)
+        .instrument(span)
+        .await
+}
+
+/// Drain a connection's outbound queue into the WebSocket sink, batching GREEN packets.
+///
+/// Packets are encoded with the wire `version` and `framing` negotiated for the connection,
+/// each in a `send` span whose trace context it carries. GREEN packets held for a
+/// batch are written later, when the batch is flushed. Nothing is written after a
+/// close frame; whatever is still queued is dropped.
+pub async fn run_writer<S>(
+    mut sink: S,
+    mut rx: mpsc::Receiver<Outbound>,
+    mut coalescer: BatchCoalescer,
+    version: u8,
+    framing: Framing,
+    traffic: Arc<Traffic>,
+) -> Result<(), tungstenite::Error>
+where
+    S: Sink<Message, Error = tungstenite::Error> + Unpin,
+{
+    loop {
+        tokio::select! {
+            outbound = rx.recv() => match outbound {
+                Some(Outbound::Packet(packet)) => {
+                    traffic.record_out(0, 1);
+                    let span = telemetry::send_span(&packet);
+                    let mut packet = packet.with_version(version);
+                    telemetry::inject(&span, &mut packet);
+                    async {
+                        for frame in coalescer.push(packet, Instant::now()) {
+                            send_frame(&mut sink, framing, &frame, &traffic).await?;
+                        }
+                        Ok::<_, tungstenite::Error>(())
+                    }
+                    .instrument(span)
+                    .await?;
+                }
+                Some(Outbound::Message(msg)) => {
+                    if let Some(batch) = coalescer.flush() {
+                        send_frame(&mut sink, framing, &batch, &traffic).await?;
+                    }
+                    traffic.record_out(msg.len(), usize::from(msg.is_text() || msg.is_binary()));
+                    let closing = msg.is_close();
+                    sink.send(msg).await?;
+                    if closing {
+                        break;
+                    }
+                }
+                None => break,
+            },
+            _ = sleep_until_deadline(coalescer.deadline()) => {
+                if let Some(batch) = coalescer.poll_expired(Instant::now()) {
+                    send_frame(&mut sink, framing, &batch, &traffic).await?;
+                }
+            }
+        }
+    }
+
+    if let Some(batch) = coalescer.flush() {
+        send_frame(&mut sink, framing, &batch, &traffic).await?;
+    }
+    sink.close().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protocol::Urgency;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_writer_stops_after_close() {
+        let (tx, rx) = mpsc::channel(8);
+        tx.send(Outbound::Packet(Packet::red("before")))
+            .await
+            .unwrap();
+        tx.send(Outbound::Message(close_message(CloseCode::Normal, "bye")))
+            .await
+            .unwrap();
+        tx.send(Outbound::Packet(Packet::red("after")))
+            .await
+            .unwrap();
+
+        let mut sent: Vec<Message> = Vec::new();
+        let sink = (&mut sent).sink_map_err(|never| match never {});
+        let coalescer = BatchCoalescer::new(Duration::from_millis(50), 16, 4096);
+        run_writer(sink, rx, coalescer, 1, Framing::Binary, Arc::default())
+            .await
+            .unwrap();
+
+        assert_eq!(sent.len(), 2);
+        let Message::Binary(data) = &sent[0] else {
+            panic!("expected a binary frame, got {:?}", sent[0]);
+        };
+        assert_eq!(
+            Packet::from_bytes(data).unwrap().header.urgency,
+            Urgency::Red
+        );
+        assert!(sent[1].is_close());
+    }
+}
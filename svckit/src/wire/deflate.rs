@@ -0,0 +1,573 @@
+//! permessage-deflate (RFC 7692) on the raw byte stream under tungstenite.
+//!
+//! tungstenite neither negotiates the extension nor accepts frames with the
+//! RSV1 bit set, so compression happens underneath it. [`DeflateCodec`] does
+//! no I/O: it takes the bytes read from the peer and hands back the same
+//! frames inflated with RSV1 cleared, and it takes the frames tungstenite
+//! writes and hands back compressed ones. Control frames and messages the
+//! peer sent uncompressed pass through untouched. [`DeflateStream`] runs a
+//! codec on a socket. Negotiation lives in [`protocol::DeflateParams`].
+
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress, Status};
+use futures_util::ready;
+use protocol::{DeflateError, DeflateParams, MAX_WINDOW_BITS, MIN_WINDOW_BITS};
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// Bytes every sync flush ends with; stripped from and re-appended to each message.
+const FLUSH_TAIL: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+
+/// Scratch space for one round of compression or decompression.
+const CHUNK: usize = 16 * 1024;
+
+/// WebSocket opcodes below this are data frames, the rest control frames.
+const OPCODE_CONTROL: u8 = 0x8;
+
+/// Opcode of a message's continuation frames.
+const OPCODE_CONTINUATION: u8 = 0x0;
+
+/// Which end of the connection a [`DeflateCodec`] runs on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeflateRole {
+    Client,
+    Server,
+}
+
+/// One WebSocket frame, with its payload unmasked.
+struct Frame {
+    /// First header byte: FIN, RSV bits and opcode.
+    head: u8,
+    mask: Option<[u8; 4]>,
+    payload: Vec<u8>,
+}
+
+impl Frame {
+    const FIN: u8 = 0x80;
+    const RSV1: u8 = 0x40;
+
+    fn fin(&self) -> bool {
+        self.head & Self::FIN != 0
+    }
+
+    fn rsv1(&self) -> bool {
+        self.head & Self::RSV1 != 0
+    }
+
+    fn opcode(&self) -> u8 {
+        self.head & 0x0f
+    }
+
+    fn set_rsv1(&mut self, on: bool) {
+        if on {
+            self.head |= Self::RSV1;
+        } else {
+            self.head &= !Self::RSV1;
+        }
+    }
+
+    /// Parse the frame at the start of `buf`, with the number of bytes it takes.
+    ///
+    /// `Ok(None)` until the whole frame has arrived.
+    fn parse(buf: &[u8], max_payload: usize) -> Result<Option<(Self, usize)>, DeflateError> {
+        let [head, second, ..] = *buf else {
+            return Ok(None);
+        };
+        let (len, mut pos) = match second & 0x7f {
+            126 if buf.len() >= 4 => (u64::from(u16::from_be_bytes([buf[2], buf[3]])), 4),
+            127 if buf.len() >= 10 => {
+                let bytes: [u8; 8] = buf[2..10].try_into().expect("eight bytes");
+                (u64::from_be_bytes(bytes), 10)
+            }
+            126 | 127 => return Ok(None),
+            len => (u64::from(len), 2),
+        };
+        let len = usize::try_from(len)
+            .ok()
+            .filter(|len| *len <= max_payload)
+            .ok_or(DeflateError::TooLarge { limit: max_payload })?;
+
+        let mask = if second & 0x80 != 0 {
+            let Some(key) = buf.get(pos..pos + 4) else {
+                return Ok(None);
+            };
+            pos += 4;
+            Some([key[0], key[1], key[2], key[3]])
+        } else {
+            None
+        };
+        let Some(payload) = buf.get(pos..pos + len) else {
+            return Ok(None);
+        };
+
+        let mut payload = payload.to_vec();
+        if let Some(key) = mask {
+            apply_mask(&mut payload, key);
+        }
+        Ok(Some((
+            Self {
+                head,
+                mask,
+                payload,
+            },
+            pos + len,
+        )))
+    }
+
+    /// Append the frame in wire format, masked with its original key.
+    fn write(mut self, out: &mut Vec<u8>) {
+        let len = self.payload.len();
+        let mask_bit = if self.mask.is_some() { 0x80 } else { 0 };
+        out.push(self.head);
+        match len {
+            0..=125 => out.push(mask_bit | len as u8),
+            126..=0xffff => {
+                out.push(mask_bit | 126);
+                out.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            _ => {
+                out.push(mask_bit | 127);
+                out.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        if let Some(key) = self.mask {
+            out.extend_from_slice(&key);
+            apply_mask(&mut self.payload, key);
+        }
+        out.extend_from_slice(&self.payload);
+    }
+}
+
+fn apply_mask(payload: &mut [u8], key: [u8; 4]) {
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= key[i % 4];
+    }
+}
+
+/// Compresses outbound and inflates inbound frames of one session.
+///
+/// `max_message_size` caps both the frames buffered while they arrive and the
+/// inflated size of a message, so a peer cannot make the session hold more.
+pub struct DeflateCodec {
+    compress: Compress,
+    decompress: Decompress,
+    /// Reset the compressor after every message we send.
+    reset_compress: bool,
+    /// Reset the decompressor after every message we receive.
+    reset_decompress: bool,
+    max_message_size: usize,
+    /// Bytes from the peer not yet forming a whole frame.
+    inbound: Vec<u8>,
+    /// Frames from tungstenite not yet written whole.
+    outbound: Vec<u8>,
+    /// Inflated bytes of the compressed message being received, if any.
+    inflating: Option<usize>,
+    /// Whether a message being sent in fragments is compressed.
+    deflating: bool,
+}
+
+impl DeflateCodec {
+    pub fn new(params: DeflateParams, role: DeflateRole, max_message_size: usize) -> Self {
+        let (own_bits, peer_bits, reset_compress, reset_decompress) = match role {
+            DeflateRole::Server => (
+                params.server_max_window_bits,
+                params.client_max_window_bits,
+                params.server_no_context_takeover,
+                params.client_no_context_takeover,
+            ),
+            DeflateRole::Client => (
+                params.client_max_window_bits,
+                params.server_max_window_bits,
+                params.client_no_context_takeover,
+                params.server_no_context_takeover,
+            ),
+        };
+        Self {
+            compress: Compress::new_with_window_bits(
+                Compression::default(),
+                false,
+                own_bits.clamp(MIN_WINDOW_BITS, MAX_WINDOW_BITS),
+            ),
+            // A window at least as large as the peer's reads everything it writes
+            decompress: Decompress::new_with_window_bits(
+                false,
+                peer_bits.clamp(MIN_WINDOW_BITS, MAX_WINDOW_BITS),
+            ),
+            reset_compress,
+            reset_decompress,
+            max_message_size,
+            inbound: Vec::new(),
+            outbound: Vec::new(),
+            inflating: None,
+            deflating: false,
+        }
+    }
+
+    /// Feed bytes read from the peer, appending every completed frame to `out`
+    /// as tungstenite expects it: inflated, with RSV1 cleared.
+    pub fn decode(&mut self, input: &[u8], out: &mut Vec<u8>) -> Result<(), DeflateError> {
+        self.inbound.extend_from_slice(input);
+        let mut pos = 0;
+        while let Some((mut frame, used)) =
+            Frame::parse(&self.inbound[pos..], self.max_message_size)?
+        {
+            pos += used;
+            if frame.opcode() < OPCODE_CONTROL {
+                if frame.opcode() != OPCODE_CONTINUATION {
+                    self.inflating = frame.rsv1().then_some(0);
+                }
+                if let Some(inflated) = self.inflating {
+                    frame.payload = self.inflate(&frame.payload, frame.fin(), inflated)?;
+                    frame.set_rsv1(false);
+                    self.inflating = Some(inflated + frame.payload.len());
+                    if frame.fin() {
+                        self.inflating = None;
+                        if self.reset_decompress {
+                            self.decompress.reset(false);
+                        }
+                    }
+                }
+            }
+            frame.write(out);
+        }
+        self.inbound.drain(..pos);
+        Ok(())
+    }
+
+    /// Feed bytes tungstenite wrote, appending every completed frame to `out`
+    /// compressed, with RSV1 set on the first frame of each message.
+    pub fn encode(&mut self, input: &[u8], out: &mut Vec<u8>) -> Result<(), DeflateError> {
+        self.outbound.extend_from_slice(input);
+        let mut pos = 0;
+        while let Some((mut frame, used)) = Frame::parse(&self.outbound[pos..], usize::MAX)? {
+            pos += used;
+            if frame.opcode() < OPCODE_CONTROL {
+                if frame.opcode() != OPCODE_CONTINUATION {
+                    self.deflating = true;
+                    frame.set_rsv1(true);
+                }
+                if self.deflating {
+                    frame.payload = self.deflate(&frame.payload, frame.fin())?;
+                    if frame.fin() {
+                        self.deflating = false;
+                        if self.reset_compress {
+                            self.compress.reset();
+                        }
+                    }
+                }
+            }
+            frame.write(out);
+        }
+        self.outbound.drain(..pos);
+        Ok(())
+    }
+
+    /// Compress one frame's payload; the last frame of a message drops the flush tail.
+    fn deflate(&mut self, input: &[u8], fin: bool) -> Result<Vec<u8>, DeflateError> {
+        let mut out = Vec::with_capacity(input.len() / 2 + 16);
+        let mut chunk = [0u8; CHUNK];
+        let mut pos = 0;
+        loop {
+            let (before_in, before_out) = (self.compress.total_in(), self.compress.total_out());
+            self.compress
+                .compress(&input[pos..], &mut chunk, FlushCompress::Sync)
+                .map_err(|e| DeflateError::Corrupt(e.to_string()))?;
+            let consumed = (self.compress.total_in() - before_in) as usize;
+            pos += consumed;
+            let written = (self.compress.total_out() - before_out) as usize;
+            out.extend_from_slice(&chunk[..written]);
+            if pos == input.len() && written < CHUNK {
+                break;
+            }
+            if consumed == 0 && written == 0 {
+                return Err(DeflateError::Corrupt(
+                    "deflate made no progress".to_string(),
+                ));
+            }
+        }
+        if fin && out.ends_with(&FLUSH_TAIL) {
+            out.truncate(out.len() - FLUSH_TAIL.len());
+        }
+        Ok(out)
+    }
+
+    /// Inflate one frame's payload of a message that has inflated to `inflated` bytes so far.
+    fn inflate(
+        &mut self,
+        input: &[u8],
+        fin: bool,
+        inflated: usize,
+    ) -> Result<Vec<u8>, DeflateError> {
+        let payload_len = input.len();
+        let mut tail = Vec::new();
+        let input = if fin {
+            tail.reserve(input.len() + FLUSH_TAIL.len());
+            tail.extend_from_slice(input);
+            tail.extend_from_slice(&FLUSH_TAIL);
+            &tail[..]
+        } else {
+            input
+        };
+
+        let mut out = Vec::new();
+        let mut chunk = [0u8; CHUNK];
+        let mut pos = 0;
+        loop {
+            let (before_in, before_out) = (self.decompress.total_in(), self.decompress.total_out());
+            let status = self
+                .decompress
+                .decompress(&input[pos..], &mut chunk, FlushDecompress::Sync)
+                .map_err(|e| DeflateError::Corrupt(e.to_string()))?;
+            let consumed = (self.decompress.total_in() - before_in) as usize;
+            pos += consumed;
+            let written = (self.decompress.total_out() - before_out) as usize;
+            if inflated + out.len() + written > self.max_message_size {
+                return Err(DeflateError::TooLarge {
+                    limit: self.max_message_size,
+                });
+            }
+            out.extend_from_slice(&chunk[..written]);
+            if status == Status::StreamEnd {
+                // The peer ended the stream; a new one starts with the next message.
+                // Only the flush tail appended above may follow the end.
+                if pos < payload_len {
+                    return Err(DeflateError::Corrupt(format!(
+                        "{} bytes after the end of the stream",
+                        payload_len - pos
+                    )));
+                }
+                self.decompress.reset(false);
+                break;
+            }
+            if pos == input.len() && written < CHUNK {
+                break;
+            }
+            if consumed == 0 && written == 0 {
+                return Err(DeflateError::Corrupt(
+                    "inflate made no progress".to_string(),
+                ));
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// Transport under tungstenite that applies permessage-deflate once enabled.
+///
+/// Bytes pass through untouched during the HTTP upgrade; after it, call
+/// [`DeflateStream::enable`] with the codec for the negotiated parameters.
+pub struct DeflateStream<S> {
+    inner: S,
+    codec: Option<DeflateCodec>,
+    /// Inflated frames not yet handed to tungstenite.
+    decoded: Vec<u8>,
+    decoded_pos: usize,
+    /// Compressed frames not yet written to `inner`.
+    encoded: Vec<u8>,
+    encoded_pos: usize,
+}
+
+impl<S> DeflateStream<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            codec: None,
+            decoded: Vec::new(),
+            decoded_pos: 0,
+            encoded: Vec::new(),
+            encoded_pos: 0,
+        }
+    }
+
+    /// Compress and inflate every frame from now on.
+    pub fn enable(&mut self, codec: DeflateCodec) {
+        self.codec = Some(codec);
+    }
+}
+
+fn deflate_error(e: DeflateError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+impl<S: AsyncWrite + Unpin> DeflateStream<S> {
+    /// Write out every compressed byte still pending.
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.encoded_pos < self.encoded.len() {
+            let pending = &self.encoded[self.encoded_pos..];
+            let n = ready!(Pin::new(&mut self.inner).poll_write(cx, pending))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.encoded_pos += n;
+        }
+        self.encoded.clear();
+        self.encoded_pos = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for DeflateStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let Some(codec) = &mut this.codec else {
+            return Pin::new(&mut this.inner).poll_read(cx, buf);
+        };
+        loop {
+            if this.decoded_pos < this.decoded.len() {
+                let n = buf.remaining().min(this.decoded.len() - this.decoded_pos);
+                buf.put_slice(&this.decoded[this.decoded_pos..this.decoded_pos + n]);
+                this.decoded_pos += n;
+                return Poll::Ready(Ok(()));
+            }
+            this.decoded.clear();
+            this.decoded_pos = 0;
+
+            let mut raw = [0u8; 8192];
+            let mut raw_buf = ReadBuf::new(&mut raw);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut raw_buf))?;
+            if raw_buf.filled().is_empty() {
+                return Poll::Ready(Ok(()));
+            }
+            codec
+                .decode(raw_buf.filled(), &mut this.decoded)
+                .map_err(deflate_error)?;
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for DeflateStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        data: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.codec.is_none() {
+            return Pin::new(&mut this.inner).poll_write(cx, data);
+        }
+        ready!(this.poll_drain(cx))?;
+        if let Some(codec) = &mut this.codec {
+            codec
+                .encode(data, &mut this.encoded)
+                .map_err(deflate_error)?;
+        }
+        Poll::Ready(Ok(data.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A single-frame message in wire format.
+    fn frame(opcode: u8, payload: &[u8], mask: Option<[u8; 4]>) -> Vec<u8> {
+        let mut out = Vec::new();
+        Frame {
+            head: Frame::FIN | opcode,
+            mask,
+            payload: payload.to_vec(),
+        }
+        .write(&mut out);
+        out
+    }
+
+    fn pair(params: DeflateParams, limit: usize) -> (DeflateCodec, DeflateCodec) {
+        (
+            DeflateCodec::new(params, DeflateRole::Client, limit),
+            DeflateCodec::new(params, DeflateRole::Server, limit),
+        )
+    }
+
+    #[test]
+    fn test_codec_round_trip_with_masking_and_takeover() {
+        let params = DeflateParams::negotiate(["permessage-deflate"], 15, true).unwrap();
+        let (mut client, mut server) = pair(params, 1 << 20);
+        let text = br#"{"lat":34.2345,"lon":69.1234,"urgency":"GREEN"}"#.repeat(20);
+
+        for _ in 0..3 {
+            let plain = frame(0x1, &text, Some([1, 2, 3, 4]));
+            let mut wire = Vec::new();
+            client.encode(&plain, &mut wire).unwrap();
+            assert!(wire.len() < plain.len() / 4);
+            assert_eq!(wire[0] & Frame::RSV1, Frame::RSV1);
+
+            // Deliver in two pieces to exercise partial frames
+            let mut decoded = Vec::new();
+            server.decode(&wire[..5], &mut decoded).unwrap();
+            assert!(decoded.is_empty());
+            server.decode(&wire[5..], &mut decoded).unwrap();
+            assert_eq!(decoded, plain);
+        }
+    }
+
+    #[test]
+    fn test_codec_passes_control_and_uncompressed_frames() {
+        let params = DeflateParams::negotiate(["permessage-deflate"], 15, false).unwrap();
+        let (_, mut server) = pair(params, 1024);
+        let ping = frame(0x9, b"hb", Some([9, 9, 9, 9]));
+        let text = frame(0x1, b"plain", Some([5, 6, 7, 8]));
+
+        let mut decoded = Vec::new();
+        server
+            .decode(&[ping.clone(), text.clone()].concat(), &mut decoded)
+            .unwrap();
+        assert_eq!(decoded, [ping, text].concat());
+    }
+
+    #[test]
+    fn test_codec_enforces_the_message_limit() {
+        let params = DeflateParams::negotiate(["permessage-deflate"], 15, true).unwrap();
+        let (mut client, _) = pair(params, 1 << 20);
+        let (_, mut server) = pair(params, 1024);
+
+        let mut wire = Vec::new();
+        client
+            .encode(&frame(0x2, &[0u8; 4096], None), &mut wire)
+            .unwrap();
+        assert!(wire.len() < 1024);
+        assert_eq!(
+            server.decode(&wire, &mut Vec::new()),
+            Err(DeflateError::TooLarge { limit: 1024 })
+        );
+    }
+
+    #[test]
+    fn test_codec_rejects_data_after_the_stream_end() {
+        let params = DeflateParams::negotiate(["permessage-deflate"], 15, true).unwrap();
+        let (_, mut server) = pair(params, 1024);
+
+        // A final stored block holding "hi", then bytes that belong to no stream
+        let mut compressed = vec![0x01, 0x02, 0x00, 0xfd, 0xff];
+        compressed.extend_from_slice(b"hi");
+        let mut message = frame(0x1, &compressed, None);
+        message[0] |= Frame::RSV1;
+        let mut decoded = Vec::new();
+        server.decode(&message, &mut decoded).unwrap();
+        assert_eq!(decoded, frame(0x1, b"hi", None));
+
+        compressed.extend_from_slice(b"junk");
+        let mut message = frame(0x1, &compressed, None);
+        message[0] |= Frame::RSV1;
+        assert!(matches!(
+            server.decode(&message, &mut Vec::new()),
+            Err(DeflateError::Corrupt(_))
+        ));
+    }
+}
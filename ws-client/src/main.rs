@@ -5,17 +5,43 @@
 
 use anyhow::{Context, Result};
 use async_trait::async_trait;
-use futures_util::{SinkExt, StreamExt};
-use protocol::{Packet, ProtocolApi, StrategyHandler, Urgency};
-use rustls::pki_types::{CertificateDer, ServerName};
+use futures_util::{Sink, SinkExt, Stream, StreamExt};
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use protocol::{
+    AdminRequest, AdminResponse, BatchCoalescer, Capabilities, DeflateParams, Framing, HelloAck,
+    LinkEvent, LinkMonitor, Packet, ProtocolApi, StrategyHandler, Urgency, FEATURE_BATCH,
+    PACKET_TYPE_ADMIN, PACKET_TYPE_ERROR, PACKET_TYPE_HEARTBEAT, PACKET_TYPE_TRACK,
+    PROTOCOL_VERSION, PROTOCOL_VERSION_2,
+};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
 use std::fs::File;
 use std::io::BufReader;
 use std::sync::Arc;
-use svckit::AddrConfig;
+use std::time::{Duration, Instant};
+use svckit::telemetry;
+use svckit::wire::{
+    close_message, decode_frame, encode_packet, run_writer, send_packet, sleep_until_deadline,
+    DeflateCodec, DeflateRole, DeflateStream, Inbound, Outbound, Traffic,
+};
+use svckit::{
+    AddrConfig, AuthConfig, BatchConfig, CompressionConfig, HandshakeConfig, HeartbeatConfig,
+    LogConfig, LogFormat, TraceConfig,
+};
 use tokio::net::TcpStream;
+use tokio::sync::mpsc;
 use tokio_rustls::TlsConnector;
-use tokio_tungstenite::tungstenite::Message;
-use tracing::{error, info, warn};
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::header::{
+    HeaderValue, AUTHORIZATION, SEC_WEBSOCKET_EXTENSIONS, SEC_WEBSOCKET_PROTOCOL,
+};
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
+use tokio_tungstenite::tungstenite::{self, Message};
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+use tracing::{error, info, info_span, warn, Instrument, Span};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::Layer;
 
 // ============================================================================
 // Strategy Implementation
@@ -28,24 +54,40 @@ struct ClientStrategyHandler;
 impl StrategyHandler for ClientStrategyHandler {
     async fn on_urgent_red(&self, packet: &Packet) {
         info!(
-            "[CLIENT] 🔴 RED ALERT: Drone target received: {}",
-            packet.payload_string_lossy()
+            urgency = "RED",
+            from = "server",
+            payload = %packet.payload_string_lossy(),
+            "[CLIENT] Drone target received"
         );
     }
 
     async fn on_normal(&self, packet: &Packet) {
         info!(
-            "[CLIENT] 🟢 Normal response from server: {}",
-            packet.payload_string_lossy()
+            urgency = "GREEN",
+            from = "server",
+            payload = %packet.payload_string_lossy(),
+            "[CLIENT] Response"
         );
     }
 
     async fn on_urgent_yellow(&self, packet: &Packet) {
         info!(
-            "[CLIENT] 🟡 Yellow priority response: {}",
-            packet.payload_string_lossy()
+            urgency = "YELLOW",
+            from = "server",
+            payload = %packet.payload_string_lossy(),
+            "[CLIENT] Priority response"
         );
     }
+
+    async fn on_link_event(&self, event: &LinkEvent) {
+        if let LinkEvent::Lost { silent_for } = event {
+            error!(
+                from = "server",
+                silent_for = ?silent_for,
+                "[CLIENT] Link lost after silence"
+            );
+        }
+    }
 }
 
 // ============================================================================
@@ -66,28 +108,146 @@ fn load_tls_config(config: &AddrConfig) -> Result<Arc<rustls::ClientConfig>> {
     // Build root cert store
     let mut root_store = rustls::RootCertStore::empty();
     for cert in ca_certs {
-        root_store.add(cert).context("Failed to add CA certificate")?;
+        root_store
+            .add(cert)
+            .context("Failed to add CA certificate")?;
     }
 
-    // Build client config
-    let client_config = rustls::ClientConfig::builder()
-        .with_root_certificates(root_store)
-        .with_no_client_auth();
+    // Build client config, presenting a client certificate if one is configured
+    let builder = rustls::ClientConfig::builder().with_root_certificates(root_store);
+    let client_config = match (&config.tls.client_cert_file, &config.tls.client_key_file) {
+        (Some(cert_path), Some(key_path)) => {
+            let cert_file = File::open(cert_path)
+                .with_context(|| format!("Failed to open client cert file: {:?}", cert_path))?;
+            let certs: Vec<CertificateDer<'static>> =
+                rustls_pemfile::certs(&mut BufReader::new(cert_file))
+                    .collect::<Result<Vec<_>, _>>()
+                    .context("Failed to parse client certificates")?;
+
+            let key_file = File::open(key_path)
+                .with_context(|| format!("Failed to open client key file: {:?}", key_path))?;
+            let key: PrivateKeyDer<'static> =
+                rustls_pemfile::private_key(&mut BufReader::new(key_file))
+                    .context("Failed to parse client private key")?
+                    .ok_or_else(|| anyhow::anyhow!("No private key found in {:?}", key_path))?;
+
+            builder
+                .with_client_auth_cert(certs, key)
+                .context("Failed to configure client certificate")?
+        }
+        (None, None) => builder.with_no_client_auth(),
+        _ => anyhow::bail!("TLS_CLIENT_CERT and TLS_CLIENT_KEY must be set together"),
+    };
 
     Ok(Arc::new(client_config))
 }
 
 // ============================================================================
-// WebSocket Client Session
+// Capability Handshake
 // ============================================================================
 
-async fn run_client_session(
-    config: AddrConfig,
-    initial_message: &str,
-) -> Result<()> {
-    let tls_config = load_tls_config(&config)?;
-    let tls_connector = TlsConnector::from(tls_config);
+/// Read frames until the server's HELLO-ACK, surfacing a close reason if rejected.
+async fn read_hello_ack<W>(ws: &mut W, framing: Framing) -> Result<HelloAck>
+where
+    W: Stream<Item = Result<Message, tungstenite::Error>> + Unpin,
+{
+    while let Some(msg_result) = ws.next().await {
+        let msg = msg_result.context("WebSocket error during handshake")?;
+        if let Message::Close(frame) = msg {
+            anyhow::bail!(
+                "Server rejected handshake: {}",
+                frame.map(|f| f.reason.to_string()).unwrap_or_default()
+            );
+        }
+        match decode_frame(framing, PROTOCOL_VERSION, &msg)? {
+            Some(Inbound::Packet(packet)) => return Ok(packet.to_hello_ack()?),
+            Some(Inbound::Text(_)) => anyhow::bail!("Expected HELLO-ACK, got untyped text"),
+            None => {}
+        }
+    }
+    anyhow::bail!("Connection closed during handshake")
+}
+
+/// Send our HELLO and wait for the server's HELLO-ACK.
+///
+/// An answer outside our offer closes the session with the reason in the close frame.
+async fn client_handshake<W>(
+    ws: &mut W,
+    framing: Framing,
+    capabilities: &Capabilities,
+    timeout: Duration,
+) -> Result<HelloAck>
+where
+    W: Stream<Item = Result<Message, tungstenite::Error>>
+        + Sink<Message, Error = tungstenite::Error>
+        + Unpin,
+{
+    ws.send(encode_packet(
+        framing,
+        &Packet::hello(&capabilities.hello()),
+    ))
+    .await
+    .context("Failed to send HELLO")?;
+
+    let ack = match tokio::time::timeout(timeout, read_hello_ack(ws, framing)).await {
+        Ok(result) => result?,
+        Err(_) => {
+            let _ = ws
+                .send(close_message(CloseCode::Policy, "handshake timeout"))
+                .await;
+            anyhow::bail!("Handshake timed out");
+        }
+    };
+
+    if let Err(e) = capabilities.accept(&ack) {
+        let _ = ws
+            .send(close_message(CloseCode::Protocol, &e.to_string()))
+            .await;
+        anyhow::bail!("Handshake failed: {}", e);
+    }
+
+    info!(
+        "[CLIENT] Handshake complete (v{}, codec {}, features {:?})",
+        ack.version, ack.codec, ack.features
+    );
+    Ok(ack)
+}
+
+/// Client capabilities offered in the HELLO.
+fn client_capabilities(handshake: &HandshakeConfig) -> Capabilities {
+    let mut capabilities = Capabilities {
+        max_packet_size: handshake.max_packet_size,
+        ..Capabilities::default()
+    };
+    if let Some(versions) = &handshake.versions {
+        capabilities.versions.retain(|v| versions.contains(v));
+    }
+    capabilities
+}
+
+// ============================================================================
+// Connection Setup
+// ============================================================================
+
+/// WebSocket over TLS, or over plain TCP when `use_tls` is off, compressed
+/// when the server accepts permessage-deflate.
+type ClientStream = WebSocketStream<DeflateStream<MaybeTlsStream<TcpStream>>>;
+
+/// An established session, ready for packets.
+struct Connection {
+    ws_stream: ClientStream,
+    framing: Framing,
+    ack: HelloAck,
+}
 
+/// Connect over TLS (or plain TCP when configured), upgrade to WebSocket with a
+/// subprotocol and run the capability handshake.
+async fn connect(
+    config: &AddrConfig,
+    handshake: &HandshakeConfig,
+    auth: &AuthConfig,
+    compression: &CompressionConfig,
+) -> Result<Connection> {
     // Connect TCP
     let tcp_stream = TcpStream::connect(config.addr())
         .await
@@ -95,29 +255,304 @@ async fn run_client_session(
 
     info!("[CLIENT] TCP connected to {}", config.addr());
 
-    // TLS handshake
-    let server_name: ServerName<'static> = config
-        .host
-        .clone()
-        .try_into()
-        .map_err(|_| anyhow::anyhow!("Invalid server name: {}", config.host))?;
+    let stream = if config.use_tls {
+        // TLS handshake
+        let tls_connector = TlsConnector::from(load_tls_config(config)?);
+        let server_name: ServerName<'static> = config
+            .host
+            .clone()
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("Invalid server name: {}", config.host))?;
+
+        let tls_stream = tls_connector
+            .connect(server_name, tcp_stream)
+            .await
+            .context("TLS handshake failed")?;
+
+        info!("[CLIENT] TLS handshake complete");
+        MaybeTlsStream::Rustls(tls_stream)
+    } else {
+        warn!("[CLIENT] ⚠️  TLS IS DISABLED: connecting over plaintext ws://");
+        MaybeTlsStream::Plain(tcp_stream)
+    };
 
-    let tls_stream = tls_connector
-        .connect(server_name, tcp_stream)
-        .await
-        .context("TLS handshake failed")?;
+    // WebSocket handshake, offering our subprotocols
+    let ws_url = config.ws_url();
+    let offered = if config.subprotocols.is_empty() {
+        Framing::ALL
+            .iter()
+            .map(|f| f.subprotocol().to_string())
+            .collect()
+    } else {
+        config.subprotocols.clone()
+    };
+    let mut request = ws_url
+        .as_str()
+        .into_client_request()
+        .context("Invalid WebSocket URL")?;
+    request.headers_mut().insert(
+        SEC_WEBSOCKET_PROTOCOL,
+        HeaderValue::from_str(&offered.join(", ")).context("Invalid subprotocol list")?,
+    );
+    if let Some(token) = &auth.token {
+        request.headers_mut().insert(
+            AUTHORIZATION,
+            HeaderValue::from_str(&format!("Bearer {}", token)).context("Invalid AUTH_TOKEN")?,
+        );
+    }
 
-    info!("[CLIENT] TLS handshake complete");
+    if compression.enabled {
+        let offer = DeflateParams::offer(compression.window_bits, compression.context_takeover);
+        request.headers_mut().insert(
+            SEC_WEBSOCKET_EXTENSIONS,
+            HeaderValue::from_str(&offer).context("Invalid extension offer")?,
+        );
+    }
 
-    // WebSocket handshake over TLS stream
-    let ws_url = config.ws_url();
-    let (ws_stream, _response) = tokio_tungstenite::client_async(&ws_url, tls_stream)
-        .await
-        .context("WebSocket handshake failed")?;
+    // Cap messages at the packet size we advertise in the HELLO, as the server does
+    let limit = handshake.max_packet_size as usize;
+    let ws_config = WebSocketConfig::default()
+        .max_message_size(Some(limit))
+        .max_frame_size(Some(limit));
+    let (mut ws_stream, response) = tokio_tungstenite::client_async_with_config(
+        request,
+        DeflateStream::new(stream),
+        Some(ws_config),
+    )
+    .await
+    .context("WebSocket handshake failed")?;
+
+    let framing = response
+        .headers()
+        .get(SEC_WEBSOCKET_PROTOCOL)
+        .and_then(|value| value.to_str().ok())
+        .and_then(Framing::from_subprotocol)
+        .context("Server did not select a supported subprotocol")?;
+
+    info!("[CLIENT] Connected to {} ({})", ws_url, framing);
+
+    // The server may only answer an extension we offered
+    if let Some(extensions) = response.headers().get(SEC_WEBSOCKET_EXTENSIONS) {
+        anyhow::ensure!(
+            compression.enabled,
+            "Server selected extensions that were not offered: {:?}",
+            extensions
+        );
+        let extensions = extensions
+            .to_str()
+            .context("Invalid Sec-WebSocket-Extensions header")?;
+        let params = DeflateParams::accept(
+            extensions,
+            compression.window_bits,
+            compression.context_takeover,
+        )?;
+        info!(
+            "[CLIENT] permessage-deflate enabled (windows {}/{} bits)",
+            params.client_max_window_bits, params.server_max_window_bits
+        );
+        ws_stream.get_mut().enable(DeflateCodec::new(
+            params,
+            DeflateRole::Client,
+            compression.max_message_size,
+        ));
+    }
+
+    // Capability handshake
+    let ack = client_handshake(
+        &mut ws_stream,
+        framing,
+        &client_capabilities(handshake),
+        handshake.timeout,
+    )
+    .await?;
+
+    Ok(Connection {
+        ws_stream,
+        framing,
+        ack,
+    })
+}
+
+/// Span for handling one received packet, continuing the trace it was sent in.
+fn packet_span(packet: &Packet) -> Span {
+    let span = info_span!(
+        "receive",
+        packet_type = packet.header.type_name(),
+        urgency = packet.header.urgency.as_str(),
+        len = packet.payload.len()
+    );
+    telemetry::set_remote_parent(&span, packet);
+    span
+}
 
-    info!("[CLIENT] Connected to {}", ws_url);
+/// Decode a data frame and dispatch it through the strategy handler, in a span for the packet.
+///
+/// Returns a packet to send back when the frame calls for one (a heartbeat acknowledgement).
+async fn dispatch_frame(
+    api: &ProtocolApi,
+    handler: &ClientStrategyHandler,
+    framing: Framing,
+    version: u8,
+    msg: &Message,
+) -> Option<Packet> {
+    let packet = match decode_frame(framing, version, msg) {
+        Ok(Some(Inbound::Packet(packet))) => packet,
+        Ok(Some(Inbound::Text(text))) => api.make_packet(&text, Urgency::Green),
+        Ok(None) => return None,
+        Err(e) => {
+            warn!("[CLIENT] Invalid packet format: {}", e);
+            return None;
+        }
+    };
+    let span = packet_span(&packet);
+    dispatch_packet(api, handler, packet).instrument(span).await
+}
+
+/// Act on a decoded packet according to its type.
+async fn dispatch_packet(
+    api: &ProtocolApi,
+    handler: &ClientStrategyHandler,
+    packet: Packet,
+) -> Option<Packet> {
+    match packet.header.packet_type {
+        PACKET_TYPE_HEARTBEAT => match packet.to_heartbeat() {
+            Ok(heartbeat) if !heartbeat.ack => return Some(Packet::heartbeat(&heartbeat.ack())),
+            Ok(_) => {}
+            Err(e) => warn!("[CLIENT] Invalid heartbeat: {}", e),
+        },
+        PACKET_TYPE_TRACK => match packet.to_track() {
+            Ok(update) => info!(
+                "[CLIENT] 📡 Drone track #{}: lat={:.4}, lon={:.4}",
+                update.seq, update.lat, update.lon
+            ),
+            Err(e) => warn!("[CLIENT] Invalid track update: {}", e),
+        },
+        PACKET_TYPE_ERROR => match packet.to_error_notice() {
+            Ok(notice) => warn!(
+                "[CLIENT] Server reported {}: {}",
+                notice.code, notice.message
+            ),
+            Err(e) => warn!("[CLIENT] Invalid error notice: {}", e),
+        },
+        PACKET_TYPE_ADMIN => match packet.to_admin_response() {
+            Ok(AdminResponse::Sessions(sessions)) => {
+                info!("[CLIENT] {} session(s) connected", sessions.len());
+                for s in sessions {
+                    let who = s.token_subject.as_deref().or(s.identity.as_deref());
+                    info!(
+                        "  #{} {} {} [{}] {} v{} in {}B/{}p out {}B/{}p subs {:?}",
+                        s.id,
+                        s.peer,
+                        s.endpoint,
+                        who.unwrap_or("anonymous"),
+                        s.subprotocol,
+                        s.version,
+                        s.bytes_in,
+                        s.packets_in,
+                        s.bytes_out,
+                        s.packets_out,
+                        s.subscriptions
+                    );
+                }
+            }
+            Ok(AdminResponse::Session(s)) => info!(
+                "[CLIENT] Session {}",
+                serde_json::to_string_pretty(&s).unwrap_or_default()
+            ),
+            Ok(AdminResponse::Cluster(nodes)) => {
+                info!("[CLIENT] {} node(s) in the cluster", nodes.len());
+                for node in nodes {
+                    let state = match (node.local, node.connected) {
+                        (true, _) => "this node",
+                        (false, true) => "linked",
+                        (false, false) => "unreachable",
+                    };
+                    let sessions: Vec<String> = node
+                        .sessions
+                        .iter()
+                        .map(|s| format!("#{} {}", s.id, s.who.as_deref().unwrap_or("anonymous")))
+                        .collect();
+                    info!("  {} ({}) sessions {:?}", node.node, state, sessions);
+                }
+            }
+            Ok(AdminResponse::Done(message)) => info!("[CLIENT] Admin: {}", message),
+            Ok(AdminResponse::Error(message)) => warn!("[CLIENT] Admin error: {}", message),
+            Err(e) => warn!("[CLIENT] Invalid admin response: {}", e),
+        },
+        _ => {
+            if let Some(origin) = packet.origin() {
+                info!(
+                    "[CLIENT] 📣 Alert {} raised by {} (session {})",
+                    origin.id, origin.who, origin.session
+                );
+            }
+            api.dispatch(&packet, handler)
+                .instrument(info_span!("dispatch"))
+                .await
+        }
+    }
+    None
+}
+
+/// Parse the arguments of an `!admin` command.
+fn parse_admin_command(args: &str) -> Result<AdminRequest, String> {
+    let mut parts = args.splitn(3, ' ');
+    let command = parts.next().unwrap_or_default();
+    let mut session = || -> Result<u64, String> {
+        parts
+            .next()
+            .and_then(|id| id.parse().ok())
+            .ok_or_else(|| format!("usage: !admin {} <session> ...", command))
+    };
+
+    match command {
+        "list" => Ok(AdminRequest::List),
+        "cluster" => Ok(AdminRequest::Cluster),
+        "inspect" => Ok(AdminRequest::Inspect {
+            session: session()?,
+        }),
+        "kick" => {
+            let session = session()?;
+            Ok(AdminRequest::Kick {
+                session,
+                reason: parts.next().map(str::to_string),
+            })
+        }
+        "send" => {
+            let session = session()?;
+            Ok(AdminRequest::Send {
+                session,
+                message: parts.next().unwrap_or_default().to_string(),
+                urgency: Urgency::Green.as_str().to_string(),
+            })
+        }
+        _ => Err(
+            "usage: !admin list | cluster | inspect <id> | kick <id> [reason] | send <id> <msg>"
+                .into(),
+        ),
+    }
+}
+
+// ============================================================================
+// WebSocket Client Session
+// ============================================================================
+
+async fn run_client_session(
+    config: AddrConfig,
+    handshake: HandshakeConfig,
+    heartbeat: HeartbeatConfig,
+    auth: AuthConfig,
+    compression: CompressionConfig,
+    initial_message: &str,
+) -> Result<()> {
+    let Connection {
+        ws_stream,
+        framing,
+        ack,
+    } = connect(&config, &handshake, &auth, &compression).await?;
 
     let (mut ws_sink, mut ws_source) = ws_stream.split();
+    let traffic = Traffic::default();
 
     let handler = ClientStrategyHandler;
     let api = ProtocolApi::new();
@@ -130,21 +565,45 @@ async fn run_client_session(
 
     info!("[CLIENT] Sent initial message: {}", initial_message);
 
-    // Read loop
-    while let Some(msg_result) = ws_source.next().await {
-        match msg_result {
-            Ok(Message::Text(text)) => {
-                let packet = api.make_packet(&text, Urgency::Green);
-                api.dispatch(&packet, &handler).await;
+    let mut link = LinkMonitor::new(
+        heartbeat.ping_interval,
+        heartbeat.pong_timeout,
+        Instant::now(),
+    );
+    let mut link_event = LinkEvent::Closed;
+    handler.on_link_event(&LinkEvent::Up).await;
+
+    // Read loop, pinging the server and giving up on it once it goes silent
+    loop {
+        let msg_result = tokio::select! {
+            msg_result = ws_source.next() => match msg_result {
+                Some(msg_result) => msg_result,
+                None => break,
+            },
+            _ = sleep_until_deadline(link.next_ping()) => {
+                let heartbeat = link.ping(Instant::now()).with_version(ack.version);
+                if ws_sink.send(Message::Ping(Default::default())).await.is_err()
+                    || send_packet(&mut ws_sink, framing, heartbeat, &traffic).await.is_err()
+                {
+                    break;
+                }
+                continue;
             }
-            Ok(Message::Binary(data)) => {
-                match Packet::from_bytes(&data) {
-                    Ok(packet) => {
-                        api.dispatch(&packet, &handler).await;
-                    }
-                    Err(e) => {
-                        warn!("[CLIENT] Invalid packet format: {}", e);
-                    }
+            _ = sleep_until_deadline(link.dead_after()) => {
+                link_event = LinkEvent::Lost { silent_for: link.silent_for(Instant::now()) };
+                break;
+            }
+        };
+        link.record_activity(Instant::now());
+
+        match msg_result {
+            Ok(msg @ (Message::Text(_) | Message::Binary(_))) => {
+                traffic.record_in(msg.len(), 1);
+                if let Some(reply) =
+                    dispatch_frame(&api, &handler, framing, ack.version, &msg).await
+                {
+                    let reply = reply.with_version(ack.version);
+                    let _ = send_packet(&mut ws_sink, framing, reply, &traffic).await;
                 }
             }
             Ok(Message::Ping(data)) => {
@@ -163,7 +622,9 @@ async fn run_client_session(
         }
     }
 
+    handler.on_link_event(&link_event).await;
     info!("[CLIENT] Connection closed");
+    log_traffic(&traffic);
     Ok(())
 }
 
@@ -171,66 +632,110 @@ async fn run_client_session(
 // Interactive Client Mode
 // ============================================================================
 
-async fn run_interactive_client(config: AddrConfig) -> Result<()> {
-    let tls_config = load_tls_config(&config)?;
-    let tls_connector = TlsConnector::from(tls_config);
-
-    // Connect TCP
-    let tcp_stream = TcpStream::connect(config.addr())
-        .await
-        .with_context(|| format!("Failed to connect to {}", config.addr()))?;
-
-    // TLS handshake
-    let server_name: ServerName<'static> = config
-        .host
-        .clone()
-        .try_into()
-        .map_err(|_| anyhow::anyhow!("Invalid server name: {}", config.host))?;
-
-    let tls_stream = tls_connector
-        .connect(server_name, tcp_stream)
-        .await
-        .context("TLS handshake failed")?;
-
-    // WebSocket handshake
-    let ws_url = config.ws_url();
-    let (ws_stream, _) = tokio_tungstenite::client_async(&ws_url, tls_stream)
-        .await
-        .context("WebSocket handshake failed")?;
+async fn run_interactive_client(
+    config: AddrConfig,
+    batch: BatchConfig,
+    handshake: HandshakeConfig,
+    heartbeat: HeartbeatConfig,
+    auth: AuthConfig,
+    compression: CompressionConfig,
+) -> Result<()> {
+    let Connection {
+        ws_stream,
+        framing,
+        ack,
+    } = connect(&config, &handshake, &auth, &compression).await?;
 
-    info!("[CLIENT] Connected to {}", ws_url);
     info!("[CLIENT] Type messages to send. Commands:");
     info!("  !red <msg>    - Send RED urgency packet");
     info!("  !yellow <msg> - Send YELLOW urgency packet");
+    info!("  @<topic> <msg> - Publish to a topic (after !red / !yellow too)");
+    info!("  !sub <filter>... / !unsub <filter>... - Manage topic subscriptions");
+    info!("  !admin list | cluster | inspect <id> | kick <id> [reason] | send <id> <msg>");
     info!("  !quit         - Exit");
 
-    let (mut ws_sink, mut ws_source) = ws_stream.split();
+    let (ws_sink, mut ws_source) = ws_stream.split();
 
     let handler = ClientStrategyHandler;
     let api = ProtocolApi::new();
 
-    // Spawn reader task
-    let reader_handle = tokio::spawn(async move {
-        while let Some(msg_result) = ws_source.next().await {
-            match msg_result {
-                Ok(Message::Text(text)) => {
-                    let packet = api.make_packet(&text, Urgency::Green);
-                    api.dispatch(&packet, &handler).await;
-                }
-                Ok(Message::Binary(data)) => {
-                    if let Ok(packet) = Packet::from_bytes(&data) {
-                        api.dispatch(&packet, &handler).await;
+    // Spawn writer task so GREEN packets typed in quick succession share a frame.
+    // Batches are binary containers, so JSON sessions never batch.
+    let (out_tx, out_rx) = mpsc::channel(256);
+    let coalescer = if ack.has_feature(FEATURE_BATCH) && framing == Framing::Binary {
+        BatchCoalescer::new(
+            batch.window,
+            batch.max_packets,
+            batch.max_bytes.min(ack.max_packet_size as usize),
+        )
+    } else {
+        BatchCoalescer::disabled()
+    };
+    let traffic = Arc::new(Traffic::default());
+    let writer_handle = tokio::spawn(
+        run_writer(
+            ws_sink,
+            out_rx,
+            coalescer,
+            ack.version,
+            framing,
+            traffic.clone(),
+        )
+        .in_current_span(),
+    );
+
+    // Spawn reader task, which also pings the server and gives up on it once it goes silent
+    let reader_tx = out_tx.clone();
+    let reader_traffic = traffic.clone();
+    let reader_handle = tokio::spawn(
+        async move {
+            let mut link = LinkMonitor::new(heartbeat.ping_interval, heartbeat.pong_timeout, Instant::now());
+            let mut link_event = LinkEvent::Closed;
+            handler.on_link_event(&LinkEvent::Up).await;
+
+            loop {
+                let msg_result = tokio::select! {
+                    msg_result = ws_source.next() => match msg_result {
+                        Some(msg_result) => msg_result,
+                        None => break,
+                    },
+                    _ = sleep_until_deadline(link.next_ping()) => {
+                        let heartbeat = link.ping(Instant::now());
+                        let ping = reader_tx.send(Outbound::Message(Message::Ping(Default::default()))).await;
+                        if ping.is_err() || reader_tx.send(Outbound::Packet(heartbeat)).await.is_err() {
+                            break;
+                        }
+                        continue;
                     }
+                    _ = sleep_until_deadline(link.dead_after()) => {
+                        link_event = LinkEvent::Lost { silent_for: link.silent_for(Instant::now()) };
+                        break;
+                    }
+                };
+                link.record_activity(Instant::now());
+
+                match msg_result {
+                    Ok(msg @ (Message::Text(_) | Message::Binary(_))) => {
+                        reader_traffic.record_in(msg.len(), 1);
+                        if let Some(reply) = dispatch_frame(&api, &handler, framing, ack.version, &msg).await {
+                            let _ = reader_tx.send(Outbound::Packet(reply)).await;
+                        }
+                    }
+                    Ok(Message::Close(frame)) => {
+                        info!("[CLIENT] Server closed connection: {:?}", frame);
+                        break;
+                    }
+                    Err(e) => {
+                        error!("[CLIENT] Read error: {}", e);
+                        break;
+                    }
+                    _ => {}
                 }
-                Ok(Message::Close(_)) => break,
-                Err(e) => {
-                    error!("[CLIENT] Read error: {}", e);
-                    break;
-                }
-                _ => {}
             }
+            handler.on_link_event(&link_event).await;
         }
-    });
+        .in_current_span(),
+    );
 
     // Stdin reader loop
     let stdin = tokio::io::stdin();
@@ -250,10 +755,42 @@ async fn run_interactive_client(config: AddrConfig) -> Result<()> {
 
                 if trimmed == "!quit" {
                     info!("[CLIENT] Exiting...");
-                    let _ = ws_sink.send(Message::Close(None)).await;
+                    let _ = out_tx.send(Outbound::Message(Message::Close(None))).await;
                     break;
                 }
 
+                let subscription = if let Some(rest) = trimmed.strip_prefix("!sub ") {
+                    Some(Packet::subscribe(
+                        &rest.split_whitespace().collect::<Vec<_>>(),
+                    ))
+                } else {
+                    trimmed.strip_prefix("!unsub ").map(|rest| {
+                        Packet::unsubscribe(&rest.split_whitespace().collect::<Vec<_>>())
+                    })
+                };
+                if let Some(args) = trimmed.strip_prefix("!admin ") {
+                    match parse_admin_command(args) {
+                        Ok(request) => {
+                            let packet = Packet::admin_request(&request);
+                            if out_tx.send(Outbound::Packet(packet)).await.is_err() {
+                                error!("[CLIENT] Send error: writer closed");
+                                break;
+                            }
+                        }
+                        Err(usage) => warn!("[CLIENT] {}", usage),
+                    }
+                    continue;
+                }
+
+                if let Some(packet) = subscription {
+                    info!("[CLIENT] Updating subscriptions: {}", trimmed);
+                    if out_tx.send(Outbound::Packet(packet)).await.is_err() {
+                        error!("[CLIENT] Send error: writer closed");
+                        break;
+                    }
+                    continue;
+                }
+
                 let (urgency, msg) = if let Some(rest) = trimmed.strip_prefix("!red ") {
                     (Urgency::Red, rest)
                 } else if let Some(rest) = trimmed.strip_prefix("!yellow ") {
@@ -262,16 +799,34 @@ async fn run_interactive_client(config: AddrConfig) -> Result<()> {
                     (Urgency::Green, trimmed)
                 };
 
-                let packet = Packet::new(msg, urgency);
-                info!(
-                    "[CLIENT] Sending {} packet: {}",
-                    urgency.as_str(),
-                    msg
-                );
+                // "@<topic> <message>" publishes to a topic instead of echoing
+                let (topic, msg) = match msg.strip_prefix('@') {
+                    Some(rest) => match rest.split_once(' ') {
+                        Some((topic, msg)) => (Some(topic), msg),
+                        None => (Some(rest), ""),
+                    },
+                    None => (None, msg),
+                };
 
-                // Send as binary protocol packet
-                if let Err(e) = ws_sink.send(Message::Binary(packet.to_bytes().into())).await {
-                    error!("[CLIENT] Send error: {}", e);
+                let mut packet = Packet::new(msg, urgency);
+                if let Some(topic) = topic {
+                    if framing == Framing::Binary && ack.version < PROTOCOL_VERSION_2 {
+                        warn!("[CLIENT] Topics need protocol v2, sending without topic");
+                    } else {
+                        packet = match packet.with_topic(topic) {
+                            Ok(packet) => packet,
+                            Err(e) => {
+                                warn!("[CLIENT] {}", e);
+                                continue;
+                            }
+                        };
+                    }
+                }
+                info!("[CLIENT] Sending {} packet: {}", urgency.as_str(), msg);
+
+                // Send as protocol packet in the negotiated framing
+                if out_tx.send(Outbound::Packet(packet)).await.is_err() {
+                    error!("[CLIENT] Send error: writer closed");
                     break;
                 }
             }
@@ -282,33 +837,101 @@ async fn run_interactive_client(config: AddrConfig) -> Result<()> {
         }
     }
 
+    // Flush pending packets before tearing down the reader
+    drop(out_tx);
+    if let Ok(Err(e)) = writer_handle.await {
+        error!("[CLIENT] Send error: {}", e);
+    }
+
     reader_handle.abort();
+    log_traffic(&traffic);
     Ok(())
 }
 
+fn log_traffic(traffic: &Traffic) {
+    info!(
+        "[CLIENT] Sent {} packet(s) in {} bytes, received {} frame(s) in {} bytes",
+        traffic.packets_out(),
+        traffic.bytes_out(),
+        traffic.packets_in(),
+        traffic.bytes_in()
+    );
+}
+
+/// Install the log subscriber, writing text or JSON lines as configured.
+///
+/// Spans are also exported when an OTLP endpoint is configured; the returned
+/// provider flushes them at exit.
+fn init_tracing(log: &LogConfig, trace: &TraceConfig) -> Result<Option<SdkTracerProvider>> {
+    let filter = tracing_subscriber::EnvFilter::from_default_env()
+        .add_directive("ws_client=info".parse().unwrap())
+        .add_directive("tokio_tungstenite=info".parse().unwrap());
+    let fmt = tracing_subscriber::fmt::layer();
+    let fmt = match log.format {
+        LogFormat::Text => fmt.boxed(),
+        LogFormat::Json => fmt
+            .json()
+            .with_current_span(false)
+            .with_span_list(true)
+            .boxed(),
+    };
+    let (otlp, provider) = match telemetry::otlp_layer(trace, "ws-client")? {
+        Some((layer, provider)) => (Some(layer), Some(provider)),
+        None => (None, None),
+    };
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt)
+        .with(otlp)
+        .init();
+    if let Some(url) = trace.traces_url() {
+        info!("Exporting spans to {}", url);
+    }
+    Ok(provider)
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
-    // Initialize tracing
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::from_default_env()
-                .add_directive("ws_client=info".parse().unwrap())
-                .add_directive("tokio_tungstenite=info".parse().unwrap()),
-        )
-        .init();
+    let tracer = init_tracing(&LogConfig::from_env()?, &TraceConfig::from_env())?;
 
-    let config = AddrConfig::from_env_defaults("localhost", 8443);
+    let config = AddrConfig::from_env("localhost", 8443)?;
+    let handshake = HandshakeConfig::from_env()?;
+    let heartbeat = HeartbeatConfig::from_env()?;
+    let auth = AuthConfig::from_env()?;
+    let compression = CompressionConfig::from_env()?;
 
     info!("Starting WebSocket client...");
     info!("  Host: {}", config.host);
     info!("  Port: {}", config.port);
     info!("  CA:   {:?}", config.tls.ca_file);
 
+    // The server's address and endpoint identify the session in client logs
+    let span = info_span!("session", peer = %config.addr(), endpoint = %config.endpoint);
+
     // Check for --interactive flag
     let args: Vec<String> = std::env::args().collect();
-    if args.iter().any(|a| a == "--interactive" || a == "-i") {
-        run_interactive_client(config).await
+    let result = if args.iter().any(|a| a == "--interactive" || a == "-i") {
+        let batch = BatchConfig::from_env()?;
+        run_interactive_client(config, batch, handshake, heartbeat, auth, compression)
+            .instrument(span)
+            .await
     } else {
-        run_client_session(config, "HELLO FROM CLIENT").await
+        run_client_session(
+            config,
+            handshake,
+            heartbeat,
+            auth,
+            compression,
+            "HELLO FROM CLIENT",
+        )
+        .instrument(span)
+        .await
+    };
+
+    if let Some(tracer) = tracer {
+        if let Err(e) = tracer.shutdown() {
+            warn!("Failed to flush spans: {}", e);
+        }
     }
+    result
 }
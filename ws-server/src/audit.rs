@@ -0,0 +1,712 @@
+//! Tamper-evident audit log of critical packets and authorization decisions.
+//!
+//! Every RED / YELLOW packet, every packet or `/events` subscription the
+//! policy denies, every upgrade decision and every token decision on an HTTP
+//! request is appended to a JSON-lines file. With `all_decisions` set, allowed
+//! GREEN packets and `/events` subscriptions are recorded as well, so the log
+//! holds every authorization decision. Payloads are not recorded, only their
+//! SHA-256 hash. Each record carries the SHA-256 hash of the record before it (`prev`) and of
+//! itself (`hash`), so editing, removing or reordering records breaks the
+//! chain. The active file is rotated by size into `<file>.1`, `<file>.2`, ...;
+//! the chain runs on into the new file and every rotated file ends with a
+//! `rotate` record, so a missing or truncated file is detected too.
+//!
+//! Records are handed to a dedicated writer thread over an unbounded channel:
+//! recording never waits on the disk.
+
+use anyhow::{Context, Result};
+use protocol::{Packet, TopicFilter, Urgency};
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::{SystemTime, UNIX_EPOCH};
+use svckit::AuditConfig;
+use tokio::sync::{mpsc, oneshot};
+use tracing::error;
+
+use crate::auth::TokenClaims;
+use crate::registry::SessionHandle;
+
+/// `prev` of the first record in a chain.
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Position of the newest record in a chain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainHead {
+    pub seq: u64,
+    pub hash: String,
+}
+
+impl ChainHead {
+    fn genesis() -> Self {
+        Self {
+            seq: 0,
+            hash: GENESIS.to_string(),
+        }
+    }
+}
+
+enum Command {
+    Record {
+        event: &'static str,
+        ts: u64,
+        fields: Value,
+    },
+    Close(oneshot::Sender<ChainHead>),
+}
+
+/// Handle for appending to the audit log.
+pub struct AuditLog {
+    tx: mpsc::UnboundedSender<Command>,
+    path: PathBuf,
+    all_decisions: bool,
+}
+
+impl AuditLog {
+    /// Open the log named in config and start its writer. Returns `None` when auditing is off.
+    ///
+    /// An existing log is continued from its last record.
+    pub fn open(config: &AuditConfig) -> Result<Option<Self>> {
+        let Some(path) = &config.file else {
+            return Ok(None);
+        };
+        let writer = ChainWriter::open(path, config.rotate_bytes)?;
+        let (tx, rx) = mpsc::unbounded_channel();
+        std::thread::Builder::new()
+            .name("audit-writer".to_string())
+            .spawn(move || writer.run(rx))
+            .context("Failed to start audit writer")?;
+
+        let log = Self {
+            tx,
+            path: path.clone(),
+            all_decisions: config.all_decisions,
+        };
+        log.record("start", json!({ "version": env!("CARGO_PKG_VERSION") }));
+        Ok(Some(log))
+    }
+
+    /// Path of the active log file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Record a packet accepted for dispatch: always when it is RED / YELLOW,
+    /// otherwise only when every decision is recorded.
+    pub fn packet(&self, session: &SessionHandle, who: &str, packet: &Packet) {
+        if !self.all_decisions && !matches!(packet.header.urgency, Urgency::Red | Urgency::Yellow) {
+            return;
+        }
+        let mut fields = packet_fields(session, who, packet);
+        fields["decision"] = json!("allow");
+        self.record("packet", fields);
+    }
+
+    /// Record a packet the authorization policy refused.
+    pub fn denied(&self, session: &SessionHandle, who: &str, packet: &Packet, reason: &str) {
+        let mut fields = packet_fields(session, who, packet);
+        fields["decision"] = json!("deny");
+        fields["reason"] = json!(reason);
+        self.record("packet", fields);
+    }
+
+    /// Record an upgrade that passed every check: token, endpoint roles, session
+    /// limit and subprotocol.
+    pub fn upgrade_allowed(
+        &self,
+        peer: Option<SocketAddr>,
+        endpoint: &str,
+        claims: Option<&TokenClaims>,
+        roles: &[String],
+    ) {
+        self.record(
+            "upgrade",
+            json!({
+                "peer": peer.map(|addr| addr.to_string()),
+                "endpoint": endpoint,
+                "subject": claims.map(|c| c.sub.as_str()),
+                "roles": roles,
+                "decision": "allow",
+            }),
+        );
+    }
+
+    /// Record an upgrade refused by one of those checks.
+    pub fn upgrade_rejected(&self, peer: Option<SocketAddr>, endpoint: &str, reason: &str) {
+        self.record(
+            "upgrade",
+            json!({
+                "peer": peer.map(|addr| addr.to_string()),
+                "endpoint": endpoint,
+                "decision": "deny",
+                "reason": reason,
+            }),
+        );
+    }
+
+    /// Record an `/events` subscription the authorization policy allowed, when
+    /// every decision is recorded.
+    pub fn subscription_allowed(
+        &self,
+        peer: Option<SocketAddr>,
+        roles: &[String],
+        filters: &[TopicFilter],
+    ) {
+        if !self.all_decisions {
+            return;
+        }
+        self.record(
+            "subscribe",
+            json!({
+                "peer": peer.map(|addr| addr.to_string()),
+                "roles": roles,
+                "topics": filters.iter().map(TopicFilter::as_str).collect::<Vec<_>>(),
+                "decision": "allow",
+            }),
+        );
+    }
+
+    /// Record an `/events` subscription the authorization policy refused.
+    pub fn subscription_denied(
+        &self,
+        peer: Option<SocketAddr>,
+        roles: &[String],
+        filters: &[TopicFilter],
+        reason: &str,
+    ) {
+        self.record(
+            "subscribe",
+            json!({
+                "peer": peer.map(|addr| addr.to_string()),
+                "roles": roles,
+                "topics": filters.iter().map(TopicFilter::as_str).collect::<Vec<_>>(),
+                "decision": "deny",
+                "reason": reason,
+            }),
+        );
+    }
+
+    /// Record an HTTP request whose bearer token was accepted (or absent and not required).
+    pub fn request_allowed(
+        &self,
+        peer: Option<SocketAddr>,
+        endpoint: &str,
+        claims: Option<&TokenClaims>,
+    ) {
+        self.record(
+            "http",
+            json!({
+                "peer": peer.map(|addr| addr.to_string()),
+                "endpoint": endpoint,
+                "subject": claims.map(|c| c.sub.as_str()),
+                "roles": claims.map(|c| c.roles.as_slice()),
+                "decision": "allow",
+            }),
+        );
+    }
+
+    /// Record an HTTP request refused for a missing or invalid bearer token.
+    pub fn request_rejected(&self, peer: Option<SocketAddr>, endpoint: &str, reason: &str) {
+        self.record(
+            "http",
+            json!({
+                "peer": peer.map(|addr| addr.to_string()),
+                "endpoint": endpoint,
+                "decision": "deny",
+                "reason": reason,
+            }),
+        );
+    }
+
+    /// Write a `stop` record, flush and sync. Returns the final chain head.
+    pub async fn close(&self) -> Option<ChainHead> {
+        self.record("stop", json!({}));
+        let (reply, head) = oneshot::channel();
+        self.tx.send(Command::Close(reply)).ok()?;
+        head.await.ok()
+    }
+
+    fn record(&self, event: &'static str, fields: Value) {
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64);
+        if self.tx.send(Command::Record { event, ts, fields }).is_err() {
+            error!("[AUDIT] Writer has stopped; dropping {} record", event);
+        }
+    }
+}
+
+fn packet_fields(session: &SessionHandle, who: &str, packet: &Packet) -> Value {
+    let mut fields = json!({
+        "session": session.id,
+        "who": who,
+        "roles": session.roles,
+        "urgency": packet.header.urgency.as_str(),
+        "packet_type": packet.header.type_name(),
+        "size": packet.payload.len(),
+        "payload_sha256": hex(&Sha256::digest(&packet.payload)),
+    });
+    if let Some(topic) = packet.topic() {
+        fields["topic"] = json!(topic);
+    }
+    if let Some(origin) = packet.origin() {
+        fields["alert_id"] = json!(origin.id);
+    }
+    fields
+}
+
+/// Appends records to the active file, rotating it by size.
+struct ChainWriter {
+    path: PathBuf,
+    rotate_bytes: u64,
+    out: BufWriter<File>,
+    written: u64,
+    head: ChainHead,
+}
+
+impl ChainWriter {
+    fn open(path: &Path, rotate_bytes: u64) -> Result<Self> {
+        // Continue the chain from the active file, or from the newest rotated one
+        let head = match last_record(path)? {
+            Some(head) => head,
+            None => match rotated_files(path)?.last() {
+                Some(rotated) => last_record(rotated)?.unwrap_or_else(ChainHead::genesis),
+                None => ChainHead::genesis(),
+            },
+        };
+        let file =
+            open_append(path).with_context(|| format!("Failed to open audit log {:?}", path))?;
+        let written = file.metadata().map_or(0, |meta| meta.len());
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            rotate_bytes,
+            out: BufWriter::new(file),
+            written,
+            head,
+        })
+    }
+
+    fn run(mut self, mut rx: mpsc::UnboundedReceiver<Command>) {
+        while let Some(command) = rx.blocking_recv() {
+            // Write everything already queued before paying for a flush
+            let mut next = Some(command);
+            while let Some(command) = next.take() {
+                match command {
+                    Command::Record { event, ts, fields } => {
+                        if let Err(e) = self.append(event, ts, fields) {
+                            error!(
+                                "[AUDIT] Failed to write {} record to {:?}: {}",
+                                event, self.path, e
+                            );
+                        }
+                    }
+                    Command::Close(reply) => {
+                        self.sync();
+                        let _ = reply.send(self.head.clone());
+                        return;
+                    }
+                }
+                next = rx.try_recv().ok();
+            }
+            if let Err(e) = self.out.flush() {
+                error!("[AUDIT] Failed to flush {:?}: {}", self.path, e);
+            }
+        }
+        self.sync();
+    }
+
+    fn append(&mut self, event: &str, ts: u64, fields: Value) -> std::io::Result<()> {
+        let seq = self.head.seq + 1;
+        let mut record = Map::new();
+        record.insert("seq".to_string(), json!(seq));
+        record.insert("ts".to_string(), json!(ts));
+        record.insert("event".to_string(), json!(event));
+        if let Value::Object(fields) = fields {
+            record.extend(fields);
+        }
+        record.insert("prev".to_string(), json!(self.head.hash));
+        let hash = record_hash(&record);
+        record.insert("hash".to_string(), json!(hash));
+
+        let line = Value::Object(record).to_string();
+        writeln!(self.out, "{}", line)?;
+        self.written += line.len() as u64 + 1;
+        self.head = ChainHead { seq, hash };
+
+        if self.rotate_bytes > 0 && self.written >= self.rotate_bytes && event != "rotate" {
+            self.rotate(ts)?;
+        }
+        Ok(())
+    }
+
+    /// Close the active file with a `rotate` record naming where it goes, then start a new one.
+    fn rotate(&mut self, ts: u64) -> std::io::Result<()> {
+        let next = rotated_files(&self.path)
+            .map_err(std::io::Error::other)?
+            .iter()
+            .filter_map(|file| rotated_index(&self.path, file))
+            .max()
+            .unwrap_or(0)
+            + 1;
+        let target = PathBuf::from(format!("{}.{}", self.path.display(), next));
+
+        self.append(
+            "rotate",
+            ts,
+            json!({ "file": target.display().to_string() }),
+        )?;
+        self.out.flush()?;
+        self.out.get_ref().sync_data()?;
+        std::fs::rename(&self.path, &target)?;
+        self.out = BufWriter::new(open_append(&self.path)?);
+        self.written = 0;
+        Ok(())
+    }
+
+    fn sync(&mut self) {
+        let synced = self
+            .out
+            .flush()
+            .and_then(|()| self.out.get_ref().sync_data());
+        if let Err(e) = synced {
+            error!("[AUDIT] Failed to sync {:?}: {}", self.path, e);
+        }
+    }
+}
+
+fn open_append(path: &Path) -> std::io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Hash of a record without its `hash` field, over its canonical JSON encoding.
+fn record_hash(record: &Map<String, Value>) -> String {
+    let encoded = serde_json::to_string(record).unwrap_or_default();
+    hex(&Sha256::digest(encoded.as_bytes()))
+}
+
+/// Index `N` of a rotated file `<active>.N`.
+fn rotated_index(active: &Path, file: &Path) -> Option<u64> {
+    let active = active.file_name()?.to_str()?;
+    file.file_name()?
+        .to_str()?
+        .strip_prefix(active)?
+        .strip_prefix('.')?
+        .parse()
+        .ok()
+}
+
+/// Rotated files of the active log `path`, oldest first.
+fn rotated_files(path: &Path) -> Result<Vec<PathBuf>> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut files: Vec<(u64, PathBuf)> = match std::fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let file = entry.path();
+                rotated_index(path, &file).map(|index| (index, file))
+            })
+            .collect(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e).with_context(|| format!("Failed to list {:?}", dir)),
+    };
+    files.sort();
+    Ok(files.into_iter().map(|(_, file)| file).collect())
+}
+
+/// Head of the chain as of the last record in `path`, if the file has any.
+fn last_record(path: &Path) -> Result<Option<ChainHead>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read audit log {:?}", path)),
+    };
+    let mut last = None;
+    for line in BufReader::new(file).lines() {
+        let line = line.with_context(|| format!("Failed to read audit log {:?}", path))?;
+        if !line.is_empty() {
+            last = Some(line);
+        }
+    }
+    let Some(line) = last else {
+        return Ok(None);
+    };
+
+    let record: Value = serde_json::from_str(&line).ok().unwrap_or_default();
+    match (record["seq"].as_u64(), record["hash"].as_str()) {
+        (Some(seq), Some(hash)) => Ok(Some(ChainHead {
+            seq,
+            hash: hash.to_string(),
+        })),
+        _ => anyhow::bail!(
+            "Audit log {:?} ends with an unreadable record; check it with `ws-server verify-audit`",
+            path
+        ),
+    }
+}
+
+/// Outcome of checking an intact chain.
+#[derive(Debug)]
+pub struct VerifyReport {
+    pub files: usize,
+    pub records: u64,
+    pub head: ChainHead,
+    /// Whether the last record is a `stop`, i.e. the server shut down cleanly.
+    pub clean_stop: bool,
+}
+
+/// Check the chain across `files`, oldest first, from its first record.
+///
+/// Fails at the first record that was altered, removed or reordered, and when
+/// a rotated file is missing or lost its tail.
+pub fn verify(files: &[PathBuf]) -> Result<VerifyReport, String> {
+    let mut head = ChainHead::genesis();
+    let mut last_event = String::new();
+
+    for (index, path) in files.iter().enumerate() {
+        let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        for (number, line) in BufReader::new(file).lines().enumerate() {
+            let at = format!("{}:{}", path.display(), number + 1);
+            let line = line.map_err(|e| format!("{}: {}", at, e))?;
+            let Ok(Value::Object(mut record)) = serde_json::from_str::<Value>(&line) else {
+                return Err(format!(
+                    "{}: not a JSON record (file damaged or truncated)",
+                    at
+                ));
+            };
+
+            let hash = record.remove("hash");
+            let (Some(hash), Some(seq), Some(prev)) = (
+                hash.as_ref().and_then(Value::as_str),
+                record.get("seq").and_then(Value::as_u64),
+                record.get("prev").and_then(Value::as_str),
+            ) else {
+                return Err(format!("{}: record lacks seq, prev or hash", at));
+            };
+
+            if seq != head.seq + 1 {
+                return Err(if head.seq == 0 {
+                    format!(
+                        "{}: chain starts at seq {}; earlier files are missing",
+                        at, seq
+                    )
+                } else {
+                    format!(
+                        "{}: expected seq {}, found {} (records removed or reordered)",
+                        at,
+                        head.seq + 1,
+                        seq
+                    )
+                });
+            }
+            if prev != head.hash {
+                return Err(format!("{}: prev hash does not match seq {}", at, head.seq));
+            }
+            if record_hash(&record) != hash {
+                return Err(format!("{}: hash mismatch, record was altered", at));
+            }
+
+            head = ChainHead {
+                seq,
+                hash: hash.to_string(),
+            };
+            last_event = record
+                .get("event")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_string();
+        }
+
+        if index + 1 < files.len() && last_event != "rotate" {
+            return Err(format!(
+                "{}: does not end with a rotate record (file truncated)",
+                path.display()
+            ));
+        }
+    }
+
+    if head.seq == 0 {
+        return Err("no audit records found".to_string());
+    }
+    Ok(VerifyReport {
+        files: files.len(),
+        records: head.seq,
+        clean_stop: last_event == "stop",
+        head,
+    })
+}
+
+/// `ws-server verify-audit [FILE...]`.
+///
+/// With one file (by default `AUDIT_LOG_FILE`), its rotated files are checked
+/// before it; several files are checked in the order given.
+pub fn verify_command(args: &[String], config: &AuditConfig) -> ExitCode {
+    let files = match args {
+        [] | [_] => {
+            let Some(active) = args
+                .first()
+                .map(PathBuf::from)
+                .or_else(|| config.file.clone())
+            else {
+                eprintln!("usage: ws-server verify-audit [FILE...] (or set AUDIT_LOG_FILE)");
+                return ExitCode::FAILURE;
+            };
+            let mut files = match rotated_files(&active) {
+                Ok(files) => files,
+                Err(e) => {
+                    eprintln!("❌ {:#}", e);
+                    return ExitCode::FAILURE;
+                }
+            };
+            if active.exists() {
+                files.push(active);
+            }
+            files
+        }
+        _ => args.iter().map(PathBuf::from).collect(),
+    };
+
+    match verify(&files) {
+        Ok(report) => {
+            println!(
+                "✅ Audit chain intact: {} record(s) in {} file(s)",
+                report.records, report.files
+            );
+            println!("   head seq {} hash {}", report.head.seq, report.head.hash);
+            if !report.clean_stop {
+                println!(
+                    "   ⚠️  Last record is not a stop record: the server is running, crashed, \
+                     or the log lost its tail. Compare the head with the one the server logged."
+                );
+            }
+            ExitCode::SUCCESS
+        }
+        Err(reason) => {
+            eprintln!("❌ Audit chain broken: {}", reason);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(path: &Path, rotate_bytes: u64, events: &[&'static str]) -> ChainHead {
+        let mut writer = ChainWriter::open(path, rotate_bytes).unwrap();
+        for (i, event) in events.iter().enumerate() {
+            writer.append(event, i as u64, json!({ "n": i })).unwrap();
+        }
+        writer.sync();
+        writer.head
+    }
+
+    fn edit_lines(path: &Path, edit: impl Fn(&mut Vec<String>)) {
+        let text = std::fs::read_to_string(path).unwrap();
+        let mut lines: Vec<String> = text.lines().map(str::to_string).collect();
+        edit(&mut lines);
+        std::fs::write(path, lines.join("\n") + "\n").unwrap();
+    }
+
+    #[test]
+    fn test_chain_continues_and_verifies() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+        write(&path, 0, &["start", "packet", "stop"]);
+        let head = write(&path, 0, &["start", "stop"]);
+        assert_eq!(head.seq, 5);
+
+        let report = verify(std::slice::from_ref(&path)).unwrap();
+        assert_eq!(report.records, 5);
+        assert_eq!(report.head, head);
+        assert!(report.clean_stop);
+
+        write(&path, 0, &["start"]);
+        assert!(!verify(&[path]).unwrap().clean_stop);
+    }
+
+    #[test]
+    fn test_tampering_is_detected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+        write(&path, 0, &["start", "packet", "packet", "stop"]);
+
+        edit_lines(&path, |lines| {
+            lines[1] = lines[1].replace("\"n\":1", "\"n\":7")
+        });
+        assert!(verify(std::slice::from_ref(&path))
+            .unwrap_err()
+            .contains("altered"));
+
+        std::fs::remove_file(&path).unwrap();
+        write(&path, 0, &["start", "packet", "packet", "stop"]);
+        edit_lines(&path, |lines| {
+            lines.remove(1);
+        });
+        assert!(verify(std::slice::from_ref(&path))
+            .unwrap_err()
+            .contains("removed or reordered"));
+
+        std::fs::remove_file(&path).unwrap();
+        write(&path, 0, &["start", "packet", "packet", "stop"]);
+        edit_lines(&path, |lines| {
+            lines.remove(0);
+        });
+        assert!(verify(&[path])
+            .unwrap_err()
+            .contains("earlier files are missing"));
+    }
+
+    #[test]
+    fn test_rotation_keeps_the_chain() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+        write(&path, 200, &["start", "packet", "packet", "packet", "stop"]);
+
+        let rotated = rotated_files(&path).unwrap();
+        assert!(rotated.len() >= 2);
+        let mut files = rotated.clone();
+        files.push(path.clone());
+        let report = verify(&files).unwrap();
+        assert_eq!(report.files, files.len());
+        assert!(report.clean_stop);
+
+        // A missing middle file breaks the seq run; a truncated one loses its rotate record.
+        let mut missing = files.clone();
+        missing.remove(1);
+        assert!(verify(&missing).is_err());
+        edit_lines(&rotated[0], |lines| {
+            lines.pop();
+        });
+        assert!(verify(&files).unwrap_err().contains("file truncated"));
+    }
+
+    #[tokio::test]
+    async fn test_log_close_returns_head() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = AuditConfig {
+            file: Some(dir.path().join("audit.log")),
+            rotate_bytes: 0,
+            ..Default::default()
+        };
+        let log = AuditLog::open(&config).unwrap().unwrap();
+        log.upgrade_rejected(None, "/", "missing bearer token");
+        let head = log.close().await.unwrap();
+        assert_eq!(head.seq, 3);
+
+        let report = verify(&[log.path().to_path_buf()]).unwrap();
+        assert_eq!(report.head, head);
+        assert!(report.clean_stop);
+    }
+}
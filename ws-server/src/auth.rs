@@ -0,0 +1,352 @@
+//! Bearer token authentication for WebSocket upgrades and HTTP requests.
+//!
+//! Tokens are JWTs verified locally against a JSON key set:
+//!
+//! ```json
+//! {"keys": [
+//!   {"kid": "ops", "alg": "HS256", "secret": "..."},
+//!   {"kid": "fleet", "alg": "EdDSA", "public_key_pem": "-----BEGIN PUBLIC KEY-----..."}
+//! ]}
+//! ```
+//!
+//! A token naming a `kid` is checked against that key only; one without a
+//! `kid` is tried against every key for its algorithm.
+
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use std::path::Path;
+use svckit::AuthConfig;
+use tokio_tungstenite::tungstenite::http::header::AUTHORIZATION;
+use tokio_tungstenite::tungstenite::http::Request;
+
+/// Query parameter carrying the token for clients that cannot set headers.
+pub const TOKEN_QUERY_PARAM: &str = "access_token";
+
+/// Claims of a verified token, attached to the session.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TokenClaims {
+    /// Subject the token was issued to.
+    pub sub: String,
+    #[serde(default)]
+    pub roles: Vec<String>,
+    /// Expiry as seconds since the Unix epoch.
+    pub exp: u64,
+}
+
+#[derive(Deserialize)]
+struct KeySetFile {
+    keys: Vec<KeyEntry>,
+}
+
+#[derive(Deserialize)]
+struct KeyEntry {
+    #[serde(default)]
+    kid: Option<String>,
+    alg: String,
+    #[serde(default)]
+    secret: Option<String>,
+    #[serde(default)]
+    public_key_pem: Option<String>,
+}
+
+struct VerificationKey {
+    kid: Option<String>,
+    algorithm: Algorithm,
+    key: DecodingKey,
+}
+
+/// Verifies tokens presented on upgrade and HTTP requests.
+pub struct TokenVerifier {
+    keys: Vec<VerificationKey>,
+    required: bool,
+    issuer: Option<String>,
+    audience: Option<String>,
+}
+
+impl TokenVerifier {
+    /// Load the key set named by the config. Returns `None` when no key set is configured.
+    pub fn from_config(config: &AuthConfig) -> anyhow::Result<Option<Self>> {
+        let Some(path) = &config.keys_file else {
+            if config.required {
+                anyhow::bail!("AUTH_REQUIRED is set but no AUTH_KEYS_FILE is configured");
+            }
+            return Ok(None);
+        };
+        Ok(Some(Self {
+            keys: load_keys(path)?,
+            required: config.required,
+            issuer: config.issuer.clone(),
+            audience: config.audience.clone(),
+        }))
+    }
+
+    /// Number of keys in the key set.
+    pub fn key_count(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Whether requests must carry a token unless an endpoint says otherwise.
+    pub fn required(&self) -> bool {
+        self.required
+    }
+
+    /// Authenticate an upgrade or HTTP request.
+    ///
+    /// Returns `Ok(None)` for a request without a token when `required` is
+    /// off. A token that is present is always verified.
+    pub fn authenticate<B>(
+        &self,
+        request: &Request<B>,
+        required: bool,
+    ) -> Result<Option<TokenClaims>, String> {
+        match bearer_token(request)? {
+            Some(token) => self.verify(&token).map(Some),
+            None if required => Err("missing bearer token".to_string()),
+            None => Ok(None),
+        }
+    }
+
+    /// Verify a token's signature, expiry and, if configured, issuer and audience.
+    pub fn verify(&self, token: &str) -> Result<TokenClaims, String> {
+        let header =
+            jsonwebtoken::decode_header(token).map_err(|e| format!("malformed token: {}", e))?;
+
+        let candidates: Vec<_> = self
+            .keys
+            .iter()
+            .filter(|key| key.algorithm == header.alg)
+            .filter(|key| header.kid.is_none() || key.kid == header.kid)
+            .collect();
+        if candidates.is_empty() {
+            return Err(match &header.kid {
+                Some(kid) => format!("no {:?} key with kid {:?}", header.alg, kid),
+                None => format!("no key for algorithm {:?}", header.alg),
+            });
+        }
+
+        // A configured issuer or audience must be present, not merely consistent.
+        let mut required = vec!["exp", "sub"];
+        let mut validation = Validation::new(header.alg);
+        match &self.audience {
+            Some(audience) => {
+                validation.set_audience(&[audience]);
+                required.push("aud");
+            }
+            None => validation.validate_aud = false,
+        }
+        if let Some(issuer) = &self.issuer {
+            validation.set_issuer(&[issuer]);
+            required.push("iss");
+        }
+        validation.set_required_spec_claims(&required);
+
+        let mut last_error = None;
+        for key in candidates {
+            match jsonwebtoken::decode::<TokenClaims>(token, &key.key, &validation) {
+                Ok(data) => return Ok(data.claims),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(format!(
+            "invalid token: {}",
+            last_error.map_or_else(String::new, |e| e.to_string())
+        ))
+    }
+}
+
+/// Token from `Authorization: Bearer ...`, else from the `access_token` query parameter.
+fn bearer_token<B>(request: &Request<B>) -> Result<Option<String>, String> {
+    if let Some(value) = request.headers().get(AUTHORIZATION) {
+        let value = value
+            .to_str()
+            .map_err(|_| "unreadable Authorization header".to_string())?;
+        return match value.split_once(' ') {
+            Some((scheme, token)) if scheme.eq_ignore_ascii_case("bearer") => {
+                Ok(Some(token.trim().to_string()))
+            }
+            _ => Err("Authorization header is not a bearer token".to_string()),
+        };
+    }
+
+    let token = request.uri().query().and_then(|query| {
+        query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(name, _)| *name == TOKEN_QUERY_PARAM)
+            .map(|(_, value)| value.to_string())
+    });
+    Ok(token.filter(|token| !token.is_empty()))
+}
+
+fn load_keys(path: &Path) -> anyhow::Result<Vec<VerificationKey>> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read key set {:?}: {}", path, e))?;
+    let file: KeySetFile = serde_json::from_str(&text)
+        .map_err(|e| anyhow::anyhow!("Invalid key set {:?}: {}", path, e))?;
+
+    let mut keys = Vec::with_capacity(file.keys.len());
+    for (index, entry) in file.keys.into_iter().enumerate() {
+        let name = entry.kid.clone().unwrap_or_else(|| format!("#{}", index));
+        let (algorithm, key) = match entry.alg.as_str() {
+            "HS256" => {
+                let secret = entry
+                    .secret
+                    .ok_or_else(|| anyhow::anyhow!("Key {} (HS256) has no secret", name))?;
+                (
+                    Algorithm::HS256,
+                    DecodingKey::from_secret(secret.as_bytes()),
+                )
+            }
+            "EdDSA" => {
+                let pem = entry
+                    .public_key_pem
+                    .ok_or_else(|| anyhow::anyhow!("Key {} (EdDSA) has no public_key_pem", name))?;
+                let key = DecodingKey::from_ed_pem(pem.as_bytes()).map_err(|e| {
+                    anyhow::anyhow!("Key {} has an invalid public key: {}", name, e)
+                })?;
+                (Algorithm::EdDSA, key)
+            }
+            other => anyhow::bail!("Key {} uses unsupported algorithm {:?}", name, other),
+        };
+        keys.push(VerificationKey {
+            kid: entry.kid,
+            algorithm,
+            key,
+        });
+    }
+    if keys.is_empty() {
+        anyhow::bail!("Key set {:?} contains no keys", path);
+    }
+    Ok(keys)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{EncodingKey, Header};
+    use std::io::Write as _;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    const KEYS: &str = r#"{"keys": [
+        {"kid": "ops", "alg": "HS256", "secret": "ops-secret"},
+        {"kid": "fleet", "alg": "HS256", "secret": "fleet-secret"}
+    ]}"#;
+
+    fn verifier(issuer: Option<&str>, audience: Option<&str>) -> TokenVerifier {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(KEYS.as_bytes()).unwrap();
+        let config = AuthConfig {
+            keys_file: Some(file.path().to_path_buf()),
+            issuer: issuer.map(str::to_string),
+            audience: audience.map(str::to_string),
+            ..AuthConfig::default()
+        };
+        TokenVerifier::from_config(&config).unwrap().unwrap()
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    fn sign(kid: Option<&str>, secret: &str, claims: serde_json::Value) -> String {
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = kid.map(str::to_string);
+        jsonwebtoken::encode(
+            &header,
+            &claims,
+            &EncodingKey::from_secret(secret.as_bytes()),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_key_selection() {
+        let verifier = verifier(None, None);
+        assert_eq!(verifier.key_count(), 2);
+        let claims = serde_json::json!({"sub": "uav-7", "roles": ["pilot"], "exp": now() + 60});
+
+        let verified = verifier
+            .verify(&sign(Some("fleet"), "fleet-secret", claims.clone()))
+            .unwrap();
+        assert_eq!(verified.sub, "uav-7");
+        assert_eq!(verified.roles, vec!["pilot"]);
+
+        // Without a kid every HS256 key is tried.
+        assert!(verifier
+            .verify(&sign(None, "ops-secret", claims.clone()))
+            .is_ok());
+        // A kid pins the key, even when another key would accept the signature.
+        assert!(verifier
+            .verify(&sign(Some("ops"), "fleet-secret", claims.clone()))
+            .is_err());
+        assert!(verifier
+            .verify(&sign(Some("other"), "ops-secret", claims.clone()))
+            .unwrap_err()
+            .contains("no HS256 key"));
+
+        // {"alg":"EdDSA","typ":"JWT"}, with no EdDSA key in the set.
+        let eddsa = "eyJhbGciOiJFZERTQSIsInR5cCI6IkpXVCJ9.e30.c2ln";
+        assert!(verifier
+            .verify(eddsa)
+            .unwrap_err()
+            .contains("no key for algorithm"));
+    }
+
+    #[test]
+    fn test_required_claims() {
+        let verifier = verifier(None, None);
+        let expired = serde_json::json!({"sub": "uav-7", "exp": now() - 3600});
+        assert!(verifier.verify(&sign(None, "ops-secret", expired)).is_err());
+        let no_sub = serde_json::json!({"exp": now() + 60});
+        assert!(verifier.verify(&sign(None, "ops-secret", no_sub)).is_err());
+        let no_exp = serde_json::json!({"sub": "uav-7"});
+        assert!(verifier.verify(&sign(None, "ops-secret", no_exp)).is_err());
+    }
+
+    #[test]
+    fn test_issuer_and_audience() {
+        let verifier = verifier(Some("c2"), Some("fleet"));
+        let claims = |iss: &str, aud: &str| serde_json::json!({"sub": "uav-7", "exp": now() + 60, "iss": iss, "aud": aud});
+        assert!(verifier
+            .verify(&sign(None, "ops-secret", claims("c2", "fleet")))
+            .is_ok());
+        assert!(verifier
+            .verify(&sign(None, "ops-secret", claims("other", "fleet")))
+            .is_err());
+        assert!(verifier
+            .verify(&sign(None, "ops-secret", claims("c2", "other")))
+            .is_err());
+        let missing = serde_json::json!({"sub": "uav-7", "exp": now() + 60});
+        assert!(verifier.verify(&sign(None, "ops-secret", missing)).is_err());
+    }
+
+    #[test]
+    fn test_bearer_token_sources() {
+        let verifier = verifier(None, None);
+        let token = sign(
+            None,
+            "ops-secret",
+            serde_json::json!({"sub": "uav-7", "exp": now() + 60}),
+        );
+
+        let header = Request::builder()
+            .uri("/")
+            .header(AUTHORIZATION, format!("Bearer {}", token))
+            .body(())
+            .unwrap();
+        assert!(verifier.authenticate(&header, true).unwrap().is_some());
+
+        let query = Request::builder()
+            .uri(format!("/?{}={}", TOKEN_QUERY_PARAM, token))
+            .body(())
+            .unwrap();
+        assert!(verifier.authenticate(&query, true).unwrap().is_some());
+
+        let anonymous = Request::builder().uri("/").body(()).unwrap();
+        assert!(verifier.authenticate(&anonymous, false).unwrap().is_none());
+        assert!(verifier.authenticate(&anonymous, true).is_err());
+    }
+}
@@ -0,0 +1,269 @@
+//! Cluster backplane: carries published packets and session presence between nodes.
+//!
+//! Each node hands its local events to a [`Backplane`] and receives every
+//! node's events from it, its own included, so consumers skip messages whose
+//! `node` is their own. [`MemoryBackplane`] links nodes inside one process and
+//! is what a standalone server runs on; [`crate::mesh::TcpMesh`] links server
+//! processes over TCP.
+
+use protocol::{NodeInfo, Packet, RemoteSession, PROTOCOL_VERSION_2};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+
+use crate::registry::SessionId;
+
+/// Messages a subscriber may fall behind by before it starts missing them.
+pub const EVENT_BUFFER: usize = 1024;
+
+/// Something that happened on one node that the others need to know.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ClusterEvent {
+    /// A topic packet was published; route it to local subscribers.
+    Publish { packet: Vec<u8> },
+    /// An alert was raised; rebroadcast it to local operator sessions.
+    Alert { packet: Vec<u8> },
+    /// A session registered.
+    SessionUp {
+        session: SessionId,
+        who: Option<String>,
+    },
+    /// A session ended.
+    SessionDown { session: SessionId },
+    /// The link to the node dropped, taking its sessions out of view.
+    /// Raised locally by the backplane, never sent.
+    NodeDown,
+}
+
+impl ClusterEvent {
+    /// A publish event for a packet.
+    pub fn publish(packet: &Packet) -> Self {
+        Self::Publish {
+            packet: relay_bytes(packet),
+        }
+    }
+
+    /// An alert event for a packet.
+    pub fn alert(packet: &Packet) -> Self {
+        Self::Alert {
+            packet: relay_bytes(packet),
+        }
+    }
+}
+
+/// Encode a packet for the backplane. The link between nodes always speaks v2,
+/// so the topic, origin and other extensions of packets from v1 sessions survive.
+fn relay_bytes(packet: &Packet) -> Vec<u8> {
+    packet.clone().with_version(PROTOCOL_VERSION_2).to_bytes()
+}
+
+/// A [`ClusterEvent`] with the node it happened on.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClusterMessage {
+    pub node: String,
+    #[serde(flatten)]
+    pub event: ClusterEvent,
+}
+
+/// Transport between the nodes of a cluster.
+pub trait Backplane: Send + Sync {
+    /// This node's name.
+    fn node_id(&self) -> &str;
+
+    /// Send an event to every node. Never waits on the network; events for a
+    /// peer that cannot keep up are dropped.
+    fn broadcast(&self, event: ClusterEvent);
+
+    /// Receive events from every node, this one included.
+    fn subscribe(&self) -> broadcast::Receiver<ClusterMessage>;
+
+    /// Peers this node links to, each with whether the link is up.
+    fn peers(&self) -> Vec<(String, bool)>;
+}
+
+/// Nodes in one process sharing a broadcast channel.
+#[derive(Clone)]
+pub struct MemoryHub {
+    tx: broadcast::Sender<ClusterMessage>,
+}
+
+impl Default for MemoryHub {
+    fn default() -> Self {
+        Self {
+            tx: broadcast::Sender::new(EVENT_BUFFER),
+        }
+    }
+}
+
+impl MemoryHub {
+    /// Attach a node to the hub.
+    pub fn join(&self, node_id: impl Into<String>) -> MemoryBackplane {
+        MemoryBackplane {
+            node_id: node_id.into(),
+            tx: self.tx.clone(),
+        }
+    }
+}
+
+/// One node's attachment to a [`MemoryHub`].
+pub struct MemoryBackplane {
+    node_id: String,
+    tx: broadcast::Sender<ClusterMessage>,
+}
+
+impl Backplane for MemoryBackplane {
+    fn node_id(&self) -> &str {
+        &self.node_id
+    }
+
+    fn broadcast(&self, event: ClusterEvent) {
+        let _ = self.tx.send(ClusterMessage {
+            node: self.node_id.clone(),
+            event,
+        });
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<ClusterMessage> {
+        self.tx.subscribe()
+    }
+
+    fn peers(&self) -> Vec<(String, bool)> {
+        Vec::new()
+    }
+}
+
+/// Sessions on the other nodes, kept up to date from presence events.
+#[derive(Default)]
+pub struct ClusterView {
+    nodes: Mutex<HashMap<String, BTreeMap<SessionId, Option<String>>>>,
+}
+
+impl ClusterView {
+    /// Apply a presence event from another node; other events are ignored.
+    pub fn apply(&self, message: &ClusterMessage) {
+        let mut nodes = self.nodes.lock().unwrap_or_else(|e| e.into_inner());
+        match &message.event {
+            ClusterEvent::SessionUp { session, who } => {
+                nodes
+                    .entry(message.node.clone())
+                    .or_default()
+                    .insert(*session, who.clone());
+            }
+            ClusterEvent::SessionDown { session } => {
+                if let Some(sessions) = nodes.get_mut(&message.node) {
+                    sessions.remove(session);
+                }
+            }
+            ClusterEvent::NodeDown => {
+                nodes.remove(&message.node);
+            }
+            ClusterEvent::Publish { .. } | ClusterEvent::Alert { .. } => {}
+        }
+    }
+
+    /// Every remote node heard from, with its sessions.
+    pub fn nodes(&self) -> Vec<NodeInfo> {
+        let nodes = self.nodes.lock().unwrap_or_else(|e| e.into_inner());
+        let mut infos: Vec<NodeInfo> = nodes
+            .iter()
+            .map(|(node, sessions)| NodeInfo {
+                node: node.clone(),
+                local: false,
+                connected: true,
+                sessions: sessions
+                    .iter()
+                    .map(|(id, who)| RemoteSession {
+                        id: *id,
+                        who: who.clone(),
+                    })
+                    .collect(),
+            })
+            .collect();
+        infos.sort_by(|a, b| a.node.cmp(&b.node));
+        infos
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protocol::{AlertOrigin, PROTOCOL_VERSION};
+
+    fn message(node: &str, event: ClusterEvent) -> ClusterMessage {
+        ClusterMessage {
+            node: node.to_string(),
+            event,
+        }
+    }
+
+    fn up(session: SessionId, who: &str) -> ClusterEvent {
+        ClusterEvent::SessionUp {
+            session,
+            who: Some(who.to_string()),
+        }
+    }
+
+    /// Each node with the ids of its sessions, in order.
+    fn sessions(view: &ClusterView) -> Vec<(String, Vec<SessionId>)> {
+        view.nodes()
+            .into_iter()
+            .map(|info| (info.node, info.sessions.iter().map(|s| s.id).collect()))
+            .collect()
+    }
+
+    #[test]
+    fn test_view_follows_presence() {
+        let view = ClusterView::default();
+        view.apply(&message("c", up(1, "uav-1")));
+        view.apply(&message("b", up(2, "uav-2")));
+        view.apply(&message("b", up(1, "operator-1")));
+        assert_eq!(
+            sessions(&view),
+            vec![("b".to_string(), vec![1, 2]), ("c".to_string(), vec![1])]
+        );
+        let b = &view.nodes()[0];
+        assert!(!b.local && b.connected);
+        assert_eq!(b.sessions[0].who.as_deref(), Some("operator-1"));
+
+        // Traffic events do not touch presence, and unknown sessions are ignored
+        view.apply(&message("d", ClusterEvent::Publish { packet: Vec::new() }));
+        view.apply(&message("d", ClusterEvent::SessionDown { session: 1 }));
+        view.apply(&message("b", ClusterEvent::SessionDown { session: 2 }));
+        assert_eq!(
+            sessions(&view),
+            vec![("b".to_string(), vec![1]), ("c".to_string(), vec![1])]
+        );
+
+        view.apply(&message("b", ClusterEvent::NodeDown));
+        assert_eq!(sessions(&view), vec![("c".to_string(), vec![1])]);
+    }
+
+    #[test]
+    fn test_relayed_packets_keep_extensions() {
+        let origin = AlertOrigin {
+            id: "a-1".into(),
+            session: 3,
+            who: "op-1".into(),
+        };
+        // Packets from a v1 session still hold their extensions in memory
+        let packet = Packet::red("TARGET LOST")
+            .with_topic("uav-7/alerts")
+            .unwrap()
+            .with_origin(&origin)
+            .unwrap()
+            .with_version(PROTOCOL_VERSION);
+
+        for event in [ClusterEvent::publish(&packet), ClusterEvent::alert(&packet)] {
+            let (ClusterEvent::Publish { packet: bytes } | ClusterEvent::Alert { packet: bytes }) =
+                event
+            else {
+                unreachable!();
+            };
+            let relayed = Packet::from_bytes(&bytes).unwrap();
+            assert_eq!(relayed.topic(), Some("uav-7/alerts"));
+            assert_eq!(relayed.origin().as_ref(), Some(&origin));
+        }
+    }
+}
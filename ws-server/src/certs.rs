@@ -0,0 +1,132 @@
+//! Server certificate that can be rotated while the server runs.
+//!
+//! The resolver hands rustls whichever certificate is current at the moment a
+//! TLS handshake starts, so established sessions keep the certificate they
+//! negotiated and only new connections see a replacement. A replacement that
+//! fails to load is rejected and the previous certificate stays in service.
+
+use anyhow::{Context, Result};
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::SystemTime;
+
+/// Serves the certificate in `cert_file` / `key_file`, reloading it on demand.
+#[derive(Debug)]
+pub struct ReloadingCertResolver {
+    cert_file: PathBuf,
+    key_file: PathBuf,
+    provider: Arc<CryptoProvider>,
+    current: RwLock<Arc<CertifiedKey>>,
+    modified: Mutex<(Option<SystemTime>, Option<SystemTime>)>,
+}
+
+impl ReloadingCertResolver {
+    /// Load the certificate and key for the first time.
+    pub fn load(cert_file: &Path, key_file: &Path, provider: Arc<CryptoProvider>) -> Result<Self> {
+        let modified = (modified_time(cert_file), modified_time(key_file));
+        let certified = load_certified_key(cert_file, key_file, &provider)?;
+
+        Ok(Self {
+            cert_file: cert_file.to_path_buf(),
+            key_file: key_file.to_path_buf(),
+            provider,
+            current: RwLock::new(Arc::new(certified)),
+            modified: Mutex::new(modified),
+        })
+    }
+
+    /// Path of the certificate chain.
+    pub fn cert_file(&self) -> &Path {
+        &self.cert_file
+    }
+
+    /// Reload the certificate and key unconditionally, keeping the old pair on error.
+    pub fn reload(&self) -> Result<()> {
+        let modified = (
+            modified_time(&self.cert_file),
+            modified_time(&self.key_file),
+        );
+        *self.modified.lock().unwrap_or_else(|e| e.into_inner()) = modified;
+        self.install()
+    }
+
+    /// Reload if either file's modification time changed.
+    ///
+    /// Returns `Ok(true)` when a new certificate was installed.
+    pub fn reload_if_changed(&self) -> Result<bool> {
+        let modified = (
+            modified_time(&self.cert_file),
+            modified_time(&self.key_file),
+        );
+        let mut last = self.modified.lock().unwrap_or_else(|e| e.into_inner());
+        if modified == *last {
+            return Ok(false);
+        }
+
+        // Record the attempt first so a broken pair is not re-parsed every poll;
+        // a half-written rotation changes the mtimes again when it completes.
+        *last = modified;
+        drop(last);
+        self.install()?;
+        Ok(true)
+    }
+
+    fn install(&self) -> Result<()> {
+        let certified = load_certified_key(&self.cert_file, &self.key_file, &self.provider)?;
+        *self.current.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(certified);
+        Ok(())
+    }
+}
+
+impl ResolvesServerCert for ReloadingCertResolver {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(Arc::clone(
+            &self.current.read().unwrap_or_else(|e| e.into_inner()),
+        ))
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .ok()
+}
+
+/// Load a certificate chain and its private key, checking that they belong together.
+fn load_certified_key(
+    cert_file: &Path,
+    key_file: &Path,
+    provider: &CryptoProvider,
+) -> Result<CertifiedKey> {
+    // Load certificate chain
+    let cert_reader = File::open(cert_file)
+        .with_context(|| format!("Failed to open cert file: {:?}", cert_file))?;
+    let certs: Vec<CertificateDer<'static>> =
+        rustls_pemfile::certs(&mut BufReader::new(cert_reader))
+            .collect::<Result<Vec<_>, _>>()
+            .context("Failed to parse certificates")?;
+
+    if certs.is_empty() {
+        anyhow::bail!("No certificates found in {:?}", cert_file);
+    }
+
+    // Load private key
+    let key_reader =
+        File::open(key_file).with_context(|| format!("Failed to open key file: {:?}", key_file))?;
+    let key: PrivateKeyDer<'static> = rustls_pemfile::private_key(&mut BufReader::new(key_reader))
+        .context("Failed to parse private key")?
+        .ok_or_else(|| anyhow::anyhow!("No private key found in {:?}", key_file))?;
+
+    CertifiedKey::from_der(certs, key, provider).with_context(|| {
+        format!(
+            "Failed to pair certificate {:?} with private key {:?}",
+            cert_file, key_file
+        )
+    })
+}
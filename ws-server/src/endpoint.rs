@@ -0,0 +1,232 @@
+//! WebSocket endpoints: the strategies, limits and access rules of each path.
+//!
+//! Upgrades are only accepted on a configured path; any other gets a 404
+//! during the handshake. Without an endpoint file the server has a single
+//! endpoint at `AddrConfig::endpoint` running every strategy. The file binds
+//! each path to its own set:
+//!
+//! ```json
+//! {
+//!   "endpoints": {
+//!     "/telemetry": { "strategies": ["log"], "max_packet_size": 65536, "max_sessions": 500, "compression": true },
+//!     "/ops": { "strategies": ["log", "drone_stream"], "auth_required": true, "roles": ["operator"], "compression": false },
+//!     "/admin": { "strategies": ["log"], "admin": true, "roles": ["operator"] }
+//!   }
+//! }
+//! ```
+//!
+//! `auth_required` overrides `AUTH_REQUIRED` for the path, `roles` admits only
+//! sessions holding one of the listed policy roles, `compression` overrides
+//! `WS_DEFLATE`, and ADMIN packets are only answered on endpoints with
+//! `"admin": true`, which need an authorization policy to say who administers.
+
+use anyhow::Context;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Server-side strategy handlers an endpoint can bind, by name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    /// Log every packet with who sent it, and lost links.
+    Log,
+    /// Start the drone coordinate stream on RED packets.
+    DroneStream,
+}
+
+impl Strategy {
+    /// Every strategy, in dispatch order.
+    pub const ALL: [Strategy; 2] = [Strategy::Log, Strategy::DroneStream];
+
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "log" => Some(Self::Log),
+            "drone_stream" => Some(Self::DroneStream),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct EndpointFile {
+    endpoints: BTreeMap<String, EndpointEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EndpointEntry {
+    strategies: Vec<String>,
+    #[serde(default)]
+    max_packet_size: Option<u32>,
+    #[serde(default)]
+    max_sessions: Option<usize>,
+    #[serde(default)]
+    auth_required: Option<bool>,
+    #[serde(default)]
+    roles: Option<Vec<String>>,
+    #[serde(default)]
+    compression: Option<bool>,
+    #[serde(default)]
+    admin: bool,
+}
+
+/// One path sessions can upgrade on.
+#[derive(Debug)]
+pub struct Endpoint {
+    pub path: String,
+    /// Strategies every packet is dispatched to, in order.
+    pub strategies: Vec<Strategy>,
+    /// Cap on the packet size negotiated in the capability handshake.
+    pub max_packet_size: Option<u32>,
+    /// Sessions the endpoint holds at once.
+    pub max_sessions: Option<usize>,
+    /// Whether upgrades need a bearer token; `None` follows `AUTH_REQUIRED`.
+    pub auth_required: Option<bool>,
+    /// Policy roles admitted; `None` admits every session.
+    pub roles: Option<Vec<String>>,
+    /// Whether permessage-deflate is negotiated; `None` follows `WS_DEFLATE`.
+    pub compression: Option<bool>,
+    /// Whether ADMIN packets are answered.
+    pub admin: bool,
+    sessions: AtomicUsize,
+}
+
+impl Endpoint {
+    /// An endpoint running every strategy, without limits and not answering ADMIN packets.
+    pub fn open(path: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            strategies: Strategy::ALL.to_vec(),
+            max_packet_size: None,
+            max_sessions: None,
+            auth_required: None,
+            roles: None,
+            compression: None,
+            admin: false,
+            sessions: AtomicUsize::new(0),
+        }
+    }
+
+    /// Whether a session holding `roles` may use the endpoint.
+    pub fn admits(&self, roles: &[String]) -> bool {
+        self.roles
+            .as_ref()
+            .is_none_or(|allowed| roles.iter().any(|role| allowed.contains(role)))
+    }
+
+    /// Take one of the endpoint's session slots. Fails when it is full.
+    pub fn claim(self: &Arc<Self>) -> Result<EndpointSlot, String> {
+        let claimed = self
+            .sessions
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| {
+                match self.max_sessions {
+                    Some(max) if count >= max => None,
+                    _ => Some(count + 1),
+                }
+            });
+        match claimed {
+            Ok(_) => Ok(EndpointSlot(Arc::clone(self))),
+            Err(count) => Err(format!(
+                "endpoint {} is full ({} sessions)",
+                self.path, count
+            )),
+        }
+    }
+}
+
+/// A session's place on an endpoint. Dropping it frees the slot.
+pub struct EndpointSlot(Arc<Endpoint>);
+
+impl Deref for EndpointSlot {
+    type Target = Endpoint;
+
+    fn deref(&self) -> &Endpoint {
+        &self.0
+    }
+}
+
+impl Drop for EndpointSlot {
+    fn drop(&mut self) {
+        self.0.sessions.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Every endpoint of the server, keyed by path.
+pub struct EndpointTable {
+    endpoints: BTreeMap<String, Arc<Endpoint>>,
+}
+
+impl EndpointTable {
+    /// A single endpoint at `path` running every strategy.
+    pub fn single(path: &str) -> Self {
+        let endpoint = Arc::new(Endpoint::open(path));
+        Self {
+            endpoints: BTreeMap::from([(path.to_string(), endpoint)]),
+        }
+    }
+
+    /// Load and validate an endpoint file.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read endpoints {:?}", path))?;
+        let file: EndpointFile =
+            serde_json::from_str(&text).with_context(|| format!("Invalid endpoints {:?}", path))?;
+        if file.endpoints.is_empty() {
+            anyhow::bail!("Endpoints {:?} define no endpoint", path);
+        }
+
+        let mut endpoints = BTreeMap::new();
+        for (endpoint_path, entry) in file.endpoints {
+            if !endpoint_path.starts_with('/') {
+                anyhow::bail!("Endpoint {:?} must start with '/'", endpoint_path);
+            }
+            let strategies = entry
+                .strategies
+                .iter()
+                .map(|name| {
+                    Strategy::parse(name).ok_or_else(|| {
+                        anyhow::anyhow!("Endpoint {}: unknown strategy {:?}", endpoint_path, name)
+                    })
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            let endpoint = Endpoint {
+                path: endpoint_path.clone(),
+                strategies,
+                max_packet_size: entry.max_packet_size,
+                max_sessions: entry.max_sessions,
+                auth_required: entry.auth_required,
+                roles: entry.roles,
+                compression: entry.compression,
+                admin: entry.admin,
+                sessions: AtomicUsize::new(0),
+            };
+            endpoints.insert(endpoint_path, Arc::new(endpoint));
+        }
+        Ok(Self { endpoints })
+    }
+
+    /// The endpoint serving a request path.
+    pub fn get(&self, path: &str) -> Option<&Arc<Endpoint>> {
+        self.endpoints.get(path)
+    }
+
+    /// Every endpoint, ordered by path.
+    pub fn iter(&self) -> impl Iterator<Item = &Endpoint> {
+        self.endpoints.values().map(|endpoint| endpoint.as_ref())
+    }
+
+    /// Largest packet any endpoint can negotiate when the server allows `server_max`.
+    pub fn max_packet_size(&self, server_max: u32) -> u32 {
+        self.iter()
+            .map(|endpoint| {
+                endpoint
+                    .max_packet_size
+                    .map_or(server_max, |limit| limit.min(server_max))
+            })
+            .max()
+            .unwrap_or(server_max)
+    }
+}
@@ -0,0 +1,241 @@
+//! Rebroadcast of RED / YELLOW alerts to every operator session.
+//!
+//! An alert raised by a local session is stamped with a fresh origin, while
+//! one relayed by another node keeps the origin that node gave it. It is then
+//! sent either to every session allowed to receive alerts or to a single
+//! alert topic. Alerts this server has already handled, including copies that
+//! come back through a relay, are dropped.
+
+use protocol::{AlertDeduplicator, AlertOrigin, Packet, Urgency, PACKET_TYPE_MESSAGE};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use svckit::AlertConfig;
+use tracing::warn;
+
+use crate::policy::Policy;
+use crate::registry::{SessionId, SessionRegistry};
+use crate::router::TopicRouter;
+use crate::store::OfflineStore;
+
+/// Most alert ids remembered for duplicate suppression.
+const DEDUP_CAPACITY: usize = 4096;
+
+/// Alert fan-out settings and duplicate-suppression state.
+pub struct AlertFanout {
+    urgencies: Vec<Urgency>,
+    topic: Option<String>,
+    /// Prefix of alert ids raised here: node id and start time, so restarts never reuse ids.
+    id_prefix: String,
+    next_id: AtomicU64,
+    dedup: Mutex<AlertDeduplicator>,
+}
+
+impl AlertFanout {
+    /// Build the fan-out from config. Returns `None` when no urgencies are rebroadcast.
+    pub fn from_config(config: &AlertConfig) -> anyhow::Result<Option<Self>> {
+        if config.urgencies.is_empty() {
+            return Ok(None);
+        }
+        let urgencies = config
+            .urgencies
+            .iter()
+            .map(|name| name.parse::<Urgency>())
+            .collect::<Result<Vec<_>, _>>()?;
+        // Reject an unusable alert topic at startup rather than on the first alert
+        if let Some(topic) = &config.topic {
+            Packet::green("").with_topic(topic)?;
+        }
+
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis());
+        Ok(Some(Self {
+            urgencies,
+            topic: config.topic.clone(),
+            id_prefix: format!("{}-{:x}", config.node_id, started),
+            next_id: AtomicU64::new(0),
+            dedup: Mutex::new(AlertDeduplicator::new(config.dedup_window, DEDUP_CAPACITY)),
+        }))
+    }
+
+    /// Urgencies that are rebroadcast.
+    pub fn urgencies(&self) -> &[Urgency] {
+        &self.urgencies
+    }
+
+    /// Whether `packet` is an alert: a message at one of the configured urgencies.
+    pub fn applies_to(&self, packet: &Packet) -> bool {
+        packet.header.packet_type == PACKET_TYPE_MESSAGE
+            && self.urgencies.contains(&packet.header.urgency)
+    }
+
+    /// Stamp an alert raised by a local session with a fresh origin.
+    ///
+    /// An origin the packet already carries is never trusted; it only marks a
+    /// copy of an alert this server has handled coming back through a relay.
+    /// Returns `None` for such a copy.
+    pub fn admit(&self, packet: Packet, session: SessionId, who: &str) -> Option<Packet> {
+        let now = Instant::now();
+        let mut dedup = self.dedup.lock().unwrap_or_else(|e| e.into_inner());
+        if packet
+            .origin()
+            .is_some_and(|claimed| dedup.seen(&claimed.id, now))
+        {
+            return None;
+        }
+        let origin = AlertOrigin {
+            id: format!(
+                "{}-{}",
+                self.id_prefix,
+                self.next_id.fetch_add(1, Ordering::Relaxed) + 1
+            ),
+            session,
+            who: who.to_string(),
+        };
+        dedup.first_sighting(&origin.id, now);
+        drop(dedup);
+        Some(stamp(packet, &origin))
+    }
+
+    /// Admit an alert another node relayed, keeping the origin it stamped.
+    ///
+    /// Alerts from nodes on wire version 1 carry no stamp and are stamped
+    /// here as raised by `node`. Returns `None` for an alert this server has
+    /// already handled.
+    pub fn admit_relayed(&self, packet: Packet, node: &str) -> Option<Packet> {
+        let Some(origin) = packet.origin() else {
+            return self.admit(packet, 0, node);
+        };
+        let first = self
+            .dedup
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .first_sighting(&origin.id, Instant::now());
+        first.then_some(packet)
+    }
+
+    /// Send an admitted alert on, never back to the session it came from.
+    ///
+    /// Identities that are offline get it in their store-and-forward queue.
+    /// Returns the number of sessions and offline queues it was added to.
+    pub fn fan_out(
+        &self,
+        packet: &Packet,
+        from: Option<SessionId>,
+        registry: &SessionRegistry,
+        router: &TopicRouter,
+        store: Option<&OfflineStore>,
+        policy: Option<&Policy>,
+    ) -> usize {
+        if let Some(topic) = &self.topic {
+            return match packet.clone().with_topic(topic) {
+                Ok(alert) => {
+                    router.publish(&alert) + store.map_or(0, |store| store.publish(&alert))
+                }
+                Err(e) => {
+                    warn!("[ALERT] Cannot publish alert to {}: {}", topic, e);
+                    0
+                }
+            };
+        }
+
+        let queued = store.map_or(0, |store| store.alert(packet));
+        let delivered = registry
+            .list()
+            .iter()
+            .filter(|handle| Some(handle.id) != from)
+            .filter(|handle| policy.is_none_or(|policy| policy.receives_alerts(&handle.roles)))
+            .filter(|handle| match handle.send(packet.clone()) {
+                Ok(()) => true,
+                Err(e) => {
+                    warn!("[ALERT] {}", e);
+                    false
+                }
+            })
+            .count();
+        delivered + queued
+    }
+}
+
+/// Stamp `packet` with `origin`, sending it on unstamped if it cannot carry one.
+fn stamp(packet: Packet, origin: &AlertOrigin) -> Packet {
+    match packet.clone().with_origin(origin) {
+        Ok(stamped) => stamped,
+        Err(e) => {
+            warn!("[ALERT] Cannot stamp alert {}: {}", origin.id, e);
+            packet
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protocol::PROTOCOL_VERSION_2;
+    use std::time::Duration;
+
+    fn fanout() -> AlertFanout {
+        let config = AlertConfig {
+            urgencies: vec!["RED".to_string()],
+            node_id: "node-a".to_string(),
+            dedup_window: Duration::from_secs(60),
+            ..AlertConfig::default()
+        };
+        AlertFanout::from_config(&config).unwrap().unwrap()
+    }
+
+    fn alert() -> Packet {
+        Packet::red("TARGET LOST").with_version(PROTOCOL_VERSION_2)
+    }
+
+    #[test]
+    fn test_local_alerts_get_fresh_origins() {
+        let alerts = fanout();
+        assert!(alerts.applies_to(&alert()));
+        assert!(!alerts.applies_to(&Packet::yellow("BEARING 270")));
+
+        let first = alerts.admit(alert(), 3, "op-1").unwrap();
+        let origin = first.origin().unwrap();
+        assert!(origin.id.starts_with("node-a-"));
+        assert_eq!((origin.session, origin.who.as_str()), (3, "op-1"));
+
+        // A client-supplied origin is replaced, so it can neither spoof the
+        // sender nor claim an id that a later alert will be given
+        let spoofed = AlertOrigin {
+            id: format!("{}-2", alerts.id_prefix),
+            session: 99,
+            who: "commander".to_string(),
+        };
+        let claimed = alerts
+            .admit(alert().with_origin(&spoofed).unwrap(), 4, "uav-7")
+            .unwrap()
+            .origin()
+            .unwrap();
+        assert_ne!(claimed, spoofed);
+        assert_eq!((claimed.session, claimed.who.as_str()), (4, "uav-7"));
+        assert!(alerts.admit(alert(), 5, "op-2").is_some());
+
+        // A copy of an alert handled here, relayed back by a session, is dropped
+        assert!(alerts.admit(first, 6, "relay").is_none());
+    }
+
+    #[test]
+    fn test_relayed_alerts_are_deduplicated() {
+        let alerts = fanout();
+        let origin = AlertOrigin {
+            id: "node-b-1".to_string(),
+            session: 7,
+            who: "op-b".to_string(),
+        };
+        let relayed = alert().with_origin(&origin).unwrap();
+        let admitted = alerts.admit_relayed(relayed.clone(), "node-b").unwrap();
+        assert_eq!(admitted.origin(), Some(origin));
+        assert!(alerts.admit_relayed(relayed.clone(), "node-c").is_none());
+        assert!(alerts.admit(relayed, 1, "relay").is_none());
+
+        // Unstamped alerts from version 1 nodes are stamped as raised by the node
+        let unstamped = alerts.admit_relayed(alert(), "node-b").unwrap();
+        assert_eq!(unstamped.origin().unwrap().who, "node-b");
+    }
+}
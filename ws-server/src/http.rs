@@ -0,0 +1,644 @@
+//! HTTP endpoints for clients that do not speak WebSocket.
+//!
+//! - `GET /events?topic=<filter>&topic=...` streams packets as Server-Sent
+//!   Events, one `Packet::to_json` object per `data:` line. The stream is a
+//!   read-only session: it is listed in the registry, holds the given topic
+//!   subscriptions, receives the drone stream and rebroadcast alerts, and can
+//!   be kicked like any other session.
+//! - `POST /packets` takes one packet in JSON framing and handles it exactly
+//!   as if a session had sent it; a batch is split and each member handled on
+//!   its own. The response body is a JSON array of the packets the server
+//!   answered with (echo, ERROR or ADMIN response).
+//!
+//! Both share the server's TLS acceptor, client certificate checks and bearer
+//! token verification. Topic filters in the query string must percent-encode
+//! `#` as `%23`; `+` is taken literally.
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use futures_util::stream;
+use http_body_util::combinators::BoxBody;
+use http_body_util::{BodyExt, Full, Limited, StreamBody};
+use hyper::body::{Frame, Incoming};
+use hyper::header::{HeaderValue, ALLOW, CACHE_CONTROL, CONTENT_TYPE, WWW_AUTHENTICATE};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use protocol::{Framing, Packet, TopicFilter, ERROR_FORBIDDEN, PROTOCOL_VERSION_2};
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use svckit::wire::{Outbound, Traffic};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+use tokio_rustls::TlsAcceptor;
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{info, warn, Instrument, Span};
+
+use crate::auth::TokenClaims;
+use crate::endpoint::Endpoint;
+use crate::identity::ClientIdentity;
+use crate::registry::{NewSession, SessionGuard};
+use crate::router::Subscriber;
+use crate::{
+    client_identity, forward_drone_stream, handle_packet, packet_span, session_ended,
+    session_started, shutting_down, ServerContext, SessionHandler,
+};
+
+/// Path of the Server-Sent Events stream.
+pub const EVENTS_PATH: &str = "/events";
+
+/// Path packets are posted to.
+pub const PACKETS_PATH: &str = "/packets";
+
+/// Packets an event stream may fall behind by before the router drops them.
+const EVENT_QUEUE: usize = 256;
+
+type Body = BoxBody<Bytes, Infallible>;
+
+/// Serve HTTP on one accepted connection, over TLS unless the server runs in plaintext mode.
+pub async fn handle_connection(
+    stream: TcpStream,
+    tls_acceptor: Option<TlsAcceptor>,
+    ctx: Arc<ServerContext>,
+    keepalive: Duration,
+) -> Result<()> {
+    let peer_addr = stream.peer_addr().ok();
+    let Some(tls_acceptor) = tls_acceptor else {
+        return serve(stream, peer_addr, None, ctx, keepalive).await;
+    };
+
+    let tls_stream = tls_acceptor
+        .accept(stream)
+        .await
+        .context("TLS handshake failed")?;
+    let identity = client_identity(&tls_stream, peer_addr)?;
+    serve(tls_stream, peer_addr, identity, ctx, keepalive).await
+}
+
+async fn serve<S>(
+    stream: S,
+    peer_addr: Option<SocketAddr>,
+    identity: Option<ClientIdentity>,
+    ctx: Arc<ServerContext>,
+    keepalive: Duration,
+) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let mut shutdown = ctx.shutdown.subscribe();
+    let service = service_fn(move |request| {
+        let ctx = Arc::clone(&ctx);
+        let identity = identity.clone();
+        async move { Ok::<_, Infallible>(route(ctx, peer_addr, identity, keepalive, request).await) }
+    });
+    let connection = http1::Builder::new().serve_connection(TokioIo::new(stream), service);
+    tokio::pin!(connection);
+
+    // On shutdown, finish the request in flight and close; event streams end by themselves
+    tokio::select! {
+        served = connection.as_mut() => served?,
+        _ = shutting_down(&mut shutdown) => {
+            connection.as_mut().graceful_shutdown();
+            connection.await?;
+        }
+    }
+    Ok(())
+}
+
+async fn route(
+    ctx: Arc<ServerContext>,
+    peer_addr: Option<SocketAddr>,
+    identity: Option<ClientIdentity>,
+    keepalive: Duration,
+    request: Request<Incoming>,
+) -> Response<Body> {
+    let path = request.uri().path().to_string();
+    let (allowed, allow) = match path.as_str() {
+        EVENTS_PATH => (Method::GET, "GET"),
+        PACKETS_PATH => (Method::POST, "POST"),
+        _ => return text_response(StatusCode::NOT_FOUND, "no such endpoint"),
+    };
+    if request.method() != allowed {
+        let mut response = text_response(StatusCode::METHOD_NOT_ALLOWED, "method not allowed");
+        response
+            .headers_mut()
+            .insert(ALLOW, HeaderValue::from_static(allow));
+        return response;
+    }
+
+    let claims = match authenticate(&ctx, peer_addr, &path, &request) {
+        Ok(claims) => claims,
+        Err(reason) => {
+            let mut response = text_response(StatusCode::UNAUTHORIZED, &reason);
+            response
+                .headers_mut()
+                .insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+            return response;
+        }
+    };
+    let roles = ctx
+        .policy
+        .as_ref()
+        .map(|policy| policy.roles_for(identity.as_ref(), claims.as_ref()))
+        .unwrap_or_default();
+    let client = HttpClient {
+        peer_addr,
+        identity,
+        claims,
+        roles,
+    };
+
+    if path == EVENTS_PATH {
+        events(ctx, client, keepalive, &request)
+    } else {
+        post_packet(&ctx, client, request).await
+    }
+}
+
+/// Who is making a request, once authenticated.
+struct HttpClient {
+    peer_addr: Option<SocketAddr>,
+    identity: Option<ClientIdentity>,
+    claims: Option<TokenClaims>,
+    roles: Vec<String>,
+}
+
+impl HttpClient {
+    /// Register as a session on `path` answering on `out_tx`.
+    ///
+    /// The connection's span takes the session's fields; a later request on
+    /// the same connection replaces them.
+    fn register(
+        self,
+        ctx: &ServerContext,
+        path: &str,
+        out_tx: mpsc::Sender<Outbound>,
+    ) -> SessionGuard {
+        let session = ctx.registry.register(NewSession {
+            peer: self.peer_addr,
+            endpoint: path.to_string(),
+            identity: self.identity,
+            claims: self.claims,
+            roles: self.roles,
+            framing: Framing::Json,
+            version: PROTOCOL_VERSION_2,
+            traffic: Arc::new(Traffic::default()),
+            out_tx,
+        });
+        Span::current()
+            .record("id", session.id)
+            .record("endpoint", path)
+            .record("identity", session.identity_key().unwrap_or("anonymous"));
+        session
+    }
+}
+
+/// Check the request's bearer token and record the decision.
+fn authenticate(
+    ctx: &ServerContext,
+    peer_addr: Option<SocketAddr>,
+    path: &str,
+    request: &Request<Incoming>,
+) -> Result<Option<TokenClaims>, String> {
+    let Some(verifier) = &ctx.auth else {
+        return Ok(None);
+    };
+    match verifier.authenticate(request, verifier.required()) {
+        Ok(claims) => {
+            if let Some(audit) = &ctx.audit {
+                audit.request_allowed(peer_addr, path, claims.as_ref());
+            }
+            Ok(claims)
+        }
+        Err(reason) => {
+            warn!("[HTTP] Rejecting {} from {:?}: {}", path, peer_addr, reason);
+            if let Some(audit) = &ctx.audit {
+                audit.request_rejected(peer_addr, path, &reason);
+            }
+            Err(reason)
+        }
+    }
+}
+
+/// Open an event stream subscribed to the `topic` query parameters.
+fn events(
+    ctx: Arc<ServerContext>,
+    client: HttpClient,
+    keepalive: Duration,
+    request: &Request<Incoming>,
+) -> Response<Body> {
+    let filters = match query_values(request, "topic")
+        .iter()
+        .map(|filter| TopicFilter::parse(filter))
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(filters) => filters,
+        Err(e) => return text_response(StatusCode::BAD_REQUEST, &e.to_string()),
+    };
+    if let Some(policy) = &ctx.policy {
+        if let Err(reason) = policy.authorize_subscription(&client.roles, &filters) {
+            warn!(
+                target: "audit",
+                peer = ?client.peer_addr,
+                roles = ?client.roles,
+                decision = "deny",
+                "{}",
+                reason
+            );
+            if let Some(audit) = &ctx.audit {
+                audit.subscription_denied(client.peer_addr, &client.roles, &filters, &reason);
+            }
+            return text_response(StatusCode::FORBIDDEN, &reason);
+        }
+    }
+    if let Some(audit) = &ctx.audit {
+        audit.subscription_allowed(client.peer_addr, &client.roles, &filters);
+    }
+
+    let (out_tx, out_rx) = mpsc::channel(EVENT_QUEUE);
+    let session = client.register(&ctx, EVENTS_PATH, out_tx.clone());
+    let subscriber = ctx.router.register(session.id, out_tx.clone());
+    subscriber.subscribe(filters);
+    let who = session.identity_key().unwrap_or("anonymous").to_string();
+    let replay = session_started(&ctx, &session, &subscriber, &who);
+    info!(
+        "[HTTP] Event stream opened for {:?} as session {} ({}, topics {:?})",
+        session.peer,
+        session.id,
+        who,
+        subscriber
+            .filters()
+            .iter()
+            .map(|f| f.as_str())
+            .collect::<Vec<_>>()
+    );
+
+    let mut keepalive = tokio::time::interval(keepalive);
+    keepalive.reset();
+    let state = EventStream {
+        drone_stream: tokio::spawn(
+            forward_drone_stream(
+                ctx.handler.subscribe(),
+                out_tx,
+                ctx.metrics.broadcast_lagged.with(&["drone_stream"]),
+            )
+            .in_current_span(),
+        ),
+        shutdown: ctx.shutdown.subscribe(),
+        ctx,
+        session,
+        subscriber,
+        out_rx,
+        replay: replay.into(),
+        keepalive,
+    };
+    let body = StreamBody::new(stream::unfold(state, |mut state| async move {
+        let frame = state.next_event().await?;
+        Some((Ok(frame), state))
+    }));
+
+    let mut response = Response::new(body.boxed());
+    let headers = response.headers_mut();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("text/event-stream"));
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+    response
+}
+
+/// One open event stream. Dropping it ends the session.
+struct EventStream {
+    ctx: Arc<ServerContext>,
+    session: SessionGuard,
+    subscriber: Subscriber,
+    drone_stream: JoinHandle<()>,
+    out_rx: mpsc::Receiver<Outbound>,
+    /// Packets queued while the identity was offline, sent first.
+    replay: VecDeque<Packet>,
+    keepalive: tokio::time::Interval,
+    shutdown: watch::Receiver<bool>,
+}
+
+impl EventStream {
+    /// The next frame to send, or `None` once the stream should end.
+    async fn next_event(&mut self) -> Option<Frame<Bytes>> {
+        if let Some(packet) = self.replay.pop_front() {
+            return Some(self.event(&packet));
+        }
+        loop {
+            tokio::select! {
+                outbound = self.out_rx.recv() => match outbound? {
+                    Outbound::Packet(packet) => return Some(self.event(&packet)),
+                    Outbound::Message(Message::Close(_)) => return None,
+                    Outbound::Message(_) => {}
+                },
+                _ = self.keepalive.tick() => {
+                    return Some(Frame::data(Bytes::from_static(b": keepalive\n\n")));
+                }
+                _ = self.session.kicked() => {
+                    info!("[HTTP] Event stream {} kicked by operator", self.session.id);
+                    return None;
+                }
+                _ = shutting_down(&mut self.shutdown) => return None,
+            }
+        }
+    }
+
+    fn event(&self, packet: &Packet) -> Frame<Bytes> {
+        let event = format!("data: {}\n\n", packet.to_json());
+        self.session.traffic.record_out(event.len(), 1);
+        Frame::data(Bytes::from(event))
+    }
+}
+
+impl Drop for EventStream {
+    fn drop(&mut self) {
+        self.drone_stream.abort();
+        session_ended(&self.ctx, &self.session, &self.subscriber);
+        info!(
+            "[HTTP] Event stream closed for {:?} (session {})",
+            self.session.peer, self.session.id
+        );
+    }
+}
+
+/// Handle one posted packet as a short-lived session and return its replies.
+async fn post_packet(
+    ctx: &ServerContext,
+    client: HttpClient,
+    request: Request<Incoming>,
+) -> Response<Body> {
+    let limit = ctx.capabilities.max_packet_size as usize;
+    let body = match Limited::new(request.into_body(), limit).collect().await {
+        Ok(body) => body.to_bytes(),
+        Err(e) => {
+            return match e.downcast::<http_body_util::LengthLimitError>() {
+                Ok(_) => text_response(
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    &format!("packet exceeds {} bytes", limit),
+                ),
+                Err(e) => text_response(StatusCode::BAD_REQUEST, &e.to_string()),
+            };
+        }
+    };
+    // Topics and the other extensions need v2, which HTTP clients always get
+    let packet = serde_json::from_slice::<serde_json::Value>(&body)
+        .map_err(|e| e.to_string())
+        .and_then(|json| {
+            Packet::from_json_versioned(&json, PROTOCOL_VERSION_2).map_err(|e| e.to_string())
+        });
+    // Members of a batch are authorized, audited and dispatched one by one
+    let packets = packet.and_then(|packet| match packet.is_batch() {
+        true => packet.unbatch().map_err(|e| e.to_string()),
+        false => Ok(vec![packet]),
+    });
+    let packets = match packets {
+        Ok(packets) => packets,
+        Err(e) => {
+            ctx.metrics.decode_error(PACKETS_PATH);
+            return text_response(StatusCode::BAD_REQUEST, &format!("invalid packet: {}", e));
+        }
+    };
+
+    let (out_tx, mut out_rx) = mpsc::channel(EVENT_QUEUE);
+    let session = client.register(ctx, PACKETS_PATH, out_tx.clone());
+    let subscriber = ctx.router.register(session.id, out_tx.clone());
+    session.traffic.record_in(body.len(), packets.len());
+    // Posted packets run every strategy but never administer the server
+    let endpoint = Endpoint::open(PACKETS_PATH);
+    let handler = SessionHandler::new(
+        &ctx.handler,
+        &endpoint,
+        session.identity.as_ref(),
+        session.claims.as_ref(),
+    );
+    let mut status = StatusCode::OK;
+    let mut replies = Vec::new();
+    for packet in packets {
+        info!(
+            "[HTTP] {} posted a {} packet as session {}",
+            handler.who(),
+            packet.header.urgency.as_str(),
+            session.id
+        );
+        let span = packet_span(&packet);
+        let _ = handle_packet(ctx, &handler, &session, &subscriber, &out_tx, packet)
+            .instrument(span)
+            .await;
+
+        // Every reply was queued before handle_packet returned
+        while let Ok(Outbound::Packet(reply)) = out_rx.try_recv() {
+            if reply
+                .to_error_notice()
+                .is_ok_and(|notice| notice.code == ERROR_FORBIDDEN)
+            {
+                status = StatusCode::FORBIDDEN;
+            }
+            replies.push(reply.to_json());
+        }
+    }
+
+    let mut response =
+        Response::new(Full::new(Bytes::from(serde_json::Value::from(replies).to_string())).boxed());
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    response
+}
+
+fn text_response(status: StatusCode, message: &str) -> Response<Body> {
+    let mut response = Response::new(Full::new(Bytes::from(format!("{}\n", message))).boxed());
+    *response.status_mut() = status;
+    response.headers_mut().insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("text/plain; charset=utf-8"),
+    );
+    response
+}
+
+/// Every value of a query parameter, percent-decoded.
+fn query_values<B>(request: &Request<B>, name: &str) -> Vec<String> {
+    request
+        .uri()
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .filter(|(key, _)| *key == name)
+        .map(|(_, value)| percent_decode(value))
+        .collect()
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::Policy;
+    use crate::tests::test_context;
+    use protocol::ERROR_FORBIDDEN;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// Send one raw HTTP/1.1 request through `serve` and return the status and body.
+    async fn exchange(ctx: impl Into<Arc<ServerContext>>, head: &str, body: &str) -> (u16, String) {
+        let (client, server) = tokio::io::duplex(1024 * 1024);
+        let served = tokio::spawn(serve(
+            server,
+            None,
+            None,
+            ctx.into(),
+            Duration::from_secs(15),
+        ));
+        let (mut read, mut write) = tokio::io::split(client);
+        let request = format!(
+            "{}\r\nHost: test\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}",
+            head,
+            body.len(),
+            body
+        );
+        write.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        read.read_to_string(&mut response).await.unwrap();
+        let _ = served.await;
+
+        let status = response[9..12].parse().unwrap();
+        let body = response
+            .split_once("\r\n\r\n")
+            .map_or_else(String::new, |(_, body)| body.to_string());
+        (status, body)
+    }
+
+    #[test]
+    fn test_query_decoding() {
+        let request = Request::builder()
+            .uri("/events?topic=red/%23&topic=%2B/uav-7/+&other=x&topic=bad%2")
+            .body(())
+            .unwrap();
+        assert_eq!(
+            query_values(&request, "topic"),
+            vec!["red/#", "+/uav-7/+", "bad%2"]
+        );
+        assert_eq!(percent_decode("%2"), "%2");
+        assert_eq!(percent_decode("a%zzb"), "a%zzb");
+        assert_eq!(percent_decode("uav%2D7"), "uav-7");
+    }
+
+    #[tokio::test]
+    async fn test_routing_errors() {
+        let (status, _) = exchange(test_context(None, None), "GET /nope HTTP/1.1", "").await;
+        assert_eq!(status, 404);
+        let (status, _) = exchange(test_context(None, None), "GET /packets HTTP/1.1", "").await;
+        assert_eq!(status, 405);
+
+        let mut keys = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(
+            &mut keys,
+            br#"{"keys": [{"kid": "ops", "alg": "HS256", "secret": "s"}]}"#,
+        )
+        .unwrap();
+        let config = svckit::AuthConfig {
+            keys_file: Some(keys.path().to_path_buf()),
+            required: true,
+            ..Default::default()
+        };
+        let mut ctx = test_context(None, None);
+        ctx.auth = crate::auth::TokenVerifier::from_config(&config).unwrap();
+        let (status, _) = exchange(ctx, "POST /packets HTTP/1.1", "{}").await;
+        assert_eq!(status, 401);
+    }
+
+    #[tokio::test]
+    async fn test_post_packet_limits() {
+        let mut ctx = test_context(None, None);
+        ctx.capabilities.max_packet_size = 64;
+        let oversized = format!(r#"{{"payload": "{}"}}"#, "x".repeat(100));
+        let (status, _) = exchange(ctx, "POST /packets HTTP/1.1", &oversized).await;
+        assert_eq!(status, 413);
+
+        let (status, _) = exchange(
+            test_context(None, None),
+            "POST /packets HTTP/1.1",
+            "not json",
+        )
+        .await;
+        assert_eq!(status, 400);
+    }
+
+    #[tokio::test]
+    async fn test_posted_batch_members_are_authorized() {
+        let policy =
+            r#"{"roles": {"observer": {"urgencies": ["GREEN"]}}, "default_roles": ["observer"]}"#;
+        let member = Packet::red("LOCK").with_version(PROTOCOL_VERSION_2);
+        let batch = Packet::batch(&[member]);
+        // A GREEN container still carries the RED member
+        let mut json = batch.to_json();
+        json["urgency"] = "GREEN".into();
+        let payload = String::from_utf8(batch.payload.clone()).expect("ASCII batch payload");
+        json["payload"] = payload.into();
+
+        let ctx = test_context(Some(Policy::parse(policy).unwrap()), None);
+        let (status, body) = exchange(ctx, "POST /packets HTTP/1.1", &json.to_string()).await;
+        assert_eq!(status, 403, "{}", body);
+        let replies: Vec<serde_json::Value> = serde_json::from_str(&body).unwrap();
+        let notice = Packet::from_json(&replies[0])
+            .unwrap()
+            .to_error_notice()
+            .unwrap();
+        assert_eq!(notice.code, ERROR_FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_denied_subscription_is_audited() {
+        let policy = r#"{"roles": {"drone": {"topics": ["uav-7/#"]}}, "default_roles": ["drone"]}"#;
+        let dir = tempfile::tempdir().unwrap();
+        let config = svckit::AuditConfig {
+            file: Some(dir.path().join("audit.log")),
+            rotate_bytes: 0,
+            ..Default::default()
+        };
+        let mut ctx = test_context(Some(Policy::parse(policy).unwrap()), None);
+        ctx.audit = crate::audit::AuditLog::open(&config).unwrap();
+        let ctx = Arc::new(ctx);
+
+        let (status, _) =
+            exchange(Arc::clone(&ctx), "GET /events?topic=red/%23 HTTP/1.1", "").await;
+        assert_eq!(status, 403);
+
+        let audit = ctx.audit.as_ref().unwrap();
+        audit.close().await.unwrap();
+        let log = std::fs::read_to_string(audit.path()).unwrap();
+        let record: serde_json::Value = log
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .find(|record: &serde_json::Value| record["event"] == "subscribe")
+            .expect("no subscribe record");
+        assert_eq!(record["decision"], "deny");
+        assert_eq!(record["topics"], serde_json::json!(["red/#"]));
+    }
+}
@@ -0,0 +1,73 @@
+//! Identity of a client authenticated with a TLS client certificate.
+
+use rustls::pki_types::CertificateDer;
+use x509_parser::extensions::GeneralName;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+/// Names taken from a verified client certificate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientIdentity {
+    /// Full subject distinguished name, e.g. `CN=uav-7, O=Fleet`.
+    pub subject: String,
+    /// Subject common name, if present.
+    pub common_name: Option<String>,
+    /// Subject alternative names as `DNS:...`, `URI:...`, `email:...` or `IP:...`.
+    pub sans: Vec<String>,
+}
+
+impl ClientIdentity {
+    /// Extract the identity from the end-entity certificate of a verified chain.
+    pub fn from_certificate(cert: &CertificateDer<'_>) -> Result<Self, String> {
+        let (_, cert) = X509Certificate::from_der(cert.as_ref())
+            .map_err(|e| format!("unparseable client certificate: {}", e))?;
+
+        let common_name = cert
+            .subject()
+            .iter_common_name()
+            .next()
+            .and_then(|cn| cn.as_str().ok())
+            .map(str::to_string);
+
+        let mut sans = Vec::new();
+        if let Ok(Some(ext)) = cert.subject_alternative_name() {
+            for name in &ext.value.general_names {
+                match name {
+                    GeneralName::DNSName(dns) => sans.push(format!("DNS:{}", dns)),
+                    GeneralName::URI(uri) => sans.push(format!("URI:{}", uri)),
+                    GeneralName::RFC822Name(email) => sans.push(format!("email:{}", email)),
+                    GeneralName::IPAddress(bytes) => {
+                        if let Some(ip) = ip_from_bytes(bytes) {
+                            sans.push(format!("IP:{}", ip));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(Self {
+            subject: cert.subject().to_string(),
+            common_name,
+            sans,
+        })
+    }
+
+    /// Short name for logs and the registry: the common name, else the full subject.
+    pub fn name(&self) -> &str {
+        self.common_name.as_deref().unwrap_or(&self.subject)
+    }
+}
+
+impl std::fmt::Display for ClientIdentity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+fn ip_from_bytes(bytes: &[u8]) -> Option<std::net::IpAddr> {
+    match bytes.len() {
+        4 => <[u8; 4]>::try_from(bytes).ok().map(std::net::IpAddr::from),
+        16 => <[u8; 16]>::try_from(bytes).ok().map(std::net::IpAddr::from),
+        _ => None,
+    }
+}
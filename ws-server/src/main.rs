@@ -5,27 +5,88 @@
 
 use anyhow::{Context, Result};
 use async_trait::async_trait;
-use futures_util::{SinkExt, StreamExt};
-use protocol::{Packet, ProtocolApi, StrategyHandler, Urgency};
-use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use futures_util::{Sink, SinkExt, Stream, StreamExt};
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use protocol::{
+    AdminRequest, AdminResponse, BatchCoalescer, Capabilities, ErrorNotice, Framing, Hello,
+    HelloAck, LinkEvent, LinkMonitor, NodeInfo, Packet, ProtocolApi, ReloadingClassifier,
+    RemoteSession, StrategyHandler, TrackUpdate, Urgency, ERROR_FORBIDDEN, ERROR_INVALID_TOPIC,
+    ERROR_STREAM_LAGGED, ERROR_WRONG_VERSION, FEATURE_BATCH, PACKET_TYPE_ADMIN,
+    PACKET_TYPE_HEARTBEAT, PACKET_TYPE_SUBSCRIBE, PACKET_TYPE_UNSUBSCRIBE, PROTOCOL_VERSION,
+    PROTOCOL_VERSION_2,
+};
+use rustls::server::WebPkiClientVerifier;
 use std::fs::File;
 use std::io::BufReader;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 use std::sync::Arc;
-use svckit::AddrConfig;
+use std::time::{Duration, Instant};
+use svckit::telemetry;
+use svckit::wire::{
+    close_message, decode_frame, encode_packet, run_writer, sleep_until_deadline, DeflateCodec,
+    DeflateRole, DeflateStream, Inbound, Outbound, Traffic,
+};
+use svckit::{
+    AddrConfig, AlertConfig, AuditConfig, AuthConfig, BatchConfig, ClassifierConfig, ClusterConfig,
+    CompressionConfig, EndpointConfig, EnvError, HandshakeConfig, HeartbeatConfig, HttpConfig,
+    LogConfig, LogFormat, MetricsConfig, ShutdownConfig, StoreForwardConfig, TraceConfig,
+};
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::broadcast;
+use tokio::sync::{broadcast, mpsc, watch};
+use tokio::task::JoinSet;
+use tokio_rustls::server::TlsStream;
 use tokio_rustls::TlsAcceptor;
-use tokio_tungstenite::tungstenite::Message;
-use tracing::{error, info, warn};
+use tokio_tungstenite::tungstenite::handshake::server::{Request, Response};
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
+use tokio_tungstenite::tungstenite::{self, Message};
+use tracing::{error, field, info, info_span, warn, Instrument, Span};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::Layer;
+
+mod audit;
+mod auth;
+mod backplane;
+mod certs;
+mod endpoint;
+mod fanout;
+mod http;
+mod identity;
+mod mesh;
+mod metrics;
+mod policy;
+mod registry;
+mod router;
+mod store;
+mod upgrade;
+
+use audit::AuditLog;
+use auth::{TokenClaims, TokenVerifier};
+use backplane::{Backplane, ClusterEvent, ClusterView, MemoryHub};
+use certs::ReloadingCertResolver;
+use endpoint::{Endpoint, EndpointTable, Strategy};
+use fanout::AlertFanout;
+use identity::ClientIdentity;
+use mesh::{Snapshot, TcpMesh};
+use metrics::{Counter, Metrics};
+use policy::Policy;
+use registry::{NewSession, SessionHandle, SessionRegistry};
+use router::{Subscriber, TopicRouter};
+use store::OfflineStore;
+use upgrade::Upgrade;
 
 // ============================================================================
 // Strategy Implementation
 // ============================================================================
 
-/// Server-side strategy handler for incoming packets.
+/// Server-side state shared by the strategy handlers of every session.
 struct ServerStrategyHandler {
-    /// Sender for broadcasting drone stream data.
-    drone_stream_tx: broadcast::Sender<String>,
+    /// Sender for broadcasting drone stream data as TRACK packets.
+    drone_stream_tx: broadcast::Sender<Packet>,
 }
 
 impl ServerStrategyHandler {
@@ -33,43 +94,503 @@ impl ServerStrategyHandler {
         let (drone_stream_tx, _) = broadcast::channel(16);
         Self { drone_stream_tx }
     }
+
+    /// Subscribe a session to the drone coordinate stream.
+    fn subscribe(&self) -> broadcast::Receiver<Packet> {
+        self.drone_stream_tx.subscribe()
+    }
+
+    /// Build the handler for one of an endpoint's strategies.
+    fn strategy(&self, strategy: Strategy, who: &str) -> Box<dyn StrategyHandler> {
+        match strategy {
+            Strategy::Log => Box::new(LogStrategy {
+                who: who.to_string(),
+            }),
+            Strategy::DroneStream => Box::new(DroneStreamStrategy {
+                drone_stream_tx: self.drone_stream_tx.clone(),
+            }),
+        }
+    }
+}
+
+/// Logs every packet with who sent it, and lost links.
+struct LogStrategy {
+    who: String,
 }
 
 #[async_trait]
-impl StrategyHandler for ServerStrategyHandler {
+impl StrategyHandler for LogStrategy {
     async fn on_urgent_red(&self, packet: &Packet) {
         info!(
-            "[SERVER] 🔴 URGENT RED — STREAMING DRONE TARGET DATA: {}",
-            packet.payload_string_lossy()
+            urgency = "RED",
+            from = %self.who,
+            payload = %packet.payload_string_lossy(),
+            "[SERVER] Urgent packet"
         );
+    }
+
+    async fn on_normal(&self, packet: &Packet) {
+        info!(
+            urgency = "GREEN",
+            from = %self.who,
+            payload = %packet.payload_string_lossy(),
+            "[SERVER] Packet"
+        );
+    }
+
+    async fn on_urgent_yellow(&self, packet: &Packet) {
+        info!(
+            urgency = "YELLOW",
+            from = %self.who,
+            payload = %packet.payload_string_lossy(),
+            "[SERVER] Priority packet"
+        );
+    }
+
+    async fn on_link_event(&self, event: &LinkEvent) {
+        if let LinkEvent::Lost { silent_for } = event {
+            warn!(
+                from = %self.who,
+                silent_for = ?silent_for,
+                "[SERVER] Link lost after silence"
+            );
+        }
+    }
+}
+
+/// Streams drone target coordinates to every session when a RED packet arrives.
+struct DroneStreamStrategy {
+    drone_stream_tx: broadcast::Sender<Packet>,
+}
+
+#[async_trait]
+impl StrategyHandler for DroneStreamStrategy {
+    async fn on_urgent_red(&self, _packet: &Packet) {
+        info!("[SERVER] STREAMING DRONE TARGET DATA");
 
         // Simulate SSE-like drone coordinate stream
         let tx = self.drone_stream_tx.clone();
         tokio::spawn(async move {
-            for i in 0..5 {
-                let lat = 34.2345 + (i as f64) * 0.0001;
-                let lon = 69.1234 + (i as f64) * 0.0002;
-                let msg = format!("[DRONE STREAM] lat={:.4}, lon={:.4}", lat, lon);
-                info!("{}", msg);
-                let _ = tx.send(msg);
-                tokio::time::sleep(tokio::time::Duration::from_millis(400)).await;
+            for i in 0..5u64 {
+                let update = TrackUpdate {
+                    seq: i,
+                    lat: 34.2345 + (i as f64) * 0.0001,
+                    lon: 69.1234 + (i as f64) * 0.0002,
+                };
+                info!(
+                    "[DRONE STREAM] lat={:.4}, lon={:.4}",
+                    update.lat, update.lon
+                );
+                if tx.send(Packet::track(&update)).is_err() {
+                    warn!(
+                        "[DRONE STREAM] No sessions subscribed, update {} not delivered",
+                        i
+                    );
+                }
+                tokio::time::sleep(Duration::from_millis(400)).await;
             }
         });
     }
 
+    async fn on_normal(&self, _packet: &Packet) {}
+}
+
+/// Per-session view of the strategy handlers, aware of who is connected and where.
+struct SessionHandler<'a> {
+    /// Identity from the client certificate, when mTLS is enabled.
+    identity: Option<&'a ClientIdentity>,
+    /// Claims of the bearer token presented on upgrade, if any.
+    claims: Option<&'a TokenClaims>,
+    /// Endpoint the session connected to.
+    endpoint: &'a Endpoint,
+    /// Handlers of the endpoint's strategies, run in order.
+    strategies: Vec<Box<dyn StrategyHandler>>,
+}
+
+impl<'a> SessionHandler<'a> {
+    fn new(
+        shared: &ServerStrategyHandler,
+        endpoint: &'a Endpoint,
+        identity: Option<&'a ClientIdentity>,
+        claims: Option<&'a TokenClaims>,
+    ) -> Self {
+        let mut handler = Self {
+            identity,
+            claims,
+            endpoint,
+            strategies: Vec::new(),
+        };
+        handler.strategies = endpoint
+            .strategies
+            .iter()
+            .map(|strategy| shared.strategy(*strategy, handler.who()))
+            .collect();
+        handler
+    }
+
+    /// The token subject, else the certificate name, else `anonymous`.
+    fn who(&self) -> &str {
+        match (self.claims, self.identity) {
+            (Some(claims), _) => &claims.sub,
+            (None, Some(identity)) => identity.name(),
+            (None, None) => "anonymous",
+        }
+    }
+}
+
+#[async_trait]
+impl StrategyHandler for SessionHandler<'_> {
+    async fn on_urgent_red(&self, packet: &Packet) {
+        for strategy in &self.strategies {
+            strategy.on_urgent_red(packet).await;
+        }
+    }
+
     async fn on_normal(&self, packet: &Packet) {
-        info!(
-            "[SERVER] 🟢 Normal packet: {}",
-            packet.payload_string_lossy()
-        );
+        for strategy in &self.strategies {
+            strategy.on_normal(packet).await;
+        }
     }
 
     async fn on_urgent_yellow(&self, packet: &Packet) {
+        for strategy in &self.strategies {
+            strategy.on_urgent_yellow(packet).await;
+        }
+    }
+
+    async fn on_link_event(&self, event: &LinkEvent) {
+        for strategy in &self.strategies {
+            strategy.on_link_event(event).await;
+        }
+    }
+}
+
+/// Forward drone stream updates to one session's writer until either side closes.
+///
+/// A receiver that falls behind loses the oldest updates; the client is told
+/// how many with an ERROR packet rather than seeing a silent gap.
+async fn forward_drone_stream(
+    mut rx: broadcast::Receiver<Packet>,
+    out_tx: mpsc::Sender<Outbound>,
+    lagged: Arc<Counter>,
+) {
+    loop {
+        let packet = match rx.recv().await {
+            Ok(packet) => packet,
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                warn!(
+                    "[SERVER] Session lagged behind drone stream, {} updates dropped",
+                    missed
+                );
+                lagged.add(missed);
+                Packet::error_notice(&ErrorNotice::new(
+                    ERROR_STREAM_LAGGED,
+                    format!("{} drone stream updates dropped", missed),
+                ))
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+        if out_tx.send(Outbound::Packet(packet)).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Handle one typed packet from a session.
+///
+/// ADMIN packets are answered directly, subscription packets update the router,
+/// packets with a topic are fanned out to subscribers and everything else is
+/// echoed back to the sender.
+async fn handle_packet(
+    ctx: &ServerContext,
+    handler: &SessionHandler<'_>,
+    session: &SessionHandle,
+    subscriber: &Subscriber,
+    out_tx: &mpsc::Sender<Outbound>,
+    mut packet: Packet,
+) -> Result<(), mpsc::error::SendError<Outbound>> {
+    let _timer = ctx.metrics.packet_received(&handler.endpoint.path, &packet);
+    // Whatever the server forwards from here on continues this packet's trace
+    telemetry::inject(&Span::current(), &mut packet);
+
+    // Heartbeats are link control: answered before authorization and never dispatched
+    if packet.header.packet_type == PACKET_TYPE_HEARTBEAT {
+        match packet.to_heartbeat() {
+            Ok(heartbeat) if !heartbeat.ack => {
+                out_tx
+                    .send(Outbound::Packet(Packet::heartbeat(&heartbeat.ack())))
+                    .await?;
+            }
+            Ok(_) => {}
+            Err(e) => warn!(
+                "[SERVER] Session {} sent invalid heartbeat: {}",
+                session.id, e
+            ),
+        }
+        return Ok(());
+    }
+
+    if let Some(policy) = &ctx.policy {
+        if let Err(reason) = policy.authorize(&session.roles, &packet) {
+            warn!(
+                target: "audit",
+                session = session.id,
+                who = handler.who(),
+                roles = ?session.roles,
+                packet_type = packet.header.type_name(),
+                urgency = packet.header.urgency.as_str(),
+                topic = packet.topic(),
+                decision = "deny",
+                "{}",
+                reason
+            );
+            if let Some(audit) = &ctx.audit {
+                audit.denied(session, handler.who(), &packet, &reason);
+            }
+            let notice = Packet::error_notice(&ErrorNotice::new(ERROR_FORBIDDEN, reason));
+            out_tx.send(Outbound::Packet(notice)).await?;
+            return Ok(());
+        }
+    }
+
+    if let Some(audit) = &ctx.audit {
+        audit.packet(session, handler.who(), &packet);
+    }
+
+    // Alerts are stamped and rebroadcast once; copies seen before are dropped
+    let packet = match &ctx.alerts {
+        Some(alerts) if alerts.applies_to(&packet) => {
+            match alerts.admit(packet, session.id, handler.who()) {
+                Some(packet) => {
+                    ctx.backplane.broadcast(ClusterEvent::alert(&packet));
+                    let delivered = alerts.fan_out(
+                        &packet,
+                        Some(session.id),
+                        &ctx.registry,
+                        &ctx.router,
+                        ctx.store.as_ref(),
+                        ctx.policy.as_ref(),
+                    );
+                    info!(
+                        "[ALERT] {} alert from {} rebroadcast to {} session(s)",
+                        packet.header.urgency.as_str(),
+                        handler.who(),
+                        delivered
+                    );
+                    packet
+                }
+                None => {
+                    info!(
+                        "[ALERT] Dropping duplicate alert from session {}",
+                        session.id
+                    );
+                    return Ok(());
+                }
+            }
+        }
+        _ => packet,
+    };
+
+    match packet.header.packet_type {
+        PACKET_TYPE_ADMIN if !handler.endpoint.admin => {
+            warn!(
+                "[ADMIN] Rejected request from session {} on {}",
+                session.id, handler.endpoint.path
+            );
+            let notice = Packet::error_notice(&ErrorNotice::new(
+                ERROR_FORBIDDEN,
+                format!(
+                    "admin requests are not accepted on {}",
+                    handler.endpoint.path
+                ),
+            ));
+            out_tx.send(Outbound::Packet(notice)).await?;
+        }
+        PACKET_TYPE_ADMIN => {
+            let reply = handle_admin(ctx, session, &packet);
+            out_tx.send(Outbound::Packet(reply)).await?;
+        }
+        PACKET_TYPE_SUBSCRIBE | PACKET_TYPE_UNSUBSCRIBE => {
+            if let Err(notice) = update_subscriptions(subscriber, &packet) {
+                out_tx.send(Outbound::Packet(notice)).await?;
+            }
+        }
+        _ if packet.topic().is_some() => {
+            ctx.api
+                .dispatch(&packet, handler)
+                .instrument(info_span!("dispatch"))
+                .await;
+            ctx.router.publish(&packet);
+            if let Some(store) = &ctx.store {
+                store.publish(&packet);
+            }
+            ctx.backplane.broadcast(ClusterEvent::publish(&packet));
+        }
+        _ => {
+            ctx.api
+                .dispatch(&packet, handler)
+                .instrument(info_span!("dispatch"))
+                .await;
+            // Echo back
+            out_tx.send(Outbound::Packet(packet)).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Turn an untyped text frame into a packet, GREEN unless a classification
+/// rule matches.
+fn classify_text(ctx: &ServerContext, text: String) -> Packet {
+    let Some(classifier) = &ctx.classifier else {
+        return ctx.api.make_packet(&text, Urgency::Green);
+    };
+    let rules = classifier.current();
+    let classification = rules.classify(&text);
+    if let Some(rule) = &classification.rule {
         info!(
-            "[SERVER] 🟡 Yellow priority: {}",
-            packet.payload_string_lossy()
+            "[SERVER] Rule {} classified text as {}",
+            rule,
+            classification.urgency.as_str()
         );
     }
+    Packet::typed(
+        classification.packet_type,
+        classification.urgency,
+        text.into_bytes(),
+    )
+}
+
+/// Answer an ADMIN request. Only sessions holding an admin role and connected
+/// from loopback may administer.
+fn handle_admin(ctx: &ServerContext, session: &SessionHandle, packet: &Packet) -> Packet {
+    if !ctx
+        .policy
+        .as_ref()
+        .is_some_and(|policy| policy.administers(&session.roles))
+    {
+        warn!(
+            "[ADMIN] Rejected request from session {} without an admin role",
+            session.id
+        );
+        return Packet::error_notice(&ErrorNotice::new(
+            ERROR_FORBIDDEN,
+            "admin requests need a role with \"admin\": true",
+        ));
+    }
+    if !session.peer.is_some_and(|addr| addr.ip().is_loopback()) {
+        warn!(
+            "[ADMIN] Rejected request from non-local session {}",
+            session.id
+        );
+        return Packet::error_notice(&ErrorNotice::new(
+            ERROR_FORBIDDEN,
+            "admin requests are only accepted from loopback connections",
+        ));
+    }
+
+    let request = match packet.to_admin_request() {
+        Ok(request) => request,
+        Err(e) => return Packet::admin_response(&AdminResponse::Error(e.to_string())),
+    };
+    info!("[ADMIN] Session {} requested {:?}", session.id, request);
+
+    let info = |handle: &SessionHandle| {
+        let filters = ctx.router.filters(handle.id);
+        handle.info(filters.iter().map(|f| f.to_string()).collect())
+    };
+    let response = match request {
+        AdminRequest::List => {
+            AdminResponse::Sessions(ctx.registry.list().iter().map(|h| info(h)).collect())
+        }
+        AdminRequest::Inspect { session: id } => match ctx.registry.get(id) {
+            Some(handle) => AdminResponse::Session(Box::new(info(&handle))),
+            None => AdminResponse::Error(format!("no session {}", id)),
+        },
+        AdminRequest::Kick {
+            session: id,
+            reason,
+        } => match ctx.registry.get(id) {
+            Some(handle) => {
+                handle.kick(reason.as_deref().unwrap_or("kicked by operator"));
+                AdminResponse::Done(format!("kicked session {}", id))
+            }
+            None => AdminResponse::Error(format!("no session {}", id)),
+        },
+        AdminRequest::Send {
+            session: id,
+            message,
+            urgency,
+        } => match (ctx.registry.get(id), urgency.parse::<Urgency>()) {
+            (None, _) => AdminResponse::Error(format!("no session {}", id)),
+            (_, Err(e)) => AdminResponse::Error(e.to_string()),
+            (Some(handle), Ok(urgency)) => match handle.send(Packet::new(message, urgency)) {
+                Ok(()) => AdminResponse::Done(format!("sent to session {}", id)),
+                Err(e) => AdminResponse::Error(e),
+            },
+        },
+        AdminRequest::Cluster => AdminResponse::Cluster(cluster_nodes(ctx)),
+    };
+    Packet::admin_response(&response)
+}
+
+/// This node and every node it links to or hears from, with their sessions.
+fn cluster_nodes(ctx: &ServerContext) -> Vec<NodeInfo> {
+    let local = NodeInfo {
+        node: ctx.backplane.node_id().to_string(),
+        local: true,
+        connected: true,
+        sessions: ctx
+            .registry
+            .list()
+            .iter()
+            .map(|handle| RemoteSession {
+                id: handle.id,
+                who: handle.identity_key().map(String::from),
+            })
+            .collect(),
+    };
+
+    let mut remote = ctx.cluster.nodes();
+    for (peer, up) in ctx.backplane.peers() {
+        match remote.iter_mut().find(|node| node.node == peer) {
+            Some(node) => node.connected = up,
+            None => remote.push(NodeInfo {
+                node: peer,
+                local: false,
+                connected: up,
+                sessions: Vec::new(),
+            }),
+        }
+    }
+    std::iter::once(local).chain(remote).collect()
+}
+
+/// Apply a SUBSCRIBE or UNSUBSCRIBE packet, returning an ERROR packet if it is invalid.
+fn update_subscriptions(subscriber: &Subscriber, packet: &Packet) -> Result<(), Packet> {
+    let filters = packet.to_topic_filters().map_err(|e| {
+        warn!(
+            "[SERVER] Session {} sent invalid subscription: {}",
+            subscriber.id(),
+            e
+        );
+        Packet::error_notice(&ErrorNotice::new(ERROR_INVALID_TOPIC, e.to_string()))
+    })?;
+
+    if packet.header.packet_type == PACKET_TYPE_SUBSCRIBE {
+        subscriber.subscribe(filters);
+    } else {
+        subscriber.unsubscribe(&filters);
+    }
+    info!(
+        "[SERVER] Session {} subscriptions: {:?}",
+        subscriber.id(),
+        subscriber
+            .filters()
+            .iter()
+            .map(|f| f.as_str())
+            .collect::<Vec<_>>()
+    );
+    Ok(())
 }
 
 // ============================================================================
@@ -77,123 +598,806 @@ impl StrategyHandler for ServerStrategyHandler {
 // ============================================================================
 
 /// Load TLS certificates and private key from PEM files.
-fn load_tls_config(config: &AddrConfig) -> Result<Arc<rustls::ServerConfig>> {
-    // Load certificate chain
-    let cert_file = File::open(&config.tls.cert_file)
-        .with_context(|| format!("Failed to open cert file: {:?}", config.tls.cert_file))?;
-    let mut cert_reader = BufReader::new(cert_file);
-    let certs: Vec<CertificateDer<'static>> = rustls_pemfile::certs(&mut cert_reader)
-        .collect::<Result<Vec<_>, _>>()
-        .context("Failed to parse certificates")?;
+fn load_tls_config(
+    config: &AddrConfig,
+) -> Result<(Arc<rustls::ServerConfig>, Arc<ReloadingCertResolver>)> {
+    // Build server config, requiring client certificates when a client CA is set
+    let builder = rustls::ServerConfig::builder();
+    let resolver = Arc::new(ReloadingCertResolver::load(
+        &config.tls.cert_file,
+        &config.tls.key_file,
+        Arc::clone(builder.crypto_provider()),
+    )?);
+    let builder = match &config.tls.client_ca_file {
+        Some(client_ca_file) => builder.with_client_cert_verifier(load_client_verifier(
+            client_ca_file,
+            &config.tls.crl_files,
+        )?),
+        None => builder.with_no_client_auth(),
+    };
+    let server_config = builder.with_cert_resolver(Arc::clone(&resolver) as _);
 
-    if certs.is_empty() {
-        anyhow::bail!("No certificates found in {:?}", config.tls.cert_file);
+    Ok((Arc::new(server_config), resolver))
+}
+
+/// Build a verifier that accepts client certificates issued by `ca_file`,
+/// rejecting any listed in the PEM `crl_files`.
+fn load_client_verifier(
+    ca_file: &Path,
+    crl_files: &[PathBuf],
+) -> Result<Arc<dyn rustls::server::danger::ClientCertVerifier>> {
+    let ca_reader = File::open(ca_file)
+        .with_context(|| format!("Failed to open client CA file: {:?}", ca_file))?;
+    let mut roots = rustls::RootCertStore::empty();
+    for cert in rustls_pemfile::certs(&mut BufReader::new(ca_reader)) {
+        let cert = cert.context("Failed to parse client CA certificates")?;
+        roots
+            .add(cert)
+            .context("Failed to add client CA certificate")?;
+    }
+    if roots.is_empty() {
+        anyhow::bail!("No certificates found in {:?}", ca_file);
     }
 
-    // Load private key
-    let key_file = File::open(&config.tls.key_file)
-        .with_context(|| format!("Failed to open key file: {:?}", config.tls.key_file))?;
-    let mut key_reader = BufReader::new(key_file);
+    let mut crls = Vec::new();
+    for crl_file in crl_files {
+        let crl_reader = File::open(crl_file)
+            .with_context(|| format!("Failed to open CRL file: {:?}", crl_file))?;
+        for crl in rustls_pemfile::crls(&mut BufReader::new(crl_reader)) {
+            crls.push(crl.with_context(|| format!("Failed to parse CRL: {:?}", crl_file))?);
+        }
+    }
 
-    let key: PrivateKeyDer<'static> = rustls_pemfile::private_key(&mut key_reader)
-        .context("Failed to parse private key")?
-        .ok_or_else(|| anyhow::anyhow!("No private key found in {:?}", config.tls.key_file))?;
+    WebPkiClientVerifier::builder(Arc::new(roots))
+        .with_crls(crls)
+        .build()
+        .context("Failed to build client certificate verifier")
+}
+
+// ============================================================================
+// Capability Handshake
+// ============================================================================
+
+/// Read frames until the first data frame, which must be a HELLO packet.
+async fn read_hello<W>(ws: &mut W, framing: Framing) -> Result<Hello, String>
+where
+    W: Stream<Item = Result<Message, tungstenite::Error>> + Unpin,
+{
+    while let Some(msg_result) = ws.next().await {
+        let msg = msg_result.map_err(|e| e.to_string())?;
+        if let Message::Close(_) = msg {
+            return Err("closed during handshake".into());
+        }
+        match decode_frame(framing, PROTOCOL_VERSION, &msg) {
+            Ok(Some(Inbound::Packet(packet))) => {
+                return packet
+                    .to_hello()
+                    .map_err(|e| format!("expected HELLO: {}", e));
+            }
+            Ok(Some(Inbound::Text(_))) => return Err("expected HELLO, got untyped text".into()),
+            Ok(None) => {}
+            Err(e) => return Err(format!("expected HELLO: {}", e)),
+        }
+    }
+    Err("connection closed during handshake".into())
+}
+
+/// Wait for the client's HELLO and answer with the negotiated HELLO-ACK.
+///
+/// On failure the session is closed with the reason in the close frame.
+async fn server_handshake<W>(
+    ws: &mut W,
+    ctx: &ServerContext,
+    endpoint: &Endpoint,
+    framing: Framing,
+) -> Result<HelloAck>
+where
+    W: Stream<Item = Result<Message, tungstenite::Error>>
+        + Sink<Message, Error = tungstenite::Error>
+        + Unpin,
+{
+    let hello = match tokio::time::timeout(ctx.handshake.timeout, read_hello(ws, framing)).await {
+        Ok(Ok(hello)) => hello,
+        Ok(Err(reason)) => {
+            let _ = ws.send(close_message(CloseCode::Protocol, &reason)).await;
+            anyhow::bail!("Handshake failed: {}", reason);
+        }
+        Err(_) => {
+            let _ = ws
+                .send(close_message(CloseCode::Policy, "handshake timeout"))
+                .await;
+            anyhow::bail!("Handshake timed out");
+        }
+    };
 
-    // Build server config
-    let server_config = rustls::ServerConfig::builder()
-        .with_no_client_auth()
-        .with_single_cert(certs, key)
-        .context("Failed to build TLS server config")?;
+    // The endpoint may accept smaller packets than the server as a whole
+    let mut capabilities = ctx.capabilities.clone();
+    if let Some(limit) = endpoint.max_packet_size {
+        capabilities.max_packet_size = capabilities.max_packet_size.min(limit);
+    }
+    match capabilities.negotiate(&hello) {
+        Ok(ack) => {
+            ws.send(encode_packet(framing, &Packet::hello_ack(&ack)))
+                .await
+                .context("Failed to send HELLO-ACK")?;
+            Ok(ack)
+        }
+        Err(e) => {
+            let _ = ws
+                .send(close_message(CloseCode::Protocol, &e.to_string()))
+                .await;
+            Err(anyhow::anyhow!("Handshake failed: {}", e))
+        }
+    }
+}
 
-    Ok(Arc::new(server_config))
+/// Whether a received packet's version fits the session's negotiated version.
+///
+/// JSON packets carrying extensions are promoted to v2, so JSON sessions take
+/// v2 packets on a v1 session too.
+fn version_accepted(framing: Framing, version: u8, packet: &Packet) -> bool {
+    packet.header.version == version
+        || (framing == Framing::Json && packet.header.version == PROTOCOL_VERSION_2)
 }
 
 // ============================================================================
 // WebSocket Session Handler
 // ============================================================================
 
-async fn handle_session(
+/// Shared state handed to every session.
+struct ServerContext {
+    handler: ServerStrategyHandler,
+    api: ProtocolApi,
+    batch: BatchConfig,
+    handshake: HandshakeConfig,
+    heartbeat: HeartbeatConfig,
+    capabilities: Capabilities,
+    /// Accepted subprotocols, in server preference order.
+    framings: Vec<Framing>,
+    /// Urgency rules for untyped text; `None` keeps it GREEN.
+    classifier: Option<Arc<ReloadingClassifier>>,
+    /// Paths sessions can upgrade on, with their strategies and rules.
+    endpoints: EndpointTable,
+    /// permessage-deflate settings; endpoints decide whether to negotiate it.
+    compression: CompressionConfig,
+    /// Topic subscriptions of every live session.
+    router: Arc<TopicRouter>,
+    /// Every live session, for the admin interface.
+    registry: Arc<SessionRegistry>,
+    /// Bearer token checks on upgrade; `None` accepts every upgrade.
+    auth: Option<TokenVerifier>,
+    /// Role grants checked before dispatch; `None` allows every packet.
+    policy: Option<Policy>,
+    /// Rebroadcast of alerts to operators; `None` when disabled.
+    alerts: Option<AlertFanout>,
+    /// Hash-chained record of critical packets and authorization decisions.
+    audit: Option<AuditLog>,
+    /// Queues for identities that are offline; `None` when store-and-forward is off.
+    store: Option<OfflineStore>,
+    /// Link to the other nodes of the cluster; an in-memory hub when standalone.
+    backplane: Arc<dyn Backplane>,
+    /// Sessions on the other nodes.
+    cluster: ClusterView,
+    /// Counters, gauges and histograms served on `/metrics`.
+    metrics: Metrics,
+    /// Flips to `true` when the server starts shutting down.
+    shutdown: watch::Sender<bool>,
+}
+
+/// Span for one connection over `transport` (`ws` or `http`). Its session id,
+/// endpoint and identity are recorded as they become known.
+fn session_span(transport: &'static str, peer: SocketAddr) -> Span {
+    info_span!(
+        "session",
+        transport,
+        %peer,
+        id = field::Empty,
+        endpoint = field::Empty,
+        identity = field::Empty
+    )
+}
+
+/// Span for handling one received packet, continuing the trace it was sent in.
+fn packet_span(packet: &Packet) -> Span {
+    let span = info_span!(
+        "receive",
+        packet_type = packet.header.type_name(),
+        urgency = packet.header.urgency.as_str(),
+        len = packet.payload.len()
+    );
+    telemetry::set_remote_parent(&span, packet);
+    span
+}
+
+/// Accept a connection, over TLS unless the server runs in plaintext mode.
+async fn handle_connection(
     stream: TcpStream,
-    tls_acceptor: TlsAcceptor,
-    handler: Arc<ServerStrategyHandler>,
-    api: Arc<ProtocolApi>,
+    tls_acceptor: Option<TlsAcceptor>,
+    ctx: Arc<ServerContext>,
 ) -> Result<()> {
     let peer_addr = stream.peer_addr().ok();
     info!("[SERVER] New connection from {:?}", peer_addr);
 
+    let Some(tls_acceptor) = tls_acceptor else {
+        return handle_session(stream, peer_addr, None, ctx).await;
+    };
+
     // TLS handshake
     let tls_stream = tls_acceptor
         .accept(stream)
         .await
         .context("TLS handshake failed")?;
 
-    // WebSocket handshake
-    let ws_stream = tokio_tungstenite::accept_async(tls_stream)
-        .await
-        .context("WebSocket handshake failed")?;
+    let identity = client_identity(&tls_stream, peer_addr)?;
+    handle_session(tls_stream, peer_addr, identity, ctx).await
+}
 
-    info!("[SERVER] WebSocket session opened for {:?}", peer_addr);
+/// Identify the client by its certificate, present only when mTLS is enabled.
+fn client_identity(
+    tls_stream: &TlsStream<TcpStream>,
+    peer_addr: Option<SocketAddr>,
+) -> Result<Option<ClientIdentity>> {
+    let identity = match tls_stream
+        .get_ref()
+        .1
+        .peer_certificates()
+        .and_then(<[_]>::first)
+    {
+        Some(cert) => Some(ClientIdentity::from_certificate(cert).map_err(anyhow::Error::msg)?),
+        None => None,
+    };
+    if let Some(identity) = &identity {
+        info!(
+            "[SERVER] Client {:?} authenticated as {} (SANs {:?})",
+            peer_addr, identity.subject, identity.sans
+        );
+    }
+    Ok(identity)
+}
+
+async fn handle_session<S>(
+    stream: S,
+    peer_addr: Option<SocketAddr>,
+    identity: Option<ClientIdentity>,
+    ctx: Arc<ServerContext>,
+) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    // WebSocket handshake: the upgrade module decides endpoint, token, roles,
+    // compression and subprotocol
+    let mut upgrade = None;
+    // The error type is fixed by tungstenite's handshake callback
+    #[allow(clippy::result_large_err)]
+    let accept_upgrade = |request: &Request, response: Response| {
+        let (response, accepted) =
+            upgrade::accept(&ctx, peer_addr, identity.as_ref(), request, response)?;
+        upgrade = Some(accepted);
+        Ok(response)
+    };
+    // The endpoint is only known inside the callback, so messages are capped at
+    // the largest size any endpoint negotiates; the session's own, possibly
+    // smaller, limit is enforced per message once the handshake is done
+    let limit = ctx
+        .endpoints
+        .max_packet_size(ctx.capabilities.max_packet_size) as usize;
+    let config = WebSocketConfig::default()
+        .max_message_size(Some(limit))
+        .max_frame_size(Some(limit));
+    let mut ws_stream = tokio_tungstenite::accept_hdr_async_with_config(
+        DeflateStream::new(stream),
+        accept_upgrade,
+        Some(config),
+    )
+    .await
+    .context("WebSocket handshake failed")?;
+    let Upgrade {
+        endpoint,
+        framing,
+        claims,
+        roles,
+        deflate,
+    } = upgrade.context("Upgrade accepted without a decision")?;
+    Span::current().record("endpoint", endpoint.path.as_str());
+    if let Some(params) = deflate {
+        info!(
+            "[SERVER] Compressing session with {:?} (windows {}/{} bits)",
+            peer_addr, params.server_max_window_bits, params.client_max_window_bits
+        );
+        ws_stream.get_mut().enable(DeflateCodec::new(
+            params,
+            DeflateRole::Server,
+            ctx.compression.max_message_size,
+        ));
+    }
+    if let Some(claims) = &claims {
+        info!(
+            "[SERVER] Client {:?} presented a token for {} (roles {:?})",
+            peer_addr, claims.sub, claims.roles
+        );
+    }
 
-    let (mut ws_sink, mut ws_source) = ws_stream.split();
+    // Capability handshake
+    let ack = server_handshake(&mut ws_stream, &ctx, &endpoint, framing).await?;
+    info!(
+        "[SERVER] WebSocket session opened for {:?} on {} ({}, v{}, codec {}, features {:?})",
+        peer_addr, endpoint.path, framing, ack.version, ack.codec, ack.features
+    );
 
-    // Read loop
-    while let Some(msg_result) = ws_source.next().await {
-        match msg_result {
-            Ok(Message::Text(text)) => {
-                // Create packet and dispatch via strategy
-                let packet = api.make_packet(&text, Urgency::Green);
-                api.dispatch(&packet, handler.as_ref()).await;
+    let (ws_sink, mut ws_source) = ws_stream.split();
+    let max_packet_size = ack.max_packet_size as usize;
 
-                // Echo back
-                if let Err(e) = ws_sink.send(Message::Text(text.clone())).await {
-                    warn!("[SERVER] Failed to send response: {}", e);
-                    break;
-                }
+    // Writer task owns the sink so GREEN packets can be coalesced.
+    // Batches are binary containers, so JSON sessions never batch.
+    let (out_tx, out_rx) = mpsc::channel(256);
+    let coalescer = if ack.has_feature(FEATURE_BATCH) && framing == Framing::Binary {
+        BatchCoalescer::new(
+            ctx.batch.window,
+            ctx.batch.max_packets,
+            ctx.batch.max_bytes.min(max_packet_size),
+        )
+    } else {
+        BatchCoalescer::disabled()
+    };
+    let traffic = Arc::new(Traffic::default());
+    let writer = tokio::spawn(
+        run_writer(
+            ws_sink,
+            out_rx,
+            coalescer,
+            ack.version,
+            framing,
+            Arc::clone(&traffic),
+        )
+        .in_current_span(),
+    );
+    let drone_stream = tokio::spawn(
+        forward_drone_stream(
+            ctx.handler.subscribe(),
+            out_tx.clone(),
+            ctx.metrics.broadcast_lagged.with(&["drone_stream"]),
+        )
+        .in_current_span(),
+    );
+    let session = ctx.registry.register(NewSession {
+        peer: peer_addr,
+        endpoint: endpoint.path.clone(),
+        identity,
+        claims,
+        roles,
+        framing,
+        version: ack.version,
+        traffic,
+        out_tx: out_tx.clone(),
+    });
+    let subscriber = ctx.router.register(session.id, out_tx.clone());
+    let handler = SessionHandler::new(
+        &ctx.handler,
+        &endpoint,
+        session.identity.as_ref(),
+        session.claims.as_ref(),
+    );
+    Span::current()
+        .record("id", session.id)
+        .record("identity", handler.who());
+    info!(
+        "[SERVER] Registered {:?} as session {} ({}, roles {:?})",
+        peer_addr,
+        session.id,
+        handler.who(),
+        session.roles
+    );
+    for packet in session_started(&ctx, &session, &subscriber, handler.who()) {
+        if out_tx.send(Outbound::Packet(packet)).await.is_err() {
+            break;
+        }
+    }
+
+    let mut shutdown = ctx.shutdown.subscribe();
+    let mut link = LinkMonitor::new(
+        ctx.heartbeat.ping_interval,
+        ctx.heartbeat.pong_timeout,
+        Instant::now(),
+    );
+    let mut link_event = LinkEvent::Closed;
+    handler.on_link_event(&LinkEvent::Up).await;
+
+    // Read loop, until the client leaves or goes silent, an operator kicks the
+    // session or the server shuts down. A packet being handled is always finished first.
+    'session: loop {
+        let msg_result = tokio::select! {
+            _ = shutting_down(&mut shutdown) => {
+                info!("[SERVER] Closing session {} for shutdown", session.id);
+                let close = close_message(CloseCode::Away, "server shutting down");
+                let _ = out_tx.send(Outbound::Message(close)).await;
+                break;
             }
-            Ok(Message::Binary(data)) => {
-                // Parse as protocol packet if possible
-                match Packet::from_bytes(&data) {
-                    Ok(packet) => {
-                        api.dispatch(&packet, handler.as_ref()).await;
-                        // Echo back
-                        if let Err(e) = ws_sink.send(Message::Binary(data)).await {
-                            warn!("[SERVER] Failed to send response: {}", e);
-                            break;
-                        }
-                    }
-                    Err(e) => {
-                        warn!("[SERVER] Invalid packet format: {}", e);
-                    }
-                }
+            msg_result = ws_source.next() => match msg_result {
+                Some(msg_result) => msg_result,
+                None => break,
+            },
+            _ = session.kicked() => {
+                info!("[SERVER] Session {} kicked by operator", session.id);
+                break;
             }
-            Ok(Message::Ping(data)) => {
-                let _ = ws_sink.send(Message::Pong(data)).await;
+            _ = sleep_until_deadline(link.next_ping()) => {
+                let heartbeat = link.ping(Instant::now());
+                let ping = out_tx.send(Outbound::Message(Message::Ping(Default::default()))).await;
+                if ping.is_err() || out_tx.send(Outbound::Packet(heartbeat)).await.is_err() {
+                    break;
+                }
+                continue;
             }
-            Ok(Message::Pong(_)) => {}
-            Ok(Message::Close(_)) => {
-                info!("[SERVER] Client requested close");
+            _ = sleep_until_deadline(link.dead_after()) => {
+                let silent_for = link.silent_for(Instant::now());
+                link_event = LinkEvent::Lost { silent_for };
+                let close = close_message(CloseCode::Away, "heartbeat timeout");
+                let _ = out_tx.send(Outbound::Message(close)).await;
                 break;
             }
-            Ok(Message::Frame(_)) => {}
+        };
+        let msg = match msg_result {
+            Ok(msg) => msg,
             Err(e) => {
                 error!("[SERVER] WebSocket error: {}", e);
                 break;
             }
+        };
+        link.record_activity(Instant::now());
+
+        match msg {
+            Message::Text(_) | Message::Binary(_) if msg.len() > max_packet_size => {
+                warn!(
+                    "[SERVER] Frame of {} bytes exceeds negotiated maximum {}",
+                    msg.len(),
+                    max_packet_size
+                );
+                let close = close_message(CloseCode::Size, "packet exceeds negotiated maximum");
+                let _ = out_tx.send(Outbound::Message(close)).await;
+                break;
+            }
+            Message::Ping(data) => {
+                let _ = out_tx.send(Outbound::Message(Message::Pong(data))).await;
+            }
+            Message::Close(_) => {
+                info!("[SERVER] Client requested close");
+                break;
+            }
+            Message::Pong(_) | Message::Frame(_) => {}
+            Message::Text(_) | Message::Binary(_) => match decode_frame(framing, ack.version, &msg)
+            {
+                Ok(Some(Inbound::Text(text))) => {
+                    session.traffic.record_in(msg.len(), 1);
+
+                    // Classified text goes through the same checks as a typed packet
+                    let packet = classify_text(&ctx, text).with_version(ack.version);
+                    let span = packet_span(&packet);
+                    if handle_packet(&ctx, &handler, &session, &subscriber, &out_tx, packet)
+                        .instrument(span)
+                        .await
+                        .is_err()
+                    {
+                        warn!("[SERVER] Failed to send response: writer closed");
+                        break;
+                    }
+                }
+                Ok(Some(Inbound::Packet(packet)))
+                    if !version_accepted(framing, ack.version, &packet) =>
+                {
+                    warn!(
+                        "[SERVER] Dropping v{} packet on v{} session",
+                        packet.header.version, ack.version
+                    );
+                    let notice = Packet::error_notice(&ErrorNotice::new(
+                        ERROR_WRONG_VERSION,
+                        format!(
+                            "packet is v{}, the session negotiated v{}",
+                            packet.header.version, ack.version
+                        ),
+                    ));
+                    if out_tx.send(Outbound::Packet(notice)).await.is_err() {
+                        warn!("[SERVER] Failed to send response: writer closed");
+                        break;
+                    }
+                }
+                Ok(Some(Inbound::Packet(packet))) => {
+                    // Handle batch members one by one so control packets inside still apply;
+                    // unbatch rejects members of another version than the container
+                    let packets = if packet.is_batch() {
+                        match packet.unbatch() {
+                            Ok(packets) => packets,
+                            Err(e) => {
+                                warn!("[SERVER] Dropping malformed batch packet: {}", e);
+                                ctx.metrics.decode_error(&endpoint.path);
+                                continue;
+                            }
+                        }
+                    } else {
+                        vec![packet]
+                    };
+                    session.traffic.record_in(msg.len(), packets.len());
+                    for packet in packets {
+                        let span = packet_span(&packet);
+                        if handle_packet(&ctx, &handler, &session, &subscriber, &out_tx, packet)
+                            .instrument(span)
+                            .await
+                            .is_err()
+                        {
+                            warn!("[SERVER] Failed to send response: writer closed");
+                            break 'session;
+                        }
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    warn!("[SERVER] Invalid packet format: {}", e);
+                    ctx.metrics.decode_error(&endpoint.path);
+                }
+            },
         }
     }
 
-    info!("[SERVER] WebSocket session closed for {:?}", peer_addr);
+    handler.on_link_event(&link_event).await;
+    session_ended(&ctx, &session, &subscriber);
+
+    // Let the writer flush anything still queued
+    drone_stream.abort();
+    let _ = drone_stream.await;
+    drop(subscriber);
+    drop(session);
+    drop(out_tx);
+    match writer.await {
+        Ok(Err(e)) => warn!("[SERVER] Writer error for {:?}: {}", peer_addr, e),
+        Err(e) => error!("[SERVER] Writer task failed for {:?}: {}", peer_addr, e),
+        Ok(Ok(())) => {}
+    }
+
+    info!(
+        "[SERVER] WebSocket session closed for {:?} ({} sessions remain)",
+        peer_addr,
+        ctx.registry.session_count()
+    );
     Ok(())
 }
 
+/// Announce a registered session to the cluster and pick up where the
+/// identity's previous session left off.
+///
+/// Restores the identity's subscriptions and returns its queued packets, most urgent first.
+fn session_started(
+    ctx: &ServerContext,
+    session: &SessionHandle,
+    subscriber: &Subscriber,
+    who: &str,
+) -> Vec<Packet> {
+    ctx.backplane.broadcast(ClusterEvent::SessionUp {
+        session: session.id,
+        who: session.identity_key().map(String::from),
+    });
+
+    let released = ctx
+        .store
+        .as_ref()
+        .zip(session.identity_key())
+        .and_then(|(store, key)| store.release(key));
+    let Some((filters, replay)) = released else {
+        return Vec::new();
+    };
+    subscriber.subscribe(filters);
+    info!(
+        "[SERVER] Replaying {} queued packet(s) to {} with subscriptions {:?}",
+        replay.len(),
+        who,
+        subscriber
+            .filters()
+            .iter()
+            .map(|f| f.as_str())
+            .collect::<Vec<_>>()
+    );
+    replay
+}
+
+/// Announce the end of a session to the cluster and start queueing for its
+/// identity, unless another of its sessions is still live.
+fn session_ended(ctx: &ServerContext, session: &SessionHandle, subscriber: &Subscriber) {
+    ctx.backplane.broadcast(ClusterEvent::SessionDown {
+        session: session.id,
+    });
+
+    if let (Some(store), Some(key)) = (&ctx.store, session.identity_key()) {
+        let still_online = ctx
+            .registry
+            .list()
+            .iter()
+            .any(|other| other.id != session.id && other.identity_key() == Some(key));
+        if !still_online {
+            let alerts = ctx
+                .policy
+                .as_ref()
+                .is_none_or(|policy| policy.receives_alerts(&session.roles));
+            store.hold(key, subscriber.filters(), alerts);
+        }
+    }
+}
+
 // ============================================================================
 // Main Server Loop
 // ============================================================================
 
-async fn run_server(config: AddrConfig) -> Result<()> {
-    // Initialize TLS
-    let tls_config = load_tls_config(&config)?;
-    let tls_acceptor = TlsAcceptor::from(tls_config);
+/// Poll the classification rules file and swap in new rules when it changes.
+///
+/// A file that fails to parse is logged and the previous rules stay in force.
+async fn watch_classifier(classifier: Arc<ReloadingClassifier>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        match classifier.reload_if_changed() {
+            Ok(true) => info!(
+                "Reloaded {} classification rules from {:?}",
+                classifier.current().len(),
+                classifier.path()
+            ),
+            Ok(false) => {}
+            Err(e) => warn!(
+                "Keeping previous classification rules, reload of {:?} failed: {}",
+                classifier.path(),
+                e
+            ),
+        }
+    }
+}
+
+/// Reload the server certificate when its files change or on SIGHUP.
+async fn watch_certificates(resolver: Arc<ReloadingCertResolver>, interval: Option<Duration>) {
+    let mut ticker = interval.map(tokio::time::interval);
+    if let Some(ticker) = &mut ticker {
+        ticker.tick().await;
+    }
+    let mut hangup = hangup_signal();
+
+    loop {
+        let result = tokio::select! {
+            _ = next_tick(&mut ticker) => resolver.reload_if_changed(),
+            _ = next_hangup(&mut hangup) => {
+                info!("Received SIGHUP, reloading TLS certificate");
+                resolver.reload().map(|()| true)
+            }
+        };
+        match result {
+            Ok(true) => info!("Reloaded TLS certificate from {:?}", resolver.cert_file()),
+            Ok(false) => {}
+            Err(e) => error!(
+                "Keeping current TLS certificate, reload of {:?} failed: {:#}",
+                resolver.cert_file(),
+                e
+            ),
+        }
+    }
+}
+
+/// Resolves on the ticker's next tick, or never without a ticker.
+async fn next_tick(ticker: &mut Option<tokio::time::Interval>) {
+    match ticker {
+        Some(ticker) => {
+            ticker.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(unix)]
+type HangupSignal = tokio::signal::unix::Signal;
+#[cfg(not(unix))]
+type HangupSignal = ();
+
+/// Listen for SIGHUP where the platform has it.
+fn hangup_signal() -> Option<HangupSignal> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::hangup()) {
+            Ok(hangup) => Some(hangup),
+            Err(e) => {
+                warn!("Certificate reload on SIGHUP unavailable: {}", e);
+                None
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        None
+    }
+}
+
+/// Resolves on the next SIGHUP, or never without a listener.
+async fn next_hangup(hangup: &mut Option<HangupSignal>) {
+    match hangup {
+        #[cfg(unix)]
+        Some(hangup) => {
+            hangup.recv().await;
+        }
+        _ => std::future::pending().await,
+    }
+}
+
+/// Everything the server reads from the environment at startup.
+struct ServerSettings {
+    addr: AddrConfig,
+    batch: BatchConfig,
+    handshake: HandshakeConfig,
+    heartbeat: HeartbeatConfig,
+    classify: ClassifierConfig,
+    shutdown: ShutdownConfig,
+    auth: AuthConfig,
+    alerts: AlertConfig,
+    audit: AuditConfig,
+    store: StoreForwardConfig,
+    cluster: ClusterConfig,
+    http: HttpConfig,
+    metrics: MetricsConfig,
+    compression: CompressionConfig,
+    endpoints: EndpointConfig,
+}
+
+impl ServerSettings {
+    fn from_env() -> Result<Self, EnvError> {
+        Ok(Self {
+            addr: AddrConfig::from_env("0.0.0.0", 8443)?,
+            batch: BatchConfig::from_env()?,
+            handshake: HandshakeConfig::from_env()?,
+            heartbeat: HeartbeatConfig::from_env()?,
+            classify: ClassifierConfig::from_env()?,
+            shutdown: ShutdownConfig::from_env()?,
+            auth: AuthConfig::from_env()?,
+            alerts: AlertConfig::from_env()?,
+            audit: AuditConfig::from_env()?,
+            store: StoreForwardConfig::from_env()?,
+            cluster: ClusterConfig::from_env(),
+            http: HttpConfig::from_env()?,
+            metrics: MetricsConfig::from_env(),
+            compression: CompressionConfig::from_env()?,
+            endpoints: EndpointConfig::from_env(),
+        })
+    }
+}
+
+async fn run_server(settings: ServerSettings) -> Result<ExitCode> {
+    let ServerSettings {
+        addr: config,
+        batch,
+        handshake,
+        heartbeat,
+        classify,
+        shutdown: shutdown_config,
+        auth,
+        alerts,
+        audit,
+        store,
+        cluster,
+        http,
+        metrics,
+        endpoints,
+        compression,
+    } = settings;
+
+    // Initialize TLS, unless a sidecar in front of us terminates it
+    let tls_acceptor = if config.use_tls {
+        let (tls_config, cert_resolver) = load_tls_config(&config)?;
+        tokio::spawn(watch_certificates(
+            cert_resolver,
+            config.tls.reload_interval,
+        ));
+        Some(TlsAcceptor::from(tls_config))
+    } else {
+        warn!("⚠️  TLS IS DISABLED: serving plaintext ws://. Only run this behind a TLS-terminating proxy.");
+        if config.tls.client_ca_file.is_some() {
+            warn!(
+                "⚠️  TLS_CLIENT_CA is ignored without TLS; sessions carry no certificate identity."
+            );
+        }
+        None
+    };
 
     // Bind TCP listener
     let listener = TcpListener::bind(config.addr())
@@ -202,48 +1406,733 @@ async fn run_server(config: AddrConfig) -> Result<()> {
 
     info!("🚀 Server listening on {}", config.ws_url());
 
-    let handler = Arc::new(ServerStrategyHandler::new());
-    let api = Arc::new(ProtocolApi::new());
+    let http_listener = match &http.listen {
+        Some(listen) => {
+            let listener = TcpListener::bind(listen)
+                .await
+                .with_context(|| format!("Failed to bind HTTP listener to {}", listen))?;
+            let scheme = if config.use_tls { "https" } else { "http" };
+            info!(
+                "  HTTP: {}://{}{} and {}",
+                scheme,
+                listen,
+                http::EVENTS_PATH,
+                http::PACKETS_PATH
+            );
+            Some(listener)
+        }
+        None => None,
+    };
+
+    let metrics_listener = match &metrics.listen {
+        Some(listen) => {
+            let listener = TcpListener::bind(listen)
+                .await
+                .with_context(|| format!("Failed to bind metrics listener to {}", listen))?;
+            info!("  Metrics: http://{}{}", listen, metrics::METRICS_PATH);
+            Some(listener)
+        }
+        None => None,
+    };
+
+    let mut capabilities = Capabilities {
+        max_packet_size: handshake.max_packet_size,
+        ..Capabilities::default()
+    };
+    if let Some(versions) = &handshake.versions {
+        capabilities.versions.retain(|v| versions.contains(v));
+    }
+    info!("  Protocol versions: {:?}", capabilities.versions);
+
+    let framings = if config.subprotocols.is_empty() {
+        Framing::ALL.to_vec()
+    } else {
+        config
+            .subprotocols
+            .iter()
+            .filter_map(|name| {
+                let framing = Framing::from_subprotocol(name);
+                if framing.is_none() {
+                    warn!("Ignoring unknown subprotocol: {}", name);
+                }
+                framing
+            })
+            .collect()
+    };
+    if framings.is_empty() {
+        anyhow::bail!("No supported subprotocols configured");
+    }
+    info!(
+        "  Subprotocols: {:?}",
+        framings
+            .iter()
+            .map(Framing::subprotocol)
+            .collect::<Vec<_>>()
+    );
+
+    let classifier = match &classify.rules_file {
+        Some(path) => {
+            let classifier = ReloadingClassifier::load(path)
+                .with_context(|| format!("Failed to load classification rules from {:?}", path))?;
+            info!(
+                "  Classification rules: {} from {:?}",
+                classifier.current().len(),
+                path
+            );
+            let classifier = Arc::new(classifier);
+            tokio::spawn(watch_classifier(
+                Arc::clone(&classifier),
+                classify.reload_interval,
+            ));
+            Some(classifier)
+        }
+        None => None,
+    };
+
+    let policy = match &auth.policy_file {
+        Some(path) => {
+            let policy = Policy::load(path)?;
+            info!(
+                "  Authorization policy: {} roles from {:?}",
+                policy.role_count(),
+                path
+            );
+            Some(policy)
+        }
+        None => None,
+    };
+    let alerts = AlertFanout::from_config(&alerts)?;
+    if let Some(alerts) = &alerts {
+        info!("  Alert fan-out: {:?}", alerts.urgencies());
+    }
+    let audit = AuditLog::open(&audit)?;
+    if let Some(audit) = &audit {
+        info!("  Audit log: {:?}", audit.path());
+    }
+    let store = OfflineStore::open(&store)?;
+    if let Some(store) = &store {
+        info!(
+            "  Store-and-forward: {} queued packet(s) for {} offline identities",
+            store.queued(),
+            store.identities()
+        );
+    }
+    let registry = Arc::new(SessionRegistry::default());
+    let backplane: Arc<dyn Backplane> = if cluster.is_clustered() {
+        let sessions = Arc::clone(&registry);
+        let snapshot: Snapshot = Arc::new(move || {
+            sessions
+                .list()
+                .iter()
+                .map(|handle| ClusterEvent::SessionUp {
+                    session: handle.id,
+                    who: handle.identity_key().map(String::from),
+                })
+                .collect()
+        });
+        let mesh = TcpMesh::start(&cluster, snapshot).await?;
+        info!(
+            "  Cluster: node {}, listening on {}, peers {:?}",
+            cluster.node_id,
+            mesh.local_addr()
+                .map_or_else(|| "-".to_string(), |addr| addr.to_string()),
+            cluster.peers
+        );
+        Arc::new(mesh)
+    } else {
+        Arc::new(MemoryHub::default().join(cluster.node_id.clone()))
+    };
+    let auth = TokenVerifier::from_config(&auth)?;
+    if let Some(verifier) = &auth {
+        info!("  Token keys: {}", verifier.key_count());
+    }
+
+    let endpoints = match &endpoints.file {
+        Some(path) => EndpointTable::load(path)?,
+        None => EndpointTable::single(&config.endpoint),
+    };
+    for endpoint in endpoints.iter() {
+        if endpoint.auth_required == Some(true) && auth.is_none() {
+            anyhow::bail!(
+                "Endpoint {} requires a token but no AUTH_KEYS_FILE is configured",
+                endpoint.path
+            );
+        }
+        if endpoint.roles.is_some() && policy.is_none() {
+            anyhow::bail!(
+                "Endpoint {} restricts roles but no AUTHZ_POLICY_FILE is configured",
+                endpoint.path
+            );
+        }
+        if endpoint.admin && policy.is_none() {
+            anyhow::bail!(
+                "Endpoint {} answers ADMIN packets but no AUTHZ_POLICY_FILE is configured",
+                endpoint.path
+            );
+        }
+        info!(
+            "  Endpoint {}: strategies {:?}{}{}",
+            endpoint.path,
+            endpoint.strategies,
+            if endpoint.admin { ", admin" } else { "" },
+            if endpoint.compression.unwrap_or(compression.enabled) {
+                ", permessage-deflate"
+            } else {
+                ""
+            }
+        );
+    }
+
+    let ctx = Arc::new(ServerContext {
+        handler: ServerStrategyHandler::new(),
+        api: ProtocolApi::new(),
+        batch,
+        handshake,
+        heartbeat,
+        capabilities,
+        framings,
+        classifier,
+        endpoints,
+        compression,
+        router: Arc::new(TopicRouter::default()),
+        registry,
+        auth,
+        policy,
+        alerts,
+        audit,
+        store,
+        backplane,
+        cluster: ClusterView::default(),
+        metrics: Metrics::default(),
+        shutdown: watch::Sender::new(false),
+    });
+
+    tokio::spawn(run_cluster(Arc::clone(&ctx)));
+    if let Some(listener) = metrics_listener {
+        tokio::spawn(metrics::serve(listener, Arc::clone(&ctx)));
+    }
+
+    // Accept loop, until a shutdown signal arrives
+    let mut sessions = JoinSet::new();
+    let signal = shutdown_signal();
+    tokio::pin!(signal);
+    loop {
+        tokio::select! {
+            signal_name = &mut signal => {
+                info!("Received {}, shutting down", signal_name?);
+                break;
+            }
+            Some(_) = sessions.join_next(), if !sessions.is_empty() => {}
+            accepted = listener.accept() => match accepted {
+                Ok((stream, peer)) => {
+                    let tls_acceptor = tls_acceptor.clone();
+                    let ctx = Arc::clone(&ctx);
+                    let span = session_span("ws", peer);
+                    ctx.metrics.connections.with(&["ws"]).inc();
+
+                    sessions.spawn(
+                        async move {
+                            if let Err(e) = handle_connection(stream, tls_acceptor, ctx).await {
+                                error!("[SERVER] Session error: {}", e);
+                            }
+                        }
+                        .instrument(span),
+                    );
+                }
+                Err(e) => {
+                    error!("[SERVER] Accept error: {}", e);
+                }
+            },
+            accepted = accept_optional(http_listener.as_ref()) => match accepted {
+                Ok((stream, peer)) => {
+                    let tls_acceptor = tls_acceptor.clone();
+                    let ctx = Arc::clone(&ctx);
+                    let span = session_span("http", peer);
+                    ctx.metrics.connections.with(&["http"]).inc();
+
+                    sessions.spawn(
+                        async move {
+                            if let Err(e) =
+                                http::handle_connection(stream, tls_acceptor, ctx, http.keepalive)
+                                    .await
+                            {
+                                warn!("[HTTP] Connection error: {:#}", e);
+                            }
+                        }
+                        .instrument(span),
+                    );
+                }
+                Err(e) => {
+                    error!("[HTTP] Accept error: {}", e);
+                }
+            },
+        }
+    }
 
-    // Accept loop
+    // Stop accepting, ask every session to close and let their queues drain
+    drop(listener);
+    drop(http_listener);
+    ctx.shutdown.send_replace(true);
+    info!(
+        "Draining {} session(s), up to {:?}",
+        sessions.len(),
+        shutdown_config.drain_timeout
+    );
+    let drained = tokio::time::timeout(shutdown_config.drain_timeout, async {
+        while sessions.join_next().await.is_some() {}
+    })
+    .await;
+
+    let exit_code = match drained {
+        Ok(()) => {
+            info!("All sessions closed, exiting");
+            ExitCode::SUCCESS
+        }
+        Err(_) => {
+            warn!(
+                "Drain period expired with {} session(s) still open, aborting them",
+                sessions.len()
+            );
+            sessions.shutdown().await;
+            ExitCode::from(EXIT_DRAIN_TIMEOUT)
+        }
+    };
+
+    if let Some(store) = &ctx.store {
+        store.flush();
+    }
+    // Seal the audit chain; the head lets a later truncation of the log be spotted
+    if let Some(audit) = &ctx.audit {
+        if let Some(head) = audit.close().await {
+            info!("Audit log closed at seq {} hash {}", head.seq, head.hash);
+        }
+    }
+    Ok(exit_code)
+}
+
+/// Accept from a listener that may not be configured; without one, never resolves.
+async fn accept_optional(
+    listener: Option<&TcpListener>,
+) -> std::io::Result<(TcpStream, SocketAddr)> {
+    match listener {
+        Some(listener) => listener.accept().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Apply what the other nodes of the cluster report: their sessions, topic
+/// packets for local subscribers and alerts for local operators.
+async fn run_cluster(ctx: Arc<ServerContext>) {
+    let mut events = ctx.backplane.subscribe();
     loop {
-        match listener.accept().await {
-            Ok((stream, _)) => {
-                let tls_acceptor = tls_acceptor.clone();
-                let handler = Arc::clone(&handler);
-                let api = Arc::clone(&api);
-
-                tokio::spawn(async move {
-                    if let Err(e) = handle_session(stream, tls_acceptor, handler, api).await {
-                        error!("[SERVER] Session error: {}", e);
+        let message = match events.recv().await {
+            Ok(message) => message,
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                warn!("[CLUSTER] Fell behind, missed {} cluster events", missed);
+                ctx.metrics.broadcast_lagged.with(&["cluster"]).add(missed);
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+        if message.node == ctx.backplane.node_id() {
+            continue;
+        }
+        ctx.cluster.apply(&message);
+
+        match &message.event {
+            ClusterEvent::Publish { packet } => match Packet::from_bytes(packet) {
+                Ok(packet) if relay_allowed(&ctx, &message.node, &packet) => {
+                    ctx.router.publish(&packet);
+                    if let Some(store) = &ctx.store {
+                        store.publish(&packet);
                     }
-                });
+                }
+                Ok(_) => {}
+                Err(e) => warn!("[CLUSTER] Invalid packet from node {}: {}", message.node, e),
+            },
+            ClusterEvent::Alert { packet } => {
+                let Some(alerts) = &ctx.alerts else {
+                    continue;
+                };
+                let packet = match Packet::from_bytes(packet) {
+                    Ok(packet) => packet,
+                    Err(e) => {
+                        warn!("[CLUSTER] Invalid alert from node {}: {}", message.node, e);
+                        continue;
+                    }
+                };
+                if !relay_allowed(&ctx, &message.node, &packet) {
+                    continue;
+                }
+                if let Some(packet) = alerts.admit_relayed(packet, &message.node) {
+                    let delivered = alerts.fan_out(
+                        &packet,
+                        None,
+                        &ctx.registry,
+                        &ctx.router,
+                        ctx.store.as_ref(),
+                        ctx.policy.as_ref(),
+                    );
+                    info!(
+                        "[ALERT] {} alert from node {} rebroadcast to {} session(s)",
+                        packet.header.urgency.as_str(),
+                        message.node,
+                        delivered
+                    );
+                }
             }
-            Err(e) => {
-                error!("[SERVER] Accept error: {}", e);
+            ClusterEvent::SessionUp { .. }
+            | ClusterEvent::SessionDown { .. }
+            | ClusterEvent::NodeDown => {}
+        }
+    }
+}
+
+/// Whether the policy lets peer `node` relay `packet`, checked against the
+/// roles it gives the node id. Denials are logged as audit records.
+fn relay_allowed(ctx: &ServerContext, node: &str, packet: &Packet) -> bool {
+    let Some(policy) = &ctx.policy else {
+        return true;
+    };
+    let roles = policy.roles_for_node(node);
+    match policy.authorize(&roles, packet) {
+        Ok(()) => true,
+        Err(reason) => {
+            warn!(
+                target: "audit",
+                node,
+                roles = ?roles,
+                packet_type = packet.header.type_name(),
+                urgency = packet.header.urgency.as_str(),
+                topic = packet.topic(),
+                decision = "deny",
+                "{}",
+                reason
+            );
+            false
+        }
+    }
+}
+
+/// Resolves once the shutdown flag is set (or its sender is gone).
+async fn shutting_down(shutdown: &mut watch::Receiver<bool>) {
+    let _ = shutdown.wait_for(|stopping| *stopping).await;
+}
+
+/// Exit status when sessions were still open at the end of the drain period.
+const EXIT_DRAIN_TIMEOUT: u8 = 2;
+
+/// Wait for SIGINT or, on Unix, SIGTERM. Returns the signal's name.
+async fn shutdown_signal() -> Result<&'static str> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate =
+            signal(SignalKind::terminate()).context("Failed to install SIGTERM handler")?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => {
+                result.context("Failed to listen for SIGINT")?;
+                Ok("SIGINT")
             }
+            _ = terminate.recv() => Ok("SIGTERM"),
         }
     }
+    #[cfg(not(unix))]
+    {
+        tokio::signal::ctrl_c()
+            .await
+            .context("Failed to listen for Ctrl-C")?;
+        Ok("Ctrl-C")
+    }
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    // Initialize tracing
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::from_default_env()
-                .add_directive("ws_server=info".parse().unwrap())
-                .add_directive("tokio_tungstenite=info".parse().unwrap()),
-        )
+/// Install the log subscriber, writing text or JSON lines as configured.
+///
+/// Spans are also exported when an OTLP endpoint is configured; the returned
+/// provider flushes them at exit.
+fn init_tracing(log: &LogConfig, trace: &TraceConfig) -> Result<Option<SdkTracerProvider>> {
+    let filter = tracing_subscriber::EnvFilter::from_default_env()
+        .add_directive("ws_server=info".parse().unwrap())
+        .add_directive("audit=info".parse().unwrap())
+        .add_directive("tokio_tungstenite=info".parse().unwrap());
+    let fmt = tracing_subscriber::fmt::layer();
+    let fmt = match log.format {
+        LogFormat::Text => fmt.boxed(),
+        LogFormat::Json => fmt
+            .json()
+            .with_current_span(false)
+            .with_span_list(true)
+            .boxed(),
+    };
+    let (otlp, provider) = match telemetry::otlp_layer(trace, "ws-server")? {
+        Some((layer, provider)) => (Some(layer), Some(provider)),
+        None => (None, None),
+    };
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt)
+        .with(otlp)
         .init();
+    if let Some(url) = trace.traces_url() {
+        info!("Exporting spans to {}", url);
+    }
+    Ok(provider)
+}
 
-    let config = AddrConfig::from_env_defaults("0.0.0.0", 8443);
+#[tokio::main]
+async fn main() -> Result<ExitCode> {
+    let tracer = init_tracing(&LogConfig::from_env()?, &TraceConfig::from_env())?;
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("verify-audit") {
+        return Ok(audit::verify_command(&args[1..], &AuditConfig::from_env()?));
+    }
+
+    let settings = ServerSettings::from_env()?;
 
     info!("Starting WebSocket server...");
-    info!("  Host: {}", config.host);
-    info!("  Port: {}", config.port);
-    info!("  Cert: {:?}", config.tls.cert_file);
-    info!("  Key:  {:?}", config.tls.key_file);
+    info!("  Host: {}", settings.addr.host);
+    info!("  Port: {}", settings.addr.port);
+    info!("  Cert: {:?}", settings.addr.tls.cert_file);
+    info!("  Key:  {:?}", settings.addr.tls.key_file);
+    info!("  Batch window: {:?}", settings.batch.window);
+    info!("  Max packet size: {}", settings.handshake.max_packet_size);
+    info!("  Ping interval: {:?}", settings.heartbeat.ping_interval);
+
+    let exit_code = run_server(settings).await;
+    if let Some(tracer) = tracer {
+        if let Err(e) = tracer.shutdown() {
+            warn!("Failed to flush spans: {}", e);
+        }
+    }
+    exit_code
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protocol::TopicFilter;
+    use registry::SessionGuard;
+    use std::io::Write as _;
+
+    /// Server with in-memory state only, as `run_server` would build it.
+    pub(crate) fn test_context(
+        policy: Option<Policy>,
+        classifier: Option<ReloadingClassifier>,
+    ) -> ServerContext {
+        let cluster = MemoryHub::default().join("test".to_string());
+        ServerContext {
+            handler: ServerStrategyHandler::new(),
+            api: ProtocolApi::new(),
+            batch: BatchConfig::default(),
+            handshake: HandshakeConfig::default(),
+            heartbeat: HeartbeatConfig::default(),
+            capabilities: Capabilities::default(),
+            framings: Framing::ALL.to_vec(),
+            classifier: classifier.map(Arc::new),
+            endpoints: EndpointTable::single("/"),
+            compression: CompressionConfig::default(),
+            router: Arc::new(TopicRouter::default()),
+            registry: Arc::new(SessionRegistry::default()),
+            auth: None,
+            policy,
+            alerts: None,
+            audit: None,
+            store: None,
+            backplane: Arc::new(cluster),
+            cluster: ClusterView::default(),
+            metrics: Metrics::default(),
+            shutdown: watch::Sender::new(false),
+        }
+    }
+
+    fn register(
+        ctx: &ServerContext,
+        role: &str,
+        peer: Option<&str>,
+        out_tx: &mpsc::Sender<Outbound>,
+    ) -> SessionGuard {
+        ctx.registry.register(NewSession {
+            peer: peer.map(|addr| addr.parse().unwrap()),
+            endpoint: "/".to_string(),
+            identity: None,
+            claims: None,
+            roles: vec![role.to_string()],
+            framing: Framing::Json,
+            version: PROTOCOL_VERSION_2,
+            traffic: Arc::new(Traffic::default()),
+            out_tx: out_tx.clone(),
+        })
+    }
+
+    #[test]
+    fn test_admin_needs_role_and_loopback() {
+        let policy =
+            Policy::parse(r#"{"roles": {"operator": {"admin": true}, "observer": {}}}"#).unwrap();
+        let ctx = test_context(Some(policy), None);
+        let (out_tx, _out_rx) = mpsc::channel(1);
+        let request = Packet::admin_request(&AdminRequest::List);
+
+        for (role, peer, allowed) in [
+            ("operator", "127.0.0.1:4000", true),
+            ("observer", "127.0.0.1:4000", false),
+            ("operator", "10.0.0.7:4000", false),
+        ] {
+            let session = register(&ctx, role, Some(peer), &out_tx);
+            let reply = handle_admin(&ctx, &session, &request);
+            let answered = reply.to_admin_response().is_ok();
+            assert_eq!(answered, allowed, "{} from {}", role, peer);
+        }
+
+        // Without a policy nobody holds an admin role
+        let ctx = test_context(None, None);
+        let session = register(&ctx, "operator", Some("127.0.0.1:4000"), &out_tx);
+        assert!(handle_admin(&ctx, &session, &request)
+            .to_error_notice()
+            .is_ok());
+    }
+
+    #[test]
+    fn test_relayed_packets_are_authorized() {
+        let policy = Policy::parse(
+            r#"{"roles": {"peer": {}, "observer": {"urgencies": ["GREEN"]}},
+                "nodes": {"node-b": ["peer"]}, "default_roles": ["observer"]}"#,
+        )
+        .unwrap();
+        let ctx = test_context(Some(policy), None);
+        assert!(relay_allowed(&ctx, "node-b", &Packet::red("LOCK")));
+        assert!(!relay_allowed(&ctx, "rogue", &Packet::red("LOCK")));
+        assert!(relay_allowed(&ctx, "rogue", &Packet::green("status")));
+        assert!(relay_allowed(
+            &test_context(None, None),
+            "rogue",
+            &Packet::red("LOCK")
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_classified_text_is_authorized() {
+        let mut rules = tempfile::NamedTempFile::new().unwrap();
+        write!(
+            rules,
+            r#"{{"rules": [{{"name": "lost", "when": {{"keyword": {{"any": ["TARGET LOST"]}}}}, "urgency": "RED"}}]}}"#
+        )
+        .unwrap();
+        let policy = Policy::parse(
+            r#"{"roles": {"observer": {"urgencies": ["GREEN"]}}, "default_roles": ["observer"]}"#,
+        )
+        .unwrap();
+        let ctx = test_context(
+            Some(policy),
+            Some(ReloadingClassifier::load(rules.path()).unwrap()),
+        );
+
+        let endpoint = Arc::clone(ctx.endpoints.get("/").unwrap());
+        let handler = SessionHandler::new(&ctx.handler, &endpoint, None, None);
+        let (out_tx, mut out_rx) = mpsc::channel(8);
+        let session = register(&ctx, "observer", None, &out_tx);
+        let subscriber = ctx.router.register(session.id, out_tx.clone());
+
+        for (text, forbidden) in [("TARGET LOST", true), ("all clear", false)] {
+            let packet = classify_text(&ctx, text.to_string());
+            handle_packet(&ctx, &handler, &session, &subscriber, &out_tx, packet)
+                .await
+                .unwrap();
+            let Some(Outbound::Packet(reply)) = out_rx.recv().await else {
+                panic!("expected a reply to {:?}", text);
+            };
+            let forbids = reply
+                .to_error_notice()
+                .is_ok_and(|notice| notice.code == ERROR_FORBIDDEN);
+            assert_eq!(forbids, forbidden, "{:?}", text);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_green_packets_audited_with_all_decisions() {
+        for all_decisions in [false, true] {
+            let dir = tempfile::tempdir().unwrap();
+            let mut ctx = test_context(None, None);
+            ctx.audit = AuditLog::open(&svckit::AuditConfig {
+                file: Some(dir.path().join("audit.log")),
+                all_decisions,
+                ..Default::default()
+            })
+            .unwrap();
+
+            let endpoint = Arc::clone(ctx.endpoints.get("/").unwrap());
+            let handler = SessionHandler::new(&ctx.handler, &endpoint, None, None);
+            let (out_tx, _out_rx) = mpsc::channel(8);
+            let session = register(&ctx, "observer", None, &out_tx);
+            let subscriber = ctx.router.register(session.id, out_tx.clone());
+            handle_packet(
+                &ctx,
+                &handler,
+                &session,
+                &subscriber,
+                &out_tx,
+                Packet::green("status"),
+            )
+            .await
+            .unwrap();
+
+            let audit = ctx.audit.as_ref().unwrap();
+            audit.close().await.unwrap();
+            let log = std::fs::read_to_string(audit.path()).unwrap();
+            let recorded = log
+                .lines()
+                .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+                .any(|record| record["event"] == "packet" && record["decision"] == "allow");
+            assert_eq!(recorded, all_decisions);
+        }
+    }
 
-    run_server(config).await
+    #[tokio::test]
+    async fn test_v1_topic_publish_reaches_other_node() {
+        let hub = MemoryHub::default();
+        let mut a = test_context(None, None);
+        a.backplane = Arc::new(hub.join("a"));
+        let mut b = test_context(None, None);
+        b.backplane = Arc::new(hub.join("b"));
+        let b = Arc::new(b);
+        tokio::spawn(run_cluster(Arc::clone(&b)));
+        // Let node b subscribe to the hub before anything is published
+        tokio::task::yield_now().await;
+
+        let (b_tx, mut b_rx) = mpsc::channel(8);
+        let b_session = register(&b, "operator", None, &b_tx);
+        let b_subscriber = b.router.register(b_session.id, b_tx.clone());
+        b_subscriber.subscribe(vec![TopicFilter::parse("+/uav-7/#").unwrap()]);
+
+        // A v1 JSON session on node a publishes the way ws-client does
+        let sent = Packet::green("BEARING 270")
+            .with_topic("uav-7/track")
+            .unwrap()
+            .with_version(PROTOCOL_VERSION);
+        let frame = encode_packet(Framing::Json, &sent);
+        let Ok(Some(Inbound::Packet(packet))) =
+            decode_frame(Framing::Json, PROTOCOL_VERSION, &frame)
+        else {
+            panic!("expected a packet");
+        };
+        assert!(version_accepted(Framing::Json, PROTOCOL_VERSION, &packet));
+
+        let endpoint = Arc::clone(a.endpoints.get("/").unwrap());
+        let handler = SessionHandler::new(&a.handler, &endpoint, None, None);
+        let (a_tx, _a_rx) = mpsc::channel(8);
+        let a_session = register(&a, "drone", None, &a_tx);
+        let a_subscriber = a.router.register(a_session.id, a_tx.clone());
+        handle_packet(&a, &handler, &a_session, &a_subscriber, &a_tx, packet)
+            .await
+            .unwrap();
+
+        let received = tokio::time::timeout(Duration::from_secs(5), b_rx.recv())
+            .await
+            .expect("no packet on node b");
+        let Some(Outbound::Packet(received)) = received else {
+            panic!("expected a packet on node b");
+        };
+        assert_eq!(received.topic(), Some("uav-7/track"));
+        assert_eq!(received.payload_str().unwrap(), "BEARING 270");
+    }
 }
@@ -0,0 +1,542 @@
+//! TCP mesh backplane between server processes.
+//!
+//! Every node dials every address in its peer list and sends its events down
+//! that connection; it receives the other nodes' events on the connections
+//! they dial in. Links carry events one way only, so a full mesh (each node
+//! listing all the others) delivers each event to each node exactly once
+//! without any forwarding. A dropped link is redialled with backoff, and the
+//! first thing sent on a new link is the node's current sessions.
+//!
+//! Frames are a 4-byte big-endian length followed by JSON. Each side opens
+//! with a [`Hello`]; after that only the dialling side speaks. The mesh is
+//! plaintext and meant for a private network. The shared secret, compared in
+//! constant time, keeps other hosts and nodes of other clusters from linking
+//! in, and is required whenever the listener is reachable beyond loopback.
+
+use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use subtle::ConstantTimeEq;
+use svckit::ClusterConfig;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, mpsc};
+use tracing::{info, warn};
+
+use crate::backplane::{Backplane, ClusterEvent, ClusterMessage, EVENT_BUFFER};
+
+/// Largest frame accepted from a peer.
+const MAX_FRAME: usize = 16 * 1024 * 1024;
+
+/// Largest hello accepted, read before the peer has proven it knows the secret.
+const MAX_HELLO: usize = 4 * 1024;
+
+/// Events held for a peer whose link is down or slow.
+const PEER_QUEUE: usize = 1024;
+
+/// How long a peer has to answer the opening hello.
+const HELLO_TIMEOUT: Duration = Duration::from_secs(5);
+
+const REDIAL_MIN: Duration = Duration::from_millis(500);
+const REDIAL_MAX: Duration = Duration::from_secs(10);
+
+/// Opening frame of a link.
+#[derive(Serialize, Deserialize)]
+struct Hello {
+    node: String,
+    #[serde(default)]
+    secret: Option<String>,
+}
+
+/// Produces the events that describe this node's current state to a newly linked peer.
+pub type Snapshot = Arc<dyn Fn() -> Vec<ClusterEvent> + Send + Sync>;
+
+/// Outbound link to one peer.
+struct PeerLink {
+    addr: String,
+    /// The peer's node name, once it has answered.
+    node: Mutex<Option<String>>,
+    up: AtomicBool,
+    /// Set while events for the peer are being dropped, so the drop is logged once.
+    dropping: AtomicBool,
+}
+
+/// The mesh's handle on a peer link. Only the mesh holds the sender, so
+/// dropping the mesh closes the queue and ends the link.
+struct Peer {
+    link: Arc<PeerLink>,
+    tx: mpsc::Sender<ClusterMessage>,
+}
+
+/// Backplane linking server processes over TCP.
+pub struct TcpMesh {
+    node_id: String,
+    local_addr: Option<SocketAddr>,
+    events: broadcast::Sender<ClusterMessage>,
+    peers: Vec<Peer>,
+}
+
+impl TcpMesh {
+    /// Listen for peers (if configured) and start dialling every configured peer.
+    pub async fn start(config: &ClusterConfig, snapshot: Snapshot) -> Result<Self> {
+        let events = broadcast::Sender::new(EVENT_BUFFER);
+        let mut local_addr = None;
+        if let Some(listen) = &config.listen {
+            let listener = TcpListener::bind(listen)
+                .await
+                .with_context(|| format!("Failed to bind cluster listener to {}", listen))?;
+            let addr = listener.local_addr()?;
+            local_addr = Some(addr);
+            if config.secret.is_none() && !addr.ip().is_loopback() {
+                anyhow::bail!(
+                    "CLUSTER_SECRET is required when CLUSTER_LISTEN ({}) is not a loopback address",
+                    listen
+                );
+            }
+            tokio::spawn(accept_peers(
+                listener,
+                config.node_id.clone(),
+                config.secret.clone(),
+                events.clone(),
+            ));
+        }
+
+        let peers = config
+            .peers
+            .iter()
+            .map(|addr| {
+                let (tx, rx) = mpsc::channel(PEER_QUEUE);
+                let link = Arc::new(PeerLink {
+                    addr: addr.clone(),
+                    node: Mutex::new(None),
+                    up: AtomicBool::new(false),
+                    dropping: AtomicBool::new(false),
+                });
+                let hello = Hello {
+                    node: config.node_id.clone(),
+                    secret: config.secret.clone(),
+                };
+                tokio::spawn(dial_peer(
+                    Arc::clone(&link),
+                    rx,
+                    hello,
+                    Arc::clone(&snapshot),
+                ));
+                Peer { link, tx }
+            })
+            .collect();
+
+        Ok(Self {
+            node_id: config.node_id.clone(),
+            local_addr,
+            events,
+            peers,
+        })
+    }
+
+    /// Address the peer listener is bound to, if this node accepts peers.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addr
+    }
+}
+
+impl Backplane for TcpMesh {
+    fn node_id(&self) -> &str {
+        &self.node_id
+    }
+
+    fn broadcast(&self, event: ClusterEvent) {
+        let message = ClusterMessage {
+            node: self.node_id.clone(),
+            event,
+        };
+        for peer in &self.peers {
+            match peer.tx.try_send(message.clone()) {
+                Ok(()) => peer.link.dropping.store(false, Ordering::Relaxed),
+                Err(mpsc::error::TrySendError::Full(_)) => {
+                    if !peer.link.dropping.swap(true, Ordering::Relaxed) {
+                        warn!(
+                            "[MESH] Queue for peer {} is full, dropping events",
+                            peer.link.addr
+                        );
+                    }
+                }
+                Err(mpsc::error::TrySendError::Closed(_)) => {}
+            }
+        }
+        let _ = self.events.send(message);
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<ClusterMessage> {
+        self.events.subscribe()
+    }
+
+    fn peers(&self) -> Vec<(String, bool)> {
+        self.peers
+            .iter()
+            .map(|Peer { link, .. }| {
+                let name = link.node.lock().unwrap_or_else(|e| e.into_inner()).clone();
+                (
+                    name.unwrap_or_else(|| link.addr.clone()),
+                    link.up.load(Ordering::Relaxed),
+                )
+            })
+            .collect()
+    }
+}
+
+/// Keep a link to one peer open, redialling with backoff when it drops.
+async fn dial_peer(
+    link: Arc<PeerLink>,
+    mut rx: mpsc::Receiver<ClusterMessage>,
+    hello: Hello,
+    snapshot: Snapshot,
+) {
+    let mut backoff = REDIAL_MIN;
+    let mut reported = false;
+    loop {
+        match connect_peer(&link.addr, &hello).await {
+            Ok((stream, peer_node)) => {
+                info!("[MESH] Linked to node {} at {}", peer_node, link.addr);
+                *link.node.lock().unwrap_or_else(|e| e.into_inner()) = Some(peer_node);
+                link.up.store(true, Ordering::Relaxed);
+                backoff = REDIAL_MIN;
+                reported = false;
+
+                let fed = feed_peer(stream, &mut rx, &hello.node, &snapshot).await;
+                link.up.store(false, Ordering::Relaxed);
+                match fed {
+                    Ok(()) => return,
+                    Err(e) => warn!("[MESH] Link to {} lost: {:#}", link.addr, e),
+                }
+            }
+            Err(e) => {
+                // Peers that are not up yet are expected while a cluster starts
+                if !reported {
+                    warn!("[MESH] Cannot reach peer {}: {:#} (retrying)", link.addr, e);
+                    reported = true;
+                }
+            }
+        }
+        if rx.is_closed() {
+            return;
+        }
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(REDIAL_MAX);
+    }
+}
+
+/// Dial a peer and exchange hellos. Returns the stream and the peer's node name.
+async fn connect_peer(addr: &str, hello: &Hello) -> Result<(TcpStream, String)> {
+    let mut stream = TcpStream::connect(addr).await?;
+    stream.set_nodelay(true)?;
+    write_frame(&mut stream, hello).await?;
+    let answer: Hello = tokio::time::timeout(HELLO_TIMEOUT, read_frame(&mut stream, MAX_HELLO))
+        .await
+        .context("no hello from peer")??
+        .context("peer closed the link (wrong cluster secret?)")?;
+    Ok((stream, answer.node))
+}
+
+/// Send this node's sessions, then its events as they come, until the link fails.
+///
+/// Returns `Ok` only when the mesh itself has gone away.
+async fn feed_peer(
+    stream: TcpStream,
+    rx: &mut mpsc::Receiver<ClusterMessage>,
+    node_id: &str,
+    snapshot: &Snapshot,
+) -> Result<()> {
+    let (mut reader, mut writer) = stream.into_split();
+    for event in snapshot() {
+        let message = ClusterMessage {
+            node: node_id.to_string(),
+            event,
+        };
+        write_frame(&mut writer, &message).await?;
+    }
+
+    // The peer never speaks after its hello, so a readable socket means it hung up
+    let mut probe = [0u8; 1];
+    loop {
+        tokio::select! {
+            message = rx.recv() => match message {
+                Some(message) => write_frame(&mut writer, &message).await?,
+                None => return Ok(()),
+            },
+            read = reader.read(&mut probe) => {
+                read?;
+                anyhow::bail!("closed by peer");
+            }
+        }
+    }
+}
+
+/// Accept links from peers and publish what they send.
+async fn accept_peers(
+    listener: TcpListener,
+    node_id: String,
+    secret: Option<String>,
+    events: broadcast::Sender<ClusterMessage>,
+) {
+    // Newest link per node, so an old link closing late does not drop a node that is back
+    let generations: Arc<Mutex<HashMap<String, u64>>> = Arc::default();
+    let next_generation = Arc::new(AtomicU64::new(0));
+    loop {
+        let (stream, addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("[MESH] Accept error: {}", e);
+                continue;
+            }
+        };
+        let node_id = node_id.clone();
+        let secret = secret.clone();
+        let events = events.clone();
+        let generations = Arc::clone(&generations);
+        let generation = next_generation.fetch_add(1, Ordering::Relaxed);
+        tokio::spawn(async move {
+            let mut stream = stream;
+            let peer = match greet_peer(&mut stream, &node_id, secret.as_deref()).await {
+                Ok(peer) => peer,
+                Err(e) => {
+                    warn!("[MESH] Rejected link from {}: {:#}", addr, e);
+                    return;
+                }
+            };
+            info!("[MESH] Node {} linked in from {}", peer, addr);
+            generations
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .insert(peer.clone(), generation);
+
+            if let Err(e) = receive_peer(&mut stream, &peer, &events).await {
+                warn!("[MESH] Link from node {} failed: {:#}", peer, e);
+            } else {
+                info!("[MESH] Node {} closed its link", peer);
+            }
+
+            let current = generations
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .get(&peer)
+                .copied();
+            if current == Some(generation) {
+                let _ = events.send(ClusterMessage {
+                    node: peer,
+                    event: ClusterEvent::NodeDown,
+                });
+            }
+        });
+    }
+}
+
+/// Check a dialling peer's hello and answer with ours. Returns the peer's node name.
+async fn greet_peer(stream: &mut TcpStream, node_id: &str, secret: Option<&str>) -> Result<String> {
+    let hello: Hello = tokio::time::timeout(HELLO_TIMEOUT, read_frame(stream, MAX_HELLO))
+        .await
+        .context("no hello")??
+        .context("closed before hello")?;
+    if let Some(secret) = secret {
+        let presented = hello.secret.as_deref().unwrap_or_default();
+        if !bool::from(presented.as_bytes().ct_eq(secret.as_bytes())) {
+            anyhow::bail!("node {} presented the wrong cluster secret", hello.node);
+        }
+    }
+    if hello.node == node_id {
+        anyhow::bail!("peer uses this node's own id {:?}", node_id);
+    }
+    write_frame(
+        stream,
+        &Hello {
+            node: node_id.to_string(),
+            secret: None,
+        },
+    )
+    .await?;
+    Ok(hello.node)
+}
+
+/// Publish every message a peer sends, attributed to that peer, until it hangs up.
+async fn receive_peer(
+    stream: &mut TcpStream,
+    peer: &str,
+    events: &broadcast::Sender<ClusterMessage>,
+) -> Result<()> {
+    while let Some(mut message) = read_frame::<_, ClusterMessage>(stream, MAX_FRAME).await? {
+        if matches!(message.event, ClusterEvent::NodeDown) {
+            continue;
+        }
+        message.node = peer.to_string();
+        let _ = events.send(message);
+    }
+    Ok(())
+}
+
+async fn write_frame<W: AsyncWrite + Unpin, T: Serialize>(writer: &mut W, value: &T) -> Result<()> {
+    let body = serde_json::to_vec(value)?;
+    let mut frame = Vec::with_capacity(4 + body.len());
+    frame.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&body);
+    writer.write_all(&frame).await?;
+    Ok(())
+}
+
+/// Read one frame of at most `limit` bytes, or `None` if the peer closed the
+/// link between frames.
+async fn read_frame<R: AsyncRead + Unpin, T: DeserializeOwned>(
+    reader: &mut R,
+    limit: usize,
+) -> Result<Option<T>> {
+    let len = match reader.read_u32().await {
+        Ok(len) => len as usize,
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    if len > limit {
+        anyhow::bail!("frame of {} bytes exceeds the {} byte limit", len, limit);
+    }
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body).await?;
+    Ok(Some(serde_json::from_slice(&body)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hello(node: &str, secret: Option<&str>) -> Hello {
+        Hello {
+            node: node.to_string(),
+            secret: secret.map(str::to_string),
+        }
+    }
+
+    /// Dial a listener of node `a` expecting `secret` with `hello`, returning
+    /// the node names each side learned.
+    async fn link(secret: Option<&str>, hello: Hello) -> (Result<String>, Result<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let secret = secret.map(str::to_string);
+        let greeted = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            greet_peer(&mut stream, "a", secret.as_deref()).await
+        });
+        let dialled = connect_peer(&addr, &hello).await.map(|(_, node)| node);
+        (greeted.await.unwrap(), dialled)
+    }
+
+    #[tokio::test]
+    async fn test_handshake_checks_secret() {
+        let (greeted, dialled) = link(Some("s3cret"), hello("b", Some("s3cret"))).await;
+        assert_eq!(greeted.unwrap(), "b");
+        assert_eq!(dialled.unwrap(), "a");
+
+        for presented in [Some("wrong"), Some("s3cret-longer"), Some(""), None] {
+            let (greeted, dialled) = link(Some("s3cret"), hello("b", presented)).await;
+            assert!(greeted.is_err(), "{:?}", presented);
+            assert!(dialled.is_err(), "{:?}", presented);
+        }
+
+        // Without a configured secret any peer links in, but never under our own id
+        assert!(link(None, hello("b", None)).await.0.is_ok());
+        assert!(link(None, hello("a", None)).await.0.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_secret_required_beyond_loopback() {
+        let snapshot: Snapshot = Arc::new(Vec::new);
+        let config = |listen: &str, secret: Option<&str>| ClusterConfig {
+            node_id: "a".to_string(),
+            listen: Some(listen.to_string()),
+            secret: secret.map(str::to_string),
+            ..ClusterConfig::default()
+        };
+
+        assert!(
+            TcpMesh::start(&config("127.0.0.1:0", None), Arc::clone(&snapshot))
+                .await
+                .is_ok()
+        );
+        assert!(
+            TcpMesh::start(&config("0.0.0.0:0", None), Arc::clone(&snapshot))
+                .await
+                .is_err()
+        );
+        assert!(
+            TcpMesh::start(&config("0.0.0.0:0", Some("s3cret")), snapshot)
+                .await
+                .is_ok()
+        );
+    }
+
+    /// Start a loopback node named `node_id` that dials `peers`.
+    async fn node(node_id: &str, peers: Vec<String>, snapshot: Vec<ClusterEvent>) -> TcpMesh {
+        let config = ClusterConfig {
+            node_id: node_id.to_string(),
+            listen: Some("127.0.0.1:0".to_string()),
+            peers,
+            secret: Some("s3cret".to_string()),
+        };
+        TcpMesh::start(&config, Arc::new(move || snapshot.clone()))
+            .await
+            .unwrap()
+    }
+
+    /// Next message on `events` from a node other than `own`.
+    async fn next_remote(
+        events: &mut broadcast::Receiver<ClusterMessage>,
+        own: &str,
+    ) -> ClusterMessage {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let message = events.recv().await.unwrap();
+                if message.node != own {
+                    return message;
+                }
+            }
+        })
+        .await
+        .expect("no event from the other node")
+    }
+
+    #[tokio::test]
+    async fn test_events_cross_the_mesh() {
+        let a = node("a", Vec::new(), Vec::new()).await;
+        let mut events = a.subscribe();
+        let addr = a.local_addr().unwrap().to_string();
+        let up = ClusterEvent::SessionUp {
+            session: 7,
+            who: Some("operator-1".to_string()),
+        };
+        let b = node("b", vec![addr], vec![up]).await;
+
+        // The snapshot goes first on a new link
+        let message = next_remote(&mut events, "a").await;
+        assert_eq!(message.node, "b");
+        assert!(matches!(
+            message.event,
+            ClusterEvent::SessionUp { session: 7, ref who } if who.as_deref() == Some("operator-1")
+        ));
+
+        b.broadcast(ClusterEvent::Publish {
+            packet: b"hello".to_vec(),
+        });
+        let message = next_remote(&mut events, "a").await;
+        assert_eq!(message.node, "b");
+        assert!(
+            matches!(message.event, ClusterEvent::Publish { ref packet } if packet == b"hello")
+        );
+        assert_eq!(b.peers(), vec![("a".to_string(), true)]);
+
+        drop(b);
+        let message = next_remote(&mut events, "a").await;
+        assert_eq!(message.node, "b");
+        assert!(matches!(message.event, ClusterEvent::NodeDown));
+    }
+}
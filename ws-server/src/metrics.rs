@@ -0,0 +1,389 @@
+//! Prometheus metrics: a small registry of labelled counters, gauges and
+//! histograms, served in the text exposition format on `GET /metrics`.
+//!
+//! Metrics live on their own plaintext listener so scrapers and health checks
+//! need neither a client certificate nor a bearer token. Session gauges are
+//! read from the registry at scrape time; everything else is updated as it
+//! happens.
+
+use anyhow::Result;
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper::body::Incoming;
+use hyper::header::{HeaderValue, ALLOW, CONTENT_TYPE};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use protocol::Packet;
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
+use tracing::{error, warn};
+
+use crate::{shutting_down, ServerContext};
+
+/// Path metrics are served on.
+pub const METRICS_PATH: &str = "/metrics";
+
+/// Upper bounds of the dispatch latency buckets, in seconds.
+const LATENCY_BUCKETS: [f64; 10] = [
+    0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.05, 0.25, 1.0,
+];
+
+/// One kind of metric, able to write its samples for one label set.
+pub trait Metric: Default {
+    /// Prometheus type name, for the `# TYPE` line.
+    const KIND: &'static str;
+
+    fn render(&self, name: &str, labels: &str, out: &mut String);
+}
+
+/// A value that only goes up.
+#[derive(Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+}
+
+impl Metric for Counter {
+    const KIND: &'static str = "counter";
+
+    fn render(&self, name: &str, labels: &str, out: &mut String) {
+        let _ = writeln!(
+            out,
+            "{}{} {}",
+            name,
+            braces(labels, None),
+            self.0.load(Ordering::Relaxed)
+        );
+    }
+}
+
+/// A value that goes up and down.
+#[derive(Default)]
+pub struct Gauge(AtomicI64);
+
+impl Gauge {
+    pub fn set(&self, value: i64) {
+        self.0.store(value, Ordering::Relaxed);
+    }
+}
+
+impl Metric for Gauge {
+    const KIND: &'static str = "gauge";
+
+    fn render(&self, name: &str, labels: &str, out: &mut String) {
+        let _ = writeln!(
+            out,
+            "{}{} {}",
+            name,
+            braces(labels, None),
+            self.0.load(Ordering::Relaxed)
+        );
+    }
+}
+
+/// Durations counted into [`LATENCY_BUCKETS`].
+#[derive(Default)]
+pub struct Histogram {
+    buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    count: AtomicU64,
+    sum_nanos: AtomicU64,
+}
+
+impl Histogram {
+    pub fn observe(&self, elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64();
+        if let Some(bucket) = LATENCY_BUCKETS.iter().position(|&le| seconds <= le) {
+            self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_nanos.fetch_add(
+            elapsed.as_nanos().min(u64::MAX as u128) as u64,
+            Ordering::Relaxed,
+        );
+    }
+}
+
+impl Metric for Histogram {
+    const KIND: &'static str = "histogram";
+
+    fn render(&self, name: &str, labels: &str, out: &mut String) {
+        // Buckets are stored individually and exposed cumulatively
+        let mut cumulative = 0;
+        for (le, bucket) in LATENCY_BUCKETS.iter().zip(&self.buckets) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let le = format!("le=\"{}\"", le);
+            let _ = writeln!(
+                out,
+                "{}_bucket{} {}",
+                name,
+                braces(labels, Some(&le)),
+                cumulative
+            );
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        let inf = braces(labels, Some("le=\"+Inf\""));
+        let _ = writeln!(out, "{}_bucket{} {}", name, inf, count);
+        let sum = self.sum_nanos.load(Ordering::Relaxed) as f64 / 1e9;
+        let _ = writeln!(out, "{}_sum{} {}", name, braces(labels, None), sum);
+        let _ = writeln!(out, "{}_count{} {}", name, braces(labels, None), count);
+    }
+}
+
+/// A metric with one series per combination of label values.
+pub struct Family<M> {
+    name: &'static str,
+    help: &'static str,
+    labels: &'static [&'static str],
+    series: Mutex<BTreeMap<Vec<String>, Arc<M>>>,
+}
+
+impl<M: Metric> Family<M> {
+    fn new(name: &'static str, help: &'static str, labels: &'static [&'static str]) -> Self {
+        Self {
+            name,
+            help,
+            labels,
+            series: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// The series for `values`, given in the order of the family's labels.
+    pub fn with(&self, values: &[&str]) -> Arc<M> {
+        debug_assert_eq!(values.len(), self.labels.len(), "{} label count", self.name);
+        let key: Vec<String> = values.iter().map(|value| value.to_string()).collect();
+        Arc::clone(self.lock().entry(key).or_default())
+    }
+
+    fn render(&self, out: &mut String) {
+        let _ = writeln!(out, "# HELP {} {}", self.name, self.help);
+        let _ = writeln!(out, "# TYPE {} {}", self.name, M::KIND);
+        for (values, metric) in self.lock().iter() {
+            let labels = self
+                .labels
+                .iter()
+                .zip(values)
+                .map(|(label, value)| format!("{}=\"{}\"", label, escape(value)))
+                .collect::<Vec<_>>()
+                .join(",");
+            metric.render(self.name, &labels, out);
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<Vec<String>, Arc<M>>> {
+        self.series.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Family<Gauge> {
+    /// Set every series to `0`, before new values are set.
+    fn reset(&self) {
+        for gauge in self.lock().values() {
+            gauge.set(0);
+        }
+    }
+}
+
+/// Every metric the server exposes.
+pub struct Metrics {
+    /// Accepted TCP connections, by transport (`ws` or `http`).
+    pub connections: Family<Counter>,
+    /// Live sessions, by endpoint; refreshed from the registry on each scrape.
+    sessions: Family<Gauge>,
+    /// Packets received from sessions, by endpoint, packet type and urgency.
+    packets: Family<Counter>,
+    /// Frames or bodies that did not decode into a packet, by endpoint.
+    decode_errors: Family<Counter>,
+    /// Time to handle one received packet, by packet type and urgency.
+    dispatch: Family<Histogram>,
+    /// Broadcast messages a slow receiver missed, by stream.
+    pub broadcast_lagged: Family<Counter>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            connections: Family::new(
+                "ws_connections_total",
+                "Accepted TCP connections.",
+                &["transport"],
+            ),
+            sessions: Family::new("ws_sessions", "Live sessions.", &["endpoint"]),
+            packets: Family::new(
+                "ws_packets_received_total",
+                "Packets received from sessions.",
+                &["endpoint", "packet_type", "urgency"],
+            ),
+            decode_errors: Family::new(
+                "ws_decode_errors_total",
+                "Messages that could not be decoded into a packet.",
+                &["endpoint"],
+            ),
+            dispatch: Family::new(
+                "ws_dispatch_duration_seconds",
+                "Time spent handling one received packet.",
+                &["packet_type", "urgency"],
+            ),
+            broadcast_lagged: Family::new(
+                "ws_broadcast_lagged_total",
+                "Broadcast messages dropped because a receiver fell behind.",
+                &["stream"],
+            ),
+        }
+    }
+}
+
+impl Metrics {
+    /// Count a packet received on `endpoint` and time its handling until the
+    /// returned guard is dropped.
+    pub fn packet_received(&self, endpoint: &str, packet: &Packet) -> DispatchTimer {
+        let packet_type = packet.header.type_name();
+        let urgency = packet.header.urgency.as_str();
+        self.packets.with(&[endpoint, packet_type, urgency]).inc();
+        DispatchTimer {
+            histogram: self.dispatch.with(&[packet_type, urgency]),
+            started: Instant::now(),
+        }
+    }
+
+    /// Count a message on `endpoint` that did not decode.
+    pub fn decode_error(&self, endpoint: &str) {
+        self.decode_errors.with(&[endpoint]).inc();
+    }
+
+    /// Render every metric in the Prometheus text format.
+    fn render(&self, ctx: &ServerContext) -> String {
+        // Configured endpoints show up even while nobody is connected
+        let mut sessions: BTreeMap<String, i64> = ctx
+            .endpoints
+            .iter()
+            .map(|endpoint| (endpoint.path.clone(), 0))
+            .collect();
+        for session in ctx.registry.list() {
+            *sessions.entry(session.endpoint.clone()).or_default() += 1;
+        }
+        self.sessions.reset();
+        for (endpoint, count) in &sessions {
+            self.sessions.with(&[endpoint]).set(*count);
+        }
+
+        let mut out = String::new();
+        self.connections.render(&mut out);
+        self.sessions.render(&mut out);
+        self.packets.render(&mut out);
+        self.decode_errors.render(&mut out);
+        self.dispatch.render(&mut out);
+        self.broadcast_lagged.render(&mut out);
+        out
+    }
+}
+
+/// Records how long a packet took to handle when dropped.
+pub struct DispatchTimer {
+    histogram: Arc<Histogram>,
+    started: Instant,
+}
+
+impl Drop for DispatchTimer {
+    fn drop(&mut self) {
+        self.histogram.observe(self.started.elapsed());
+    }
+}
+
+/// Serve `GET /metrics` on `listener` until the server starts shutting down.
+pub async fn serve(listener: TcpListener, ctx: Arc<ServerContext>) {
+    let mut shutdown = ctx.shutdown.subscribe();
+    loop {
+        let stream = tokio::select! {
+            _ = shutting_down(&mut shutdown) => return,
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    error!("[METRICS] Accept error: {}", e);
+                    continue;
+                }
+            },
+        };
+        let ctx = Arc::clone(&ctx);
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, ctx).await {
+                warn!("[METRICS] Connection error: {:#}", e);
+            }
+        });
+    }
+}
+
+async fn handle_connection(stream: tokio::net::TcpStream, ctx: Arc<ServerContext>) -> Result<()> {
+    let service = service_fn(move |request| {
+        let ctx = Arc::clone(&ctx);
+        async move { Ok::<_, Infallible>(route(&ctx, &request)) }
+    });
+    http1::Builder::new()
+        .serve_connection(TokioIo::new(stream), service)
+        .await?;
+    Ok(())
+}
+
+fn route(ctx: &ServerContext, request: &Request<Incoming>) -> Response<Full<Bytes>> {
+    if request.uri().path() != METRICS_PATH {
+        return text_response(StatusCode::NOT_FOUND, "no such endpoint\n".into());
+    }
+    if request.method() != Method::GET {
+        let mut response = text_response(
+            StatusCode::METHOD_NOT_ALLOWED,
+            "method not allowed\n".into(),
+        );
+        response
+            .headers_mut()
+            .insert(ALLOW, HeaderValue::from_static("GET"));
+        return response;
+    }
+
+    let mut response = text_response(StatusCode::OK, ctx.metrics.render(ctx));
+    response.headers_mut().insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("text/plain; version=0.0.4; charset=utf-8"),
+    );
+    response
+}
+
+fn text_response(status: StatusCode, body: String) -> Response<Full<Bytes>> {
+    let mut response = Response::new(Full::new(Bytes::from(body)));
+    *response.status_mut() = status;
+    response.headers_mut().insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("text/plain; charset=utf-8"),
+    );
+    response
+}
+
+/// `{labels}`, with `extra` appended; empty when there are no labels at all.
+fn braces(labels: &str, extra: Option<&str>) -> String {
+    match (labels.is_empty(), extra) {
+        (true, None) => String::new(),
+        (true, Some(extra)) => format!("{{{}}}", extra),
+        (false, None) => format!("{{{}}}", labels),
+        (false, Some(extra)) => format!("{{{},{}}}", labels, extra),
+    }
+}
+
+/// Escape a label value for the text format.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
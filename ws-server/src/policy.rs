@@ -0,0 +1,394 @@
+//! Role-based authorization of inbound packets.
+//!
+//! A session's roles come from its bearer token and from the policy's
+//! `identities` table, keyed by client certificate common name. Packets relayed
+//! by other cluster nodes get the roles of the `nodes` table, keyed by node id. Each role
+//! grants a set of urgencies, packet types and topic filters; a list that is
+//! left out grants everything. A packet is allowed when any one of the
+//! session's roles allows all three of its urgency, type and topic. A role
+//! with `topics` may only send packets published to one of them, and may only
+//! subscribe to filters that stay within them. A role with `"alerts": true`
+//! also receives rebroadcast alerts, and one with `"admin": true` may send
+//! ADMIN requests on admin endpoints.
+//!
+//! ```json
+//! {
+//!   "roles": {
+//!     "operator": { "urgencies": ["RED", "YELLOW", "GREEN"], "alerts": true, "admin": true },
+//!     "drone": { "urgencies": ["YELLOW", "GREEN"], "packet_types": ["message"], "topics": ["uav-7/#"] }
+//!   },
+//!   "identities": { "uav-7": ["drone"] },
+//!   "nodes": { "node-b": ["operator"] },
+//!   "default_roles": ["drone"]
+//! }
+//! ```
+
+use protocol::{
+    packet_type_from_name, Packet, TopicFilter, Urgency, PACKET_TYPE_SUBSCRIBE,
+    PACKET_TYPE_UNSUBSCRIBE,
+};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::auth::TokenClaims;
+use crate::identity::ClientIdentity;
+
+#[derive(Deserialize)]
+struct PolicyFile {
+    roles: HashMap<String, RoleFile>,
+    #[serde(default)]
+    identities: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    nodes: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    default_roles: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct RoleFile {
+    #[serde(default)]
+    urgencies: Option<Vec<String>>,
+    #[serde(default)]
+    packet_types: Option<Vec<String>>,
+    #[serde(default)]
+    topics: Option<Vec<String>>,
+    #[serde(default)]
+    alerts: bool,
+    #[serde(default)]
+    admin: bool,
+}
+
+/// What one role may send. `None` means unrestricted.
+struct Grant {
+    urgencies: Option<Vec<Urgency>>,
+    packet_types: Option<Vec<u8>>,
+    topics: Option<Vec<TopicFilter>>,
+    /// Whether the role receives rebroadcast alerts.
+    alerts: bool,
+    /// Whether the role may administer the server.
+    admin: bool,
+}
+
+impl Grant {
+    fn allows(&self, packet: &Packet) -> bool {
+        let urgency = packet.header.urgency;
+        let urgency_ok = self.urgencies.as_ref().is_none_or(|u| u.contains(&urgency));
+        let type_ok = self
+            .packet_types
+            .as_ref()
+            .is_none_or(|types| types.contains(&packet.header.packet_type));
+        let topic_ok = match (&self.topics, packet.topic()) {
+            (None, _) => true,
+            (Some(filters), Some(topic)) => filters.iter().any(|filter| filter.matches(topic)),
+            // Subscriptions are judged by the filters they add; other packets
+            // without a topic fall outside a topic-restricted grant
+            (Some(_), None) => match packet.header.packet_type {
+                PACKET_TYPE_SUBSCRIBE => packet
+                    .to_topic_filters()
+                    .is_ok_and(|filters| self.covers(&filters)),
+                PACKET_TYPE_UNSUBSCRIBE => true,
+                _ => false,
+            },
+        };
+        urgency_ok && type_ok && topic_ok
+    }
+
+    /// Whether subscribing to `filters` only reaches topics this role may use.
+    ///
+    /// Subscriptions match routing keys, so the urgency level in front of the
+    /// topic may be anything.
+    fn covers(&self, filters: &[TopicFilter]) -> bool {
+        let Some(topics) = &self.topics else {
+            return true;
+        };
+        filters.iter().all(|filter| {
+            topics.iter().any(|topic| {
+                TopicFilter::parse(&format!("+/{}", topic))
+                    .is_ok_and(|granted| granted.covers(filter))
+            })
+        })
+    }
+}
+
+/// Role grants loaded from the policy file.
+pub struct Policy {
+    roles: HashMap<String, Grant>,
+    identities: HashMap<String, Vec<String>>,
+    nodes: HashMap<String, Vec<String>>,
+    default_roles: Vec<String>,
+}
+
+impl Policy {
+    /// Load and validate a policy file.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read policy {:?}: {}", path, e))?;
+        Self::parse(&text).map_err(|e| anyhow::anyhow!("Invalid policy {:?}: {}", path, e))
+    }
+
+    /// Parse and validate a policy from its JSON text.
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let file: PolicyFile = serde_json::from_str(text)?;
+
+        let mut roles = HashMap::with_capacity(file.roles.len());
+        for (name, role) in file.roles {
+            roles.insert(name.clone(), parse_grant(&name, role)?);
+        }
+        for role in file
+            .identities
+            .values()
+            .chain(file.nodes.values())
+            .flatten()
+            .chain(&file.default_roles)
+        {
+            if !roles.contains_key(role) {
+                anyhow::bail!("refers to undefined role {:?}", role);
+            }
+        }
+
+        Ok(Self {
+            roles,
+            identities: file.identities,
+            nodes: file.nodes,
+            default_roles: file.default_roles,
+        })
+    }
+
+    /// Number of roles defined.
+    pub fn role_count(&self) -> usize {
+        self.roles.len()
+    }
+
+    /// Roles of a session: those in its token plus those its certificate maps to,
+    /// or the default roles when it has neither.
+    pub fn roles_for(
+        &self,
+        identity: Option<&ClientIdentity>,
+        claims: Option<&TokenClaims>,
+    ) -> Vec<String> {
+        let mut roles: Vec<String> = claims.map(|c| c.roles.clone()).unwrap_or_default();
+        if let Some(mapped) = identity.and_then(|id| self.identities.get(id.name())) {
+            roles.extend(mapped.iter().cloned());
+        }
+        if roles.is_empty() {
+            roles = self.default_roles.clone();
+        }
+        roles.sort();
+        roles.dedup();
+        roles
+    }
+
+    /// Roles of a cluster peer relaying packets: those the `nodes` table gives
+    /// its node id, or the default roles.
+    ///
+    /// Node ids are self-declared, so they are never looked up in `identities`.
+    pub fn roles_for_node(&self, node: &str) -> Vec<String> {
+        self.nodes.get(node).unwrap_or(&self.default_roles).clone()
+    }
+
+    /// Whether a session holding `roles` receives rebroadcast alerts.
+    pub fn receives_alerts(&self, roles: &[String]) -> bool {
+        roles
+            .iter()
+            .filter_map(|role| self.roles.get(role))
+            .any(|grant| grant.alerts)
+    }
+
+    /// Whether a session holding `roles` may administer the server.
+    pub fn administers(&self, roles: &[String]) -> bool {
+        roles
+            .iter()
+            .filter_map(|role| self.roles.get(role))
+            .any(|grant| grant.admin)
+    }
+
+    /// Decide whether a session holding `roles` may subscribe to `filters`,
+    /// explaining a refusal.
+    pub fn authorize_subscription(
+        &self,
+        roles: &[String],
+        filters: &[TopicFilter],
+    ) -> Result<(), String> {
+        let allowed = roles
+            .iter()
+            .filter_map(|role| self.roles.get(role))
+            .any(|grant| grant.covers(filters));
+        if allowed {
+            return Ok(());
+        }
+        Err(format!(
+            "subscription to {:?} not permitted for roles {:?}",
+            filters.iter().map(TopicFilter::as_str).collect::<Vec<_>>(),
+            roles
+        ))
+    }
+
+    /// Decide whether a session holding `roles` may send `packet`, explaining a refusal.
+    pub fn authorize(&self, roles: &[String], packet: &Packet) -> Result<(), String> {
+        let allowed = roles
+            .iter()
+            .filter_map(|role| self.roles.get(role))
+            .any(|grant| grant.allows(packet));
+        if allowed {
+            return Ok(());
+        }
+
+        let mut reason = format!(
+            "{} {} packet",
+            packet.header.urgency.as_str(),
+            packet.header.type_name()
+        );
+        if let Some(topic) = packet.topic() {
+            reason.push_str(&format!(" on topic {}", topic));
+        }
+        reason.push_str(&format!(" not permitted for roles {:?}", roles));
+        Err(reason)
+    }
+}
+
+fn parse_grant(name: &str, role: RoleFile) -> anyhow::Result<Grant> {
+    let urgencies = role
+        .urgencies
+        .map(|list| {
+            list.iter()
+                .map(|u| u.parse::<Urgency>())
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .transpose()
+        .map_err(|e| anyhow::anyhow!("Role {}: {}", name, e))?;
+
+    let packet_types = role
+        .packet_types
+        .map(|list| {
+            list.iter()
+                .map(|type_name| {
+                    packet_type_from_name(type_name).ok_or_else(|| {
+                        anyhow::anyhow!("Role {}: unknown packet type {:?}", name, type_name)
+                    })
+                })
+                .collect::<anyhow::Result<Vec<_>>>()
+        })
+        .transpose()?;
+
+    let topics = role
+        .topics
+        .map(|list| {
+            list.iter()
+                .map(|filter| TopicFilter::parse(filter))
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .transpose()
+        .map_err(|e| anyhow::anyhow!("Role {}: {}", name, e))?;
+
+    Ok(Grant {
+        urgencies,
+        packet_types,
+        topics,
+        alerts: role.alerts,
+        admin: role.admin,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protocol::PROTOCOL_VERSION_2;
+
+    const POLICY: &str = r#"{
+        "roles": {
+            "operator": { "alerts": true, "admin": true },
+            "observer": { "urgencies": ["GREEN"], "packet_types": ["message", "subscribe"] },
+            "drone": { "urgencies": ["YELLOW", "GREEN"], "topics": ["uav-7/#"] }
+        },
+        "identities": { "uav-7": ["drone"] },
+        "nodes": { "node-b": ["operator"] },
+        "default_roles": ["observer"]
+    }"#;
+
+    fn roles(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    fn published(packet: Packet, topic: &str) -> Packet {
+        packet
+            .with_version(PROTOCOL_VERSION_2)
+            .with_topic(topic)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_urgency_and_type_grants() {
+        let policy = Policy::parse(POLICY).unwrap();
+        let observer = roles(&["observer"]);
+        assert!(policy
+            .authorize(&observer, &Packet::green("status"))
+            .is_ok());
+        assert!(policy.authorize(&observer, &Packet::red("LOCK")).is_err());
+        let track = Packet::typed(protocol::PACKET_TYPE_TRACK, Urgency::Green, vec![]);
+        assert!(policy.authorize(&observer, &track).is_err());
+
+        // Any one role allowing the packet is enough; unknown roles grant nothing
+        assert!(policy
+            .authorize(&roles(&["observer", "operator"]), &Packet::red("LOCK"))
+            .is_ok());
+        assert!(policy
+            .authorize(&roles(&["ghost"]), &Packet::green("x"))
+            .is_err());
+        assert!(policy.receives_alerts(&roles(&["operator"])));
+        assert!(!policy.receives_alerts(&observer));
+        assert!(policy.administers(&roles(&["observer", "operator"])));
+        assert!(!policy.administers(&observer));
+    }
+
+    #[test]
+    fn test_topic_grants() {
+        let policy = Policy::parse(POLICY).unwrap();
+        let drone = roles(&["drone"]);
+        assert!(policy
+            .authorize(&drone, &published(Packet::yellow("x"), "uav-7/t1"))
+            .is_ok());
+        assert!(policy
+            .authorize(&drone, &published(Packet::yellow("x"), "uav-8/t1"))
+            .is_err());
+        // Packets without a topic fall outside a topic-restricted grant
+        assert!(policy.authorize(&drone, &Packet::yellow("x")).is_err());
+
+        assert!(policy
+            .authorize(&drone, &Packet::subscribe(&["+/uav-7/#"]))
+            .is_ok());
+        assert!(policy
+            .authorize(&drone, &Packet::subscribe(&["red/uav-7/t1"]))
+            .is_ok());
+        assert!(policy
+            .authorize(&drone, &Packet::subscribe(&["red/#"]))
+            .is_err());
+        assert!(policy
+            .authorize(&drone, &Packet::subscribe(&["+/+/t1"]))
+            .is_err());
+        assert!(policy
+            .authorize(&drone, &Packet::unsubscribe(&["red/#"]))
+            .is_ok());
+
+        let filters = [TopicFilter::parse("#").unwrap()];
+        assert!(policy.authorize_subscription(&drone, &filters).is_err());
+        assert!(policy
+            .authorize_subscription(&roles(&["observer"]), &filters)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_roles_for_and_validation() {
+        let policy = Policy::parse(POLICY).unwrap();
+        assert_eq!(policy.roles_for(None, None), roles(&["observer"]));
+        assert_eq!(policy.roles_for_node("node-b"), roles(&["operator"]));
+        // A node naming itself after a certificate identity gains nothing
+        assert_eq!(policy.roles_for_node("uav-7"), roles(&["observer"]));
+
+        assert!(Policy::parse(r#"{"roles": {}, "default_roles": ["ghost"]}"#).is_err());
+        assert!(Policy::parse(r#"{"roles": {}, "nodes": {"b": ["ghost"]}}"#).is_err());
+        assert!(Policy::parse(r#"{"roles": {"r": {"packet_types": ["bogus"]}}}"#).is_err());
+        assert!(Policy::parse(r#"{"roles": {"r": {"urgencies": ["PINK"]}}}"#).is_err());
+        assert!(Policy::parse(r#"{"roles": {"r": {"topics": ["a/#/b"]}}}"#).is_err());
+    }
+}
@@ -0,0 +1,206 @@
+//! Registry of live sessions, backing the admin interface.
+//!
+//! Every session registers after its handshake and receives a [`SessionGuard`].
+//! The guard removes the entry when dropped, so the registry only ever lists
+//! sessions that are still running.
+
+use protocol::{Framing, Packet, SessionInfo};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+use svckit::wire::{close_message, Outbound, Traffic};
+use tokio::sync::{mpsc, Notify};
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+
+use crate::auth::TokenClaims;
+use crate::identity::ClientIdentity;
+
+/// Registry-assigned session identifier.
+pub type SessionId = u64;
+
+/// A live session as seen by the registry.
+pub struct SessionHandle {
+    pub id: SessionId,
+    pub peer: Option<SocketAddr>,
+    /// Path the session connected to.
+    pub endpoint: String,
+    /// Identity from the client certificate, if any.
+    pub identity: Option<ClientIdentity>,
+    /// Claims of the bearer token presented on upgrade, if any.
+    pub claims: Option<TokenClaims>,
+    /// Roles the authorization policy checks packets against.
+    pub roles: Vec<String>,
+    pub framing: Framing,
+    pub version: u8,
+    pub connected_at: SystemTime,
+    pub traffic: Arc<Traffic>,
+    out_tx: mpsc::Sender<Outbound>,
+    kicked: Notify,
+}
+
+impl SessionHandle {
+    /// Who the session is: its token subject, else its certificate name.
+    pub fn identity_key(&self) -> Option<&str> {
+        match (&self.claims, &self.identity) {
+            (Some(claims), _) => Some(&claims.sub),
+            (None, Some(identity)) => Some(identity.name()),
+            (None, None) => None,
+        }
+    }
+
+    /// Queue a packet for this session. Fails if its queue is full or closed.
+    pub fn send(&self, packet: Packet) -> Result<(), String> {
+        self.out_tx
+            .try_send(Outbound::Packet(packet))
+            .map_err(|e| format!("session {} not accepting packets: {}", self.id, e))
+    }
+
+    /// Close the session with a policy close frame carrying `reason`.
+    pub fn kick(&self, reason: &str) {
+        let _ = self
+            .out_tx
+            .try_send(Outbound::Message(close_message(CloseCode::Policy, reason)));
+        self.kicked.notify_one();
+    }
+
+    /// Resolves once the session has been kicked.
+    pub async fn kicked(&self) {
+        self.kicked.notified().await
+    }
+
+    /// Snapshot for the admin interface.
+    pub fn info(&self, subscriptions: Vec<String>) -> SessionInfo {
+        SessionInfo {
+            id: self.id,
+            peer: self
+                .peer
+                .map_or_else(|| "unknown".to_string(), |addr| addr.to_string()),
+            endpoint: self.endpoint.clone(),
+            identity: self
+                .identity
+                .as_ref()
+                .map(|identity| identity.subject.clone()),
+            token_subject: self.claims.as_ref().map(|claims| claims.sub.clone()),
+            roles: self.roles.clone(),
+            token_expires_at: self.claims.as_ref().map(|claims| claims.exp),
+            subprotocol: self.framing.subprotocol().to_string(),
+            version: self.version,
+            connected_at: self
+                .connected_at
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            bytes_in: self.traffic.bytes_in(),
+            bytes_out: self.traffic.bytes_out(),
+            packets_in: self.traffic.packets_in(),
+            packets_out: self.traffic.packets_out(),
+            subscriptions,
+        }
+    }
+}
+
+/// Details a session supplies when registering.
+pub struct NewSession {
+    pub peer: Option<SocketAddr>,
+    pub endpoint: String,
+    pub identity: Option<ClientIdentity>,
+    pub claims: Option<TokenClaims>,
+    pub roles: Vec<String>,
+    pub framing: Framing,
+    pub version: u8,
+    pub traffic: Arc<Traffic>,
+    pub out_tx: mpsc::Sender<Outbound>,
+}
+
+/// All live sessions, keyed by id.
+#[derive(Default)]
+pub struct SessionRegistry {
+    sessions: RwLock<HashMap<SessionId, Arc<SessionHandle>>>,
+    next_id: AtomicU64,
+}
+
+impl SessionRegistry {
+    /// Register a session. It stays listed until the returned guard is dropped.
+    pub fn register(self: &Arc<Self>, session: NewSession) -> SessionGuard {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let handle = Arc::new(SessionHandle {
+            id,
+            peer: session.peer,
+            endpoint: session.endpoint,
+            identity: session.identity,
+            claims: session.claims,
+            roles: session.roles,
+            framing: session.framing,
+            version: session.version,
+            connected_at: SystemTime::now(),
+            traffic: session.traffic,
+            out_tx: session.out_tx,
+            kicked: Notify::new(),
+        });
+        self.sessions
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(id, Arc::clone(&handle));
+
+        SessionGuard {
+            handle,
+            registry: Arc::clone(self),
+        }
+    }
+
+    /// Look up a live session.
+    pub fn get(&self, id: SessionId) -> Option<Arc<SessionHandle>> {
+        self.sessions
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&id)
+            .cloned()
+    }
+
+    /// Every live session, ordered by id.
+    pub fn list(&self) -> Vec<Arc<SessionHandle>> {
+        let mut sessions: Vec<_> = self
+            .sessions
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .values()
+            .cloned()
+            .collect();
+        sessions.sort_by_key(|session| session.id);
+        sessions
+    }
+
+    /// Number of live sessions.
+    pub fn session_count(&self) -> usize {
+        self.sessions
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .len()
+    }
+}
+
+/// A session's registration. Dropping it removes the session from the registry.
+pub struct SessionGuard {
+    handle: Arc<SessionHandle>,
+    registry: Arc<SessionRegistry>,
+}
+
+impl Deref for SessionGuard {
+    type Target = SessionHandle;
+
+    fn deref(&self) -> &SessionHandle {
+        &self.handle
+    }
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        self.registry
+            .sessions
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.handle.id);
+    }
+}
@@ -0,0 +1,130 @@
+//! Topic router: fans published packets out to sessions with matching subscriptions.
+//!
+//! Each session registers under its registry id and receives a [`Subscriber`]
+//! handle. The handle owns the session's entry in the router, so its
+//! subscriptions disappear when the session ends, however it ends.
+
+use protocol::{Packet, TopicFilter};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use svckit::wire::Outbound;
+use tokio::sync::mpsc;
+use tracing::warn;
+
+use crate::registry::SessionId;
+
+struct Route {
+    filters: Vec<TopicFilter>,
+    tx: mpsc::Sender<Outbound>,
+}
+
+/// Subscription table shared by all sessions.
+#[derive(Default)]
+pub struct TopicRouter {
+    routes: RwLock<HashMap<SessionId, Route>>,
+}
+
+impl TopicRouter {
+    /// Register a session whose matching packets are queued on `tx`.
+    pub fn register(self: &Arc<Self>, id: SessionId, tx: mpsc::Sender<Outbound>) -> Subscriber {
+        self.write().insert(
+            id,
+            Route {
+                filters: Vec::new(),
+                tx,
+            },
+        );
+        Subscriber {
+            id,
+            router: Arc::clone(self),
+        }
+    }
+
+    /// Deliver a packet to every session with a filter matching its routing key.
+    ///
+    /// Returns the number of sessions it was queued for. Packets without a
+    /// topic are never routed. A session whose queue is full misses the packet
+    /// rather than stalling the publisher.
+    pub fn publish(&self, packet: &Packet) -> usize {
+        let Some(key) = packet.routing_key() else {
+            return 0;
+        };
+
+        let routes = self.read();
+        let mut delivered = 0;
+        for (id, route) in routes.iter() {
+            if !route.filters.iter().any(|filter| filter.matches(&key)) {
+                continue;
+            }
+            match route.tx.try_send(Outbound::Packet(packet.clone())) {
+                Ok(()) => delivered += 1,
+                Err(mpsc::error::TrySendError::Full(_)) => {
+                    warn!(
+                        "[ROUTER] Session {} queue full, dropping packet for {}",
+                        id, key
+                    );
+                }
+                Err(mpsc::error::TrySendError::Closed(_)) => {}
+            }
+        }
+        delivered
+    }
+
+    /// A session's current filters.
+    pub fn filters(&self, id: SessionId) -> Vec<TopicFilter> {
+        self.read()
+            .get(&id)
+            .map(|route| route.filters.clone())
+            .unwrap_or_default()
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, HashMap<SessionId, Route>> {
+        self.routes.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, HashMap<SessionId, Route>> {
+        self.routes.write().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A session's registration with the router. Dropping it removes every subscription.
+pub struct Subscriber {
+    id: SessionId,
+    router: Arc<TopicRouter>,
+}
+
+impl Subscriber {
+    /// The session's registry id.
+    pub fn id(&self) -> SessionId {
+        self.id
+    }
+
+    /// Add filters, ignoring ones the session already holds.
+    pub fn subscribe(&self, filters: Vec<TopicFilter>) {
+        if let Some(route) = self.router.write().get_mut(&self.id) {
+            for filter in filters {
+                if !route.filters.contains(&filter) {
+                    route.filters.push(filter);
+                }
+            }
+        }
+    }
+
+    /// Remove filters. Filters are matched as written, not by what they cover.
+    pub fn unsubscribe(&self, filters: &[TopicFilter]) {
+        if let Some(route) = self.router.write().get_mut(&self.id) {
+            route.filters.retain(|filter| !filters.contains(filter));
+        }
+    }
+
+    /// The session's current filters.
+    pub fn filters(&self) -> Vec<TopicFilter> {
+        self.router.filters(self.id)
+    }
+}
+
+impl Drop for Subscriber {
+    fn drop(&mut self) {
+        self.router.write().remove(&self.id);
+    }
+}
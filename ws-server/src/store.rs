@@ -0,0 +1,500 @@
+//! Store-and-forward queues for identified clients that are offline.
+//!
+//! When the last session of an identity (token subject or certificate name)
+//! ends, the server keeps a [`Mailbox`] for it along with the session's topic
+//! subscriptions. Packets published to those topics, and alerts if the
+//! identity receives them, are queued until a session with the same identity
+//! connects again; that session gets the subscriptions back and the queue is
+//! replayed to it. Anonymous sessions are never queued for.
+//!
+//! With a directory configured, a changed mailbox is marked dirty and written
+//! to `<dir>/<identity>.json` by a background thread, so queues survive a
+//! restart. The thread copies a mailbox under the lock and does all encoding
+//! and IO outside it; changes within one [`FLUSH_INTERVAL`] cost one write.
+
+use anyhow::{Context, Result};
+use protocol::{Mailbox, MailboxLimits, Packet, TopicFilter, PROTOCOL_VERSION_2};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use svckit::StoreForwardConfig;
+use tracing::{error, warn};
+
+/// Shortest time between two writes of the queue directory.
+const FLUSH_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Mailbox of one offline identity.
+struct Offline {
+    mailbox: Mailbox,
+    filters: Vec<TopicFilter>,
+    /// Whether rebroadcast alerts are queued too.
+    alerts: bool,
+}
+
+/// On-disk form of an [`Offline`] mailbox.
+#[derive(Serialize, Deserialize)]
+struct OfflineFile {
+    identity: String,
+    filters: Vec<String>,
+    alerts: bool,
+    packets: Vec<StoredPacket>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredPacket {
+    /// When the packet was queued, in milliseconds since the Unix epoch.
+    queued_at: u64,
+    /// The packet in v2 wire format.
+    bytes: Vec<u8>,
+}
+
+/// Mailboxes by identity, shared with the [`Flusher`].
+type Mailboxes = Arc<Mutex<HashMap<String, Offline>>>;
+
+/// Queues for every identity that is currently offline.
+pub struct OfflineStore {
+    limits: MailboxLimits,
+    mailboxes: Mailboxes,
+    flusher: Option<Arc<Flusher>>,
+}
+
+impl OfflineStore {
+    /// Build the store from config, loading persisted queues. Returns `None` when disabled.
+    pub fn open(config: &StoreForwardConfig) -> Result<Option<Self>> {
+        if !config.enabled {
+            return Ok(None);
+        }
+        let limits = MailboxLimits {
+            max_packets: config.max_packets,
+            max_bytes: config.max_bytes,
+            max_age: config.max_age,
+        };
+
+        let mailboxes = Mailboxes::default();
+        let flusher = match &config.dir {
+            Some(dir) => {
+                std::fs::create_dir_all(dir)
+                    .with_context(|| format!("Failed to create queue directory {:?}", dir))?;
+                mailboxes
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .extend(load_dir(dir, limits)?);
+                Some(Flusher::start(dir.clone(), Arc::clone(&mailboxes))?)
+            }
+            None => None,
+        };
+
+        Ok(Some(Self {
+            limits,
+            mailboxes,
+            flusher,
+        }))
+    }
+
+    /// Number of identities with a mailbox.
+    pub fn identities(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// Number of packets queued across all mailboxes.
+    pub fn queued(&self) -> usize {
+        self.lock()
+            .values()
+            .map(|offline| offline.mailbox.len())
+            .sum()
+    }
+
+    /// Start queueing for an identity whose last session ended.
+    ///
+    /// A mailbox it already has keeps its packets and takes the new filters.
+    pub fn hold(&self, identity: &str, filters: Vec<TopicFilter>, alerts: bool) {
+        let mut mailboxes = self.lock();
+        let offline = mailboxes
+            .entry(identity.to_string())
+            .or_insert_with(|| Offline {
+                mailbox: Mailbox::new(self.limits),
+                filters: Vec::new(),
+                alerts,
+            });
+        offline.filters = filters;
+        offline.alerts = alerts;
+        drop(mailboxes);
+        self.mark_dirty([identity.to_string()]);
+    }
+
+    /// Stop queueing for an identity that reconnected.
+    ///
+    /// Returns its subscriptions and the packets to replay, most urgent first.
+    pub fn release(&self, identity: &str) -> Option<(Vec<TopicFilter>, Vec<Packet>)> {
+        let mut offline = self.lock().remove(identity)?;
+        self.mark_dirty([identity.to_string()]);
+        let packets = offline.mailbox.drain(SystemTime::now());
+        Some((offline.filters, packets))
+    }
+
+    /// Queue a topic packet for every offline identity subscribed to it.
+    ///
+    /// Returns the number of mailboxes it was added to.
+    pub fn publish(&self, packet: &Packet) -> usize {
+        let Some(key) = packet.routing_key() else {
+            return 0;
+        };
+        self.queue(packet, |offline| {
+            offline.filters.iter().any(|filter| filter.matches(&key))
+        })
+    }
+
+    /// Queue a rebroadcast alert for every offline identity that receives alerts.
+    pub fn alert(&self, packet: &Packet) -> usize {
+        self.queue(packet, |offline| offline.alerts)
+    }
+
+    /// Write every queue changed since the last flush now, e.g. before exiting.
+    pub fn flush(&self) {
+        if let Some(flusher) = &self.flusher {
+            flusher.flush();
+        }
+    }
+
+    fn queue(&self, packet: &Packet, wants: impl Fn(&Offline) -> bool) -> usize {
+        let now = SystemTime::now();
+        let changed: Vec<String> = self
+            .lock()
+            .iter_mut()
+            .filter_map(|(identity, offline)| {
+                (wants(offline) && offline.mailbox.push(packet.clone(), now))
+                    .then(|| identity.clone())
+            })
+            .collect();
+        let queued = changed.len();
+        self.mark_dirty(changed);
+        queued
+    }
+
+    fn mark_dirty(&self, identities: impl IntoIterator<Item = String>) {
+        if let Some(flusher) = &self.flusher {
+            flusher.mark(identities);
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, Offline>> {
+        self.mailboxes.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Copy of a mailbox taken under the lock, to be encoded outside it.
+struct Snapshot {
+    filters: Vec<TopicFilter>,
+    alerts: bool,
+    packets: Vec<(SystemTime, Packet)>,
+}
+
+impl Snapshot {
+    fn of(offline: &Offline) -> Self {
+        Self {
+            filters: offline.filters.clone(),
+            alerts: offline.alerts,
+            packets: offline
+                .mailbox
+                .entries()
+                .map(|(at, packet)| (at, packet.clone()))
+                .collect(),
+        }
+    }
+
+    fn into_file(self, identity: &str) -> OfflineFile {
+        OfflineFile {
+            identity: identity.to_string(),
+            filters: self
+                .filters
+                .iter()
+                .map(|f| f.as_str().to_string())
+                .collect(),
+            alerts: self.alerts,
+            packets: self
+                .packets
+                .iter()
+                .map(|(at, packet)| StoredPacket {
+                    queued_at: at
+                        .duration_since(UNIX_EPOCH)
+                        .map_or(0, |d| d.as_millis() as u64),
+                    // Stored as v2 so packets from v1 sessions keep their ids and origins
+                    bytes: packet.clone().with_version(PROTOCOL_VERSION_2).to_bytes(),
+                })
+                .collect(),
+        }
+    }
+}
+
+fn from_file(file: OfflineFile, limits: MailboxLimits) -> Result<Offline> {
+    let filters = file
+        .filters
+        .iter()
+        .map(|filter| TopicFilter::parse(filter))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut mailbox = Mailbox::new(limits);
+    for stored in file.packets {
+        let packet = Packet::from_bytes(&stored.bytes)?;
+        mailbox.push(packet, UNIX_EPOCH + Duration::from_millis(stored.queued_at));
+    }
+    Ok(Offline {
+        mailbox,
+        filters,
+        alerts: file.alerts,
+    })
+}
+
+fn load_dir(dir: &Path, limits: MailboxLimits) -> Result<Vec<(String, Offline)>> {
+    let entries = std::fs::read_dir(dir)
+        .with_context(|| format!("Failed to list queue directory {:?}", dir))?;
+    let mut loaded = Vec::new();
+    for path in entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+    {
+        if path.extension().is_none_or(|ext| ext != "json") {
+            continue;
+        }
+        let parsed = std::fs::read_to_string(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|text| Ok(serde_json::from_str::<OfflineFile>(&text)?))
+            .and_then(|file| Ok((file.identity.clone(), from_file(file, limits)?)));
+        match parsed {
+            Ok(entry) => loaded.push(entry),
+            Err(e) => warn!("[QUEUE] Ignoring unreadable queue {:?}: {}", path, e),
+        }
+    }
+    Ok(loaded)
+}
+
+/// File name for an identity's queue: unsafe characters are percent-encoded.
+fn file_name(identity: &str) -> String {
+    let mut name = String::with_capacity(identity.len() + 5);
+    for byte in identity.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.') && !name.is_empty() {
+            name.push(byte as char);
+        } else {
+            name.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    name.push_str(".json");
+    name
+}
+
+/// Writes the queues of identities marked dirty, on a background thread.
+struct Flusher {
+    dir: PathBuf,
+    mailboxes: Mailboxes,
+    dirty: Mutex<HashSet<String>>,
+    changed: Condvar,
+    /// Held while flushing, so a final [`Flusher::flush`] returns only once
+    /// everything marked before it is on disk.
+    writing: Mutex<()>,
+}
+
+impl Flusher {
+    fn start(dir: PathBuf, mailboxes: Mailboxes) -> Result<Arc<Self>> {
+        let flusher = Arc::new(Self {
+            dir,
+            mailboxes,
+            dirty: Mutex::default(),
+            changed: Condvar::new(),
+            writing: Mutex::default(),
+        });
+        let background = Arc::clone(&flusher);
+        std::thread::Builder::new()
+            .name("queue-flusher".to_string())
+            .spawn(move || loop {
+                background.wait_dirty();
+                background.flush();
+                std::thread::sleep(FLUSH_INTERVAL);
+            })
+            .context("Failed to start queue flusher")?;
+        Ok(flusher)
+    }
+
+    fn mark(&self, identities: impl IntoIterator<Item = String>) {
+        let mut dirty = self.dirty.lock().unwrap_or_else(|e| e.into_inner());
+        let before = dirty.len();
+        dirty.extend(identities);
+        if dirty.len() > before {
+            self.changed.notify_one();
+        }
+    }
+
+    /// Block until some identity is dirty.
+    fn wait_dirty(&self) {
+        let mut dirty = self.dirty.lock().unwrap_or_else(|e| e.into_inner());
+        while dirty.is_empty() {
+            dirty = self.changed.wait(dirty).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    /// Write each dirty identity's current queue, or delete the file of one
+    /// that has none.
+    fn flush(&self) {
+        let _writing = self.writing.lock().unwrap_or_else(|e| e.into_inner());
+        let identities = std::mem::take(&mut *self.dirty.lock().unwrap_or_else(|e| e.into_inner()));
+        for identity in identities {
+            let snapshot = self
+                .mailboxes
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .get(&identity)
+                .map(Snapshot::of);
+            let path = self.dir.join(file_name(&identity));
+            let written = match snapshot {
+                Some(snapshot) => serde_json::to_vec(&snapshot.into_file(&identity))
+                    .map_err(std::io::Error::from)
+                    .and_then(|contents| write_atomically(&path, &contents)),
+                None => match std::fs::remove_file(&path) {
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+                    other => other,
+                },
+            };
+            if let Err(e) = written {
+                error!("[QUEUE] Failed to update {:?}: {}", path, e);
+            }
+        }
+    }
+}
+
+fn write_atomically(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, contents)?;
+    std::fs::rename(&tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protocol::{AlertOrigin, PROTOCOL_VERSION};
+
+    fn config(dir: Option<&Path>, max_packets: usize) -> StoreForwardConfig {
+        StoreForwardConfig {
+            enabled: true,
+            dir: dir.map(Path::to_path_buf),
+            max_packets,
+            ..StoreForwardConfig::default()
+        }
+    }
+
+    fn filters(list: &[&str]) -> Vec<TopicFilter> {
+        list.iter()
+            .map(|f| TopicFilter::parse(f).unwrap())
+            .collect()
+    }
+
+    fn published(packet: Packet, topic: &str) -> Packet {
+        packet
+            .with_version(PROTOCOL_VERSION_2)
+            .with_topic(topic)
+            .unwrap()
+    }
+
+    fn payloads(packets: &[Packet]) -> Vec<&str> {
+        packets.iter().map(|p| p.payload_str().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_replay_order_and_limits() {
+        let store = OfflineStore::open(&config(None, 3)).unwrap().unwrap();
+        assert!(OfflineStore::open(&StoreForwardConfig::default())
+            .unwrap()
+            .is_none());
+        store.hold("uav-7", filters(&["+/uav-7/#"]), false);
+        store.hold("op-1", filters(&["red/#"]), true);
+
+        assert_eq!(
+            store.publish(&published(Packet::green("g1"), "uav-7/t1")),
+            1
+        );
+        assert_eq!(store.publish(&published(Packet::red("r1"), "uav-7/t1")), 2);
+        assert_eq!(
+            store.publish(&published(Packet::yellow("y1"), "uav-7/t1")),
+            1
+        );
+        assert_eq!(store.publish(&published(Packet::green("x"), "uav-8/t1")), 0);
+        // Full: the oldest GREEN packet makes room for a more urgent one
+        assert_eq!(store.publish(&published(Packet::red("r2"), "uav-7/t1")), 2);
+        // ... or for a newer one of the same urgency, but never for a less urgent one
+        assert_eq!(
+            store.publish(&published(Packet::yellow("y2"), "uav-7/t1")),
+            1
+        );
+        assert_eq!(
+            store.publish(&published(Packet::green("g2"), "uav-7/t1")),
+            0
+        );
+        assert_eq!(store.alert(&Packet::red("alert")), 1);
+        assert_eq!(store.identities(), 2);
+
+        let (restored, packets) = store.release("uav-7").unwrap();
+        assert_eq!(restored, filters(&["+/uav-7/#"]));
+        assert_eq!(payloads(&packets), ["r1", "r2", "y2"]);
+        let (_, packets) = store.release("op-1").unwrap();
+        assert_eq!(payloads(&packets), ["r1", "r2", "alert"]);
+        assert!(store.release("uav-7").is_none());
+        assert_eq!(store.queued(), 0);
+    }
+
+    #[test]
+    fn test_queues_persist_across_restarts() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = OfflineStore::open(&config(Some(dir.path()), 10))
+            .unwrap()
+            .unwrap();
+        store.hold("uav/7", filters(&["+/uav-7/#"]), true);
+        store.publish(&published(Packet::green("g1"), "uav-7/t1"));
+        store.publish(&published(Packet::red("r1"), "uav-7/t1"));
+        store.hold("op-1", Vec::new(), true);
+        store.flush();
+        assert!(dir.path().join("uav%2F7.json").exists());
+
+        let reopened = OfflineStore::open(&config(Some(dir.path()), 10))
+            .unwrap()
+            .unwrap();
+        assert_eq!((reopened.identities(), reopened.queued()), (2, 2));
+        let (restored, packets) = reopened.release("uav/7").unwrap();
+        assert_eq!(restored, filters(&["+/uav-7/#"]));
+        assert_eq!(payloads(&packets), ["r1", "g1"]);
+
+        // A released queue's file is removed on the next flush
+        reopened.flush();
+        assert!(!dir.path().join("uav%2F7.json").exists());
+        assert!(dir.path().join("op-1.json").exists());
+    }
+
+    #[test]
+    fn test_v1_packets_keep_extensions_across_restarts() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = OfflineStore::open(&config(Some(dir.path()), 10))
+            .unwrap()
+            .unwrap();
+        let origin = AlertOrigin {
+            id: "a-1".into(),
+            session: 3,
+            who: "op-1".into(),
+        };
+        // An alert raised on a v1 session holds its stamps in memory only
+        let alert = Packet::red("TARGET LOST")
+            .with_id("pkt-1")
+            .unwrap()
+            .with_origin(&origin)
+            .unwrap()
+            .with_version(PROTOCOL_VERSION);
+        store.hold("op-1", Vec::new(), true);
+        assert_eq!(store.alert(&alert), 1);
+        store.flush();
+
+        let reopened = OfflineStore::open(&config(Some(dir.path()), 10))
+            .unwrap()
+            .unwrap();
+        let (_, packets) = reopened.release("op-1").unwrap();
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].id(), Some("pkt-1"));
+        assert_eq!(packets[0].origin(), Some(origin));
+    }
+}
@@ -0,0 +1,339 @@
+//! WebSocket upgrade decisions: which endpoint a request lands on and what it gets there.
+//!
+//! [`accept`] runs inside tungstenite's handshake callback. In order, it finds
+//! the endpoint for the request path, checks the bearer token, resolves policy
+//! roles and checks them against the endpoint, claims a session slot, and then
+//! negotiates permessage-deflate and the subprotocol. The first check that
+//! fails rejects the upgrade with an HTTP status and is recorded in the audit log.
+
+use protocol::{DeflateParams, Framing};
+use std::net::SocketAddr;
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::header::{
+    HeaderValue, SEC_WEBSOCKET_EXTENSIONS, SEC_WEBSOCKET_PROTOCOL, WWW_AUTHENTICATE,
+};
+use tokio_tungstenite::tungstenite::http::StatusCode;
+use tracing::warn;
+
+use crate::auth::TokenClaims;
+use crate::endpoint::EndpointSlot;
+use crate::identity::ClientIdentity;
+use crate::ServerContext;
+
+/// What an accepted upgrade negotiated.
+pub struct Upgrade {
+    /// The session's slot on its endpoint.
+    pub endpoint: EndpointSlot,
+    pub framing: Framing,
+    /// Claims of the verified bearer token, if one was presented.
+    pub claims: Option<TokenClaims>,
+    /// Policy roles of the session.
+    pub roles: Vec<String>,
+    /// permessage-deflate parameters, when compression was agreed.
+    pub deflate: Option<DeflateParams>,
+}
+
+/// Decide an upgrade request, adding the negotiated headers to `response`.
+// The error type is fixed by tungstenite's handshake callback
+#[allow(clippy::result_large_err)]
+pub fn accept(
+    ctx: &ServerContext,
+    peer_addr: Option<SocketAddr>,
+    identity: Option<&ClientIdentity>,
+    request: &Request,
+    mut response: Response,
+) -> Result<(Response, Upgrade), ErrorResponse> {
+    let path = request.uri().path();
+    let Some(endpoint) = ctx.endpoints.get(path) else {
+        warn!(
+            "[SERVER] Rejecting upgrade from {:?} on unknown path {}",
+            peer_addr, path
+        );
+        let reason = format!("No endpoint at {}", path);
+        if let Some(audit) = &ctx.audit {
+            audit.upgrade_rejected(peer_addr, path, &reason);
+        }
+        return Err(reject_upgrade(StatusCode::NOT_FOUND, reason));
+    };
+    let audit_rejected = |reason: &str| {
+        if let Some(audit) = &ctx.audit {
+            audit.upgrade_rejected(peer_addr, &endpoint.path, reason);
+        }
+    };
+
+    let mut claims = None;
+    if let Some(verifier) = &ctx.auth {
+        let required = endpoint.auth_required.unwrap_or(verifier.required());
+        match verifier.authenticate(request, required) {
+            Ok(verified) => claims = verified,
+            Err(reason) => {
+                warn!(
+                    "[SERVER] Rejecting upgrade from {:?}: {}",
+                    peer_addr, reason
+                );
+                audit_rejected(&reason);
+                return Err(reject_unauthorized(reason));
+            }
+        }
+    }
+
+    let roles = ctx
+        .policy
+        .as_ref()
+        .map(|policy| policy.roles_for(identity, claims.as_ref()))
+        .unwrap_or_default();
+    if !endpoint.admits(&roles) {
+        warn!(
+            "[SERVER] Rejecting upgrade from {:?} on {}: roles {:?} not admitted",
+            peer_addr, endpoint.path, roles
+        );
+        let reason = format!("Roles {:?} may not connect to {}", roles, endpoint.path);
+        audit_rejected(&reason);
+        return Err(reject_upgrade(StatusCode::FORBIDDEN, reason));
+    }
+    let slot = match endpoint.claim() {
+        Ok(slot) => slot,
+        Err(reason) => {
+            warn!(
+                "[SERVER] Rejecting upgrade from {:?}: {}",
+                peer_addr, reason
+            );
+            audit_rejected(&reason);
+            return Err(reject_upgrade(StatusCode::SERVICE_UNAVAILABLE, reason));
+        }
+    };
+
+    let mut deflate = None;
+    if endpoint.compression.unwrap_or(ctx.compression.enabled) {
+        let offered = request
+            .headers()
+            .get_all(SEC_WEBSOCKET_EXTENSIONS)
+            .iter()
+            .filter_map(|value| value.to_str().ok());
+        deflate = DeflateParams::negotiate(
+            offered,
+            ctx.compression.window_bits,
+            ctx.compression.context_takeover,
+        );
+        if let Some(params) = &deflate {
+            if let Ok(value) = HeaderValue::from_str(&params.to_header()) {
+                response
+                    .headers_mut()
+                    .insert(SEC_WEBSOCKET_EXTENSIONS, value);
+            }
+        }
+    }
+
+    let offered = request
+        .headers()
+        .get_all(SEC_WEBSOCKET_PROTOCOL)
+        .iter()
+        .filter_map(|value| value.to_str().ok());
+    let Some(framing) = Framing::negotiate(offered, &ctx.framings) else {
+        let supported: Vec<_> = ctx.framings.iter().map(Framing::subprotocol).collect();
+        let reason = format!(
+            "Unsupported subprotocol, expected one of: {}",
+            supported.join(", ")
+        );
+        audit_rejected(&reason);
+        return Err(reject_upgrade(StatusCode::BAD_REQUEST, reason));
+    };
+    response.headers_mut().insert(
+        SEC_WEBSOCKET_PROTOCOL,
+        HeaderValue::from_static(framing.subprotocol()),
+    );
+    if let Some(audit) = &ctx.audit {
+        audit.upgrade_allowed(peer_addr, &endpoint.path, claims.as_ref(), &roles);
+    }
+
+    Ok((
+        response,
+        Upgrade {
+            endpoint: slot,
+            framing,
+            claims,
+            roles,
+            deflate,
+        },
+    ))
+}
+
+/// Build an HTTP error response that rejects a WebSocket upgrade.
+fn reject_upgrade(status: StatusCode, reason: String) -> ErrorResponse {
+    let mut response = ErrorResponse::new(Some(reason));
+    *response.status_mut() = status;
+    response
+}
+
+/// Reject an upgrade whose bearer token is missing or invalid.
+fn reject_unauthorized(reason: String) -> ErrorResponse {
+    let mut response = reject_upgrade(StatusCode::UNAUTHORIZED, reason);
+    response
+        .headers_mut()
+        .insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::AuditLog;
+    use crate::auth::TokenVerifier;
+    use crate::endpoint::EndpointTable;
+    use crate::policy::Policy;
+    use crate::tests::test_context;
+    use std::io::Write as _;
+    use svckit::{AuditConfig, AuthConfig};
+
+    const ENDPOINTS: &str = r#"{"endpoints": {
+        "/telemetry": {"strategies": ["log"], "max_sessions": 1, "compression": true},
+        "/ops": {"strategies": ["log"], "roles": ["operator"]},
+        "/secure": {"strategies": ["log"], "auth_required": true}
+    }}"#;
+
+    fn context() -> ServerContext {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(ENDPOINTS.as_bytes()).unwrap();
+        let policy =
+            r#"{"roles": {"operator": {}, "observer": {}}, "default_roles": ["observer"]}"#;
+        let mut ctx = test_context(Some(Policy::parse(policy).unwrap()), None);
+        ctx.endpoints = EndpointTable::load(file.path()).unwrap();
+        ctx
+    }
+
+    fn request(path: &str, headers: &[(&str, &str)]) -> Request {
+        let mut request = Request::builder().uri(path);
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        request.body(()).unwrap()
+    }
+
+    fn decide(ctx: &ServerContext, request: &Request) -> Result<(Response, Upgrade), StatusCode> {
+        accept(ctx, None, None, request, Response::default()).map_err(|e| e.status())
+    }
+
+    #[test]
+    fn test_subprotocol_and_deflate() {
+        let ctx = context();
+        let offer = request(
+            "/telemetry",
+            &[
+                ("sec-websocket-protocol", "chat, drone-track.json.v1"),
+                ("sec-websocket-extensions", "permessage-deflate"),
+            ],
+        );
+        let (response, upgrade) = decide(&ctx, &offer).unwrap();
+        assert_eq!(upgrade.framing, Framing::Json);
+        assert_eq!(upgrade.endpoint.path, "/telemetry");
+        assert_eq!(upgrade.roles, ["observer"]);
+        assert!(upgrade.deflate.is_some());
+        assert_eq!(
+            response.headers()[SEC_WEBSOCKET_PROTOCOL],
+            Framing::Json.subprotocol()
+        );
+        assert!(response.headers().contains_key(SEC_WEBSOCKET_EXTENSIONS));
+        drop(upgrade);
+
+        // Compression is off by default, and a subprotocol is required
+        let offer = request(
+            "/ops",
+            &[("sec-websocket-extensions", "permessage-deflate")],
+        );
+        let mut ctx = context();
+        ctx.policy = None;
+        ctx.endpoints = EndpointTable::single("/ops");
+        assert_eq!(decide(&ctx, &offer).err(), Some(StatusCode::BAD_REQUEST));
+        let offer = request(
+            "/ops",
+            &[
+                ("sec-websocket-protocol", Framing::Binary.subprotocol()),
+                ("sec-websocket-extensions", "permessage-deflate"),
+            ],
+        );
+        let (response, upgrade) = decide(&ctx, &offer).unwrap();
+        assert!(upgrade.deflate.is_none());
+        assert!(!response.headers().contains_key(SEC_WEBSOCKET_EXTENSIONS));
+    }
+
+    #[test]
+    fn test_rejections() {
+        let ctx = context();
+        let json = [("sec-websocket-protocol", Framing::Json.subprotocol())];
+
+        assert_eq!(
+            decide(&ctx, &request("/nowhere", &json)).err(),
+            Some(StatusCode::NOT_FOUND)
+        );
+        assert_eq!(
+            decide(&ctx, &request("/ops", &json)).err(),
+            Some(StatusCode::FORBIDDEN)
+        );
+
+        // The endpoint holds one session; its slot frees when the upgrade is dropped
+        let held = decide(&ctx, &request("/telemetry", &json)).unwrap();
+        assert_eq!(
+            decide(&ctx, &request("/telemetry", &json)).err(),
+            Some(StatusCode::SERVICE_UNAVAILABLE)
+        );
+        drop(held);
+        assert!(decide(&ctx, &request("/telemetry", &json)).is_ok());
+    }
+
+    #[test]
+    fn test_token_required_per_endpoint() {
+        let mut keys = tempfile::NamedTempFile::new().unwrap();
+        keys.write_all(br#"{"keys": [{"kid": "ops", "alg": "HS256", "secret": "s"}]}"#)
+            .unwrap();
+        let mut ctx = context();
+        ctx.auth = TokenVerifier::from_config(&AuthConfig {
+            keys_file: Some(keys.path().to_path_buf()),
+            ..AuthConfig::default()
+        })
+        .unwrap();
+        let json = [("sec-websocket-protocol", Framing::Json.subprotocol())];
+
+        let rejected = accept(
+            &ctx,
+            None,
+            None,
+            &request("/secure", &json),
+            Response::default(),
+        )
+        .err()
+        .unwrap();
+        assert_eq!(rejected.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(rejected.headers()[WWW_AUTHENTICATE], "Bearer");
+
+        // Other endpoints follow AUTH_REQUIRED, which is off
+        let (_, upgrade) = decide(&ctx, &request("/telemetry", &json)).unwrap();
+        assert!(upgrade.claims.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_unknown_path_is_audited() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut ctx = context();
+        ctx.audit = AuditLog::open(&AuditConfig {
+            file: Some(dir.path().join("audit.log")),
+            ..AuditConfig::default()
+        })
+        .unwrap();
+        let json = [("sec-websocket-protocol", Framing::Json.subprotocol())];
+        assert_eq!(
+            decide(&ctx, &request("/nowhere", &json)).err(),
+            Some(StatusCode::NOT_FOUND)
+        );
+
+        let audit = ctx.audit.as_ref().unwrap();
+        audit.close().await.unwrap();
+        let log = std::fs::read_to_string(audit.path()).unwrap();
+        let record: serde_json::Value = log
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .find(|record: &serde_json::Value| record["event"] == "upgrade")
+            .expect("no upgrade record");
+        assert_eq!(record["decision"], "deny");
+        assert_eq!(record["endpoint"], "/nowhere");
+    }
+}